@@ -0,0 +1,65 @@
+//! Generate `link_app.S`, which links the user applications into the kernel.
+//!
+//! Every `user/src/bin/<name>.rs` is an application. Build them first, so
+//! that their ELFs are in `user/target/riscv64gc-unknown-none-elf/release`;
+//! without a `user` directory, the kernel gets no applications.
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+const USER_SRC_DIR: &str = "user/src/bin";
+const USER_TARGET_DIR: &str = "user/target/riscv64gc-unknown-none-elf/release";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", USER_SRC_DIR);
+    println!("cargo:rerun-if-changed={}", USER_TARGET_DIR);
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let mut apps: Vec<String> = fs::read_dir(Path::new(&manifest_dir).join(USER_SRC_DIR))
+        .map(|dir| {
+            dir.filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "rs" {
+                    return None;
+                }
+                Some(path.file_stem()?.to_str()?.to_string())
+            })
+            .collect()
+        })
+        .unwrap_or_default();
+    apps.sort();
+
+    let mut asm = String::new();
+    writeln!(asm, "    .align 3").unwrap();
+    writeln!(asm, "    .section .data").unwrap();
+    writeln!(asm, "    .global _num_app").unwrap();
+    writeln!(asm, "_num_app:").unwrap();
+    writeln!(asm, "    .quad {}", apps.len()).unwrap();
+    for i in 0..apps.len() {
+        writeln!(asm, "    .quad app_{}_start", i).unwrap();
+    }
+    match apps.len() {
+        0 => writeln!(asm, "    .quad 0").unwrap(),
+        n => writeln!(asm, "    .quad app_{}_end", n - 1).unwrap(),
+    }
+    writeln!(asm).unwrap();
+    writeln!(asm, "    .global _app_names").unwrap();
+    writeln!(asm, "_app_names:").unwrap();
+    for app in apps.iter() {
+        writeln!(asm, "    .string \"{}\"", app).unwrap();
+    }
+    for (i, app) in apps.iter().enumerate() {
+        let elf = Path::new(&manifest_dir).join(USER_TARGET_DIR).join(app);
+        writeln!(asm).unwrap();
+        writeln!(asm, "    .section .data").unwrap();
+        writeln!(asm, "    .global app_{}_start", i).unwrap();
+        writeln!(asm, "    .global app_{}_end", i).unwrap();
+        writeln!(asm, "    .align 3").unwrap();
+        writeln!(asm, "app_{}_start:", i).unwrap();
+        writeln!(asm, "    .incbin \"{}\"", elf.display()).unwrap();
+        writeln!(asm, "app_{}_end:", i).unwrap();
+    }
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("link_app.S"), asm).unwrap();
+}
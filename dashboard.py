@@ -84,28 +84,26 @@ class KernelMonitor:
         if "[KERNEL]" in line and "initialization..." in line:
             self.boot_time = datetime.now()
         
-        # Parse METRICS lines
-        if "[METRICS]" in line:
-            # Extract key=value pairs
-            match = re.search(r'memory_total_mb=(\d+)', line)
-            if match:
-                self.memory_total = int(match.group(1))
-            
-            match = re.search(r'memory_used_mb=(\d+)', line)
-            if match:
-                self.memory_used = int(match.group(1))
-                
-            match = re.search(r'memory_free_mb=(\d+)', line)
-            if match:
-                self.memory_free = int(match.group(1))
-            
-            match = re.search(r'process_count=(\d+)', line)
-            if match:
-                self.process_count = int(match.group(1))
+        # Parse the /proc/meminfo and /proc/stat lines the demos print
+        match = re.match(r'\s*MemTotal: (\d+) kB', line)
+        if match:
+            self.memory_total = int(match.group(1)) // 1024
+        
+        match = re.match(r'\s*MemUsed: (\d+) kB', line)
+        if match:
+            self.memory_used = int(match.group(1)) // 1024
             
-            match = re.search(r'syscall_count=(\d+)', line)
-            if match:
-                self.syscall_count = int(match.group(1))
+        match = re.match(r'\s*MemFree: (\d+) kB', line)
+        if match:
+            self.memory_free = int(match.group(1)) // 1024
+        
+        match = re.match(r'\s*tasks (\d+)', line)
+        if match:
+            self.process_count = int(match.group(1))
+        
+        match = re.match(r'\s*syscalls (\d+)', line)
+        if match:
+            self.syscall_count = int(match.group(1))
     
     def update_history(self):
         """Add current metrics to history"""
@@ -274,7 +272,7 @@ def run_kernel_and_monitor(monitor, text_mode=False):
                 monitor.update_history()
                 
                 # In text mode, periodically update display
-                if text_mode and "[METRICS]" in line:
+                if text_mode and re.match(r'\s*(MemFree:|syscalls )', line):
                     dashboard = TextDashboard(monitor)
                     dashboard.display()
         
@@ -1,9 +1,16 @@
 //! Kernel configuration constants
 
+use crate::logging::LogLevel;
+
+pub const KERNEL_NAME: &str = "RPOS";
+pub const KERNEL_VERSION: &str = "1.0.0";
+
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 
-pub const KERNEL_HEAP_SIZE: usize = 0x30_0000; // 3MB
+pub const KERNEL_HEAP_SIZE: usize = 0x30_0000; // 3MB in .bss to start with
+pub const KERNEL_HEAP_MAX_SIZE: usize = 0x100_0000; // 16MB, growing by frames on demand
+pub const MEMORY_START: usize = 0x8000_0000; // start of DRAM on QEMU virt
 pub const MEMORY_END: usize = 0x8800_0000; // 128MB
 
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
@@ -11,5 +18,73 @@ pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 
 pub const USER_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
+pub const USER_HEAP_SIZE: usize = 4096 * 32; // upper bound on brk growth
+pub const MAX_THREADS: usize = 16; // per process
+pub const MAX_FD: usize = 1024; // descriptors per process
+
+pub const RAM_DISK_SIZE: usize = 512 * 256; // 128KB, in 512-byte blocks
+
+pub const UART0: usize = 0x1000_0000; // NS16550A on QEMU virt
+pub const UART0_IRQ: usize = 10;
+pub const VIRTIO0: usize = 0x1000_1000; // first virtio-mmio slot on QEMU virt
+pub const PLIC_BASE: usize = 0x0c00_0000;
+pub const PLIC_MAX_IRQ: usize = 127; // highest interrupt source wired on QEMU virt
+
+/// Device MMIO regions as (base, size), identically mapped into the kernel
+pub const MMIO: &[(usize, usize)] = &[(UART0, 0x100), (VIRTIO0, 0x1000), (PLIC_BASE, 0x40_0000)];
+
+pub const MAX_HARTS: usize = 1; // harts with per-CPU data, see percpu.rs
 
 pub const CLOCK_FREQ: usize = 12500000;
+/// `CLOCK_REALTIME` at boot, 2026-01-01 UTC, as there is no RTC driver
+pub const BOOT_WALL_CLOCK_SEC: usize = 1_767_225_600;
+pub const TICKS_PER_SEC: usize = 100; // 10ms scheduling tick
+/// Ticks without scheduling after which the watchdog reboots, off by default;
+/// e.g. `Some(500)` reboots after 5s
+pub const WATCHDOG_TIMEOUT_TICKS: Option<usize> = None;
+
+pub const BIG_STRIDE: usize = 0x10_0000; // pass advance of a priority-1 task
+pub const DEFAULT_PRIORITY: usize = 16;
+pub const INIT_PID: usize = 1; // the first task, which may power off the machine
+pub const MAX_SYSCALL_NUM: usize = 500; // syscalls counted per task
+
+/// Return (bottom, top) of the kernel stack of `pid` in the kernel address space.
+///
+/// Stacks are laid out downwards from the trampoline, each one with an
+/// unmapped guard page of `PAGE_SIZE` bytes below it.
+pub fn kernel_stack_position(pid: usize) -> (usize, usize) {
+    let top = TRAMPOLINE - pid * (KERNEL_STACK_SIZE + PAGE_SIZE);
+    let bottom = top - KERNEL_STACK_SIZE;
+    (bottom, top)
+}
+
+/// Return the trap context page of thread slot `slot` in a user address space.
+///
+/// Slot 0 uses `TRAP_CONTEXT`, the pages of the others follow downwards.
+pub fn trap_cx_position(slot: usize) -> usize {
+    TRAP_CONTEXT - slot * PAGE_SIZE
+}
+
+/// Return (bottom, top) of the user stack of thread slot `slot`.
+///
+/// Stacks are laid out downwards below the trap context pages, each one with
+/// an unmapped guard page below it. The main thread of a program keeps the
+/// stack `MemorySet::from_elf` gives it instead of the one of its slot.
+pub fn thread_stack_position(slot: usize) -> (usize, usize) {
+    let top = trap_cx_position(MAX_THREADS - 1) - slot * (USER_STACK_SIZE + PAGE_SIZE);
+    let bottom = top - USER_STACK_SIZE;
+    (bottom, top)
+}
+
+/// End of the part of a user address space that is free for mappings such as
+/// `mmap`: the lower half of SV39. Addresses above it are not canonical up to
+/// the upper half, where the thread stacks and trap context pages lie, and
+/// the page table would alias them onto pages in use.
+pub const USER_SPACE_END: usize = 1 << 38;
+
+/// Most verbose log level that still gets printed
+pub const LOG_LEVEL: LogLevel = if cfg!(debug_assertions) {
+    LogLevel::Debug
+} else {
+    LogLevel::Warn
+};
@@ -1,15 +1,70 @@
 //! Console output using SBI
+//!
+//! Output is collected in a line buffer and handed to the SBI in one go when
+//! a line ends or the buffer fills up; [`console_flush`] forces it out. Line
+//! ends go out as `\r\n`, since serial terminals may not return the carriage
+//! on a bare line feed.
 
-use crate::sbi::console_putchar;
+use crate::sbi::console_write;
+use crate::sync::SpinLock;
 use core::fmt::{self, Write};
 
+const CONSOLE_BUFFER_SIZE: usize = 128;
+
+/// Bytes printed but not yet passed to the SBI
+struct ConsoleBuffer {
+    data: [u8; CONSOLE_BUFFER_SIZE],
+    len: usize,
+}
+
+impl ConsoleBuffer {
+    const fn new() -> Self {
+        Self {
+            data: [0; CONSOLE_BUFFER_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Append `s` with each `\n` turned into `\r\n`, flushing after every line
+    fn push_str(&mut self, s: &str) {
+        for line in s.as_bytes().split_inclusive(|&byte| byte == b'\n') {
+            match line.split_last() {
+                Some((b'\n', text)) => {
+                    self.push_bytes(text);
+                    self.push_bytes(b"\r\n");
+                    self.flush();
+                }
+                _ => self.push_bytes(line),
+            }
+        }
+    }
+
+    /// Append `bytes`, flushing whenever the buffer fills up
+    fn push_bytes(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let count = bytes.len().min(CONSOLE_BUFFER_SIZE - self.len);
+            self.data[self.len..self.len + count].copy_from_slice(&bytes[..count]);
+            self.len += count;
+            bytes = &bytes[count..];
+            if self.len == CONSOLE_BUFFER_SIZE {
+                self.flush();
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        console_write(&self.data[..self.len]);
+        self.len = 0;
+    }
+}
+
+static CONSOLE_BUFFER: SpinLock<ConsoleBuffer> = SpinLock::new(ConsoleBuffer::new());
+
 struct Stdout;
 
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            console_putchar(c as usize);
-        }
+        CONSOLE_BUFFER.lock().push_str(s);
         Ok(())
     }
 }
@@ -18,6 +73,29 @@ pub fn print(args: fmt::Arguments) {
     Stdout.write_fmt(args).unwrap();
 }
 
+/// Write `bytes` as they are, e.g. echoed input, and flush them right away
+pub fn console_write_bytes(bytes: &[u8]) {
+    let mut buffer = CONSOLE_BUFFER.lock();
+    buffer.push_bytes(bytes);
+    buffer.flush();
+}
+
+/// Release the console lock, so that the panic handler can print even when
+/// the panic struck while the lock was held
+///
+/// # Safety
+///
+/// The code holding the lock must never resume, as the panic handler does
+/// not return.
+pub unsafe fn console_force_unlock() {
+    CONSOLE_BUFFER.force_unlock();
+}
+
+/// Write out any buffered output that has not ended in a newline yet
+pub fn console_flush() {
+    CONSOLE_BUFFER.lock().flush();
+}
+
 #[macro_export]
 macro_rules! print {
     ($fmt: literal $(, $($arg: tt)+)?) => {
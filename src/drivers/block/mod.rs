@@ -0,0 +1,38 @@
+//! Block devices
+//!
+//! Filesystems access storage through the [`BlockDevice`] trait, one
+//! `BLOCK_SZ`-byte block at a time.
+
+mod ram_disk;
+mod virtio_blk;
+
+use crate::config::VIRTIO0;
+use alloc::boxed::Box;
+use core::any::Any;
+use lazy_static::*;
+
+pub use ram_disk::{block_device_test, RAM_DISK};
+pub use virtio_blk::VirtIOBlock;
+
+/// Size of a block in bytes
+pub const BLOCK_SZ: usize = 512;
+
+/// A device storing data in fixed-size blocks
+pub trait BlockDevice: Send + Sync + Any {
+    /// Read block `block_id` into `buf`, which must be `BLOCK_SZ` bytes long
+    fn read_block(&self, block_id: usize, buf: &mut [u8]);
+    /// Write `buf`, which must be `BLOCK_SZ` bytes long, to block `block_id`
+    fn write_block(&self, block_id: usize, buf: &[u8]);
+}
+
+lazy_static! {
+    /// The disk the kernel stores files on: the virtio-blk disk QEMU was
+    /// started with, or the RAM disk if there is none
+    pub static ref BLOCK_DEVICE: &'static dyn BlockDevice = match VirtIOBlock::new(VIRTIO0) {
+        Some(virtio_blk) => Box::leak(Box::new(virtio_blk)),
+        None => {
+            info!("no virtio-blk device found, falling back to the RAM disk");
+            &RAM_DISK
+        }
+    };
+}
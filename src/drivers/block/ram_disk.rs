@@ -0,0 +1,63 @@
+//! Block device backed by a byte array in kernel memory
+
+use super::{BlockDevice, BLOCK_SZ};
+use crate::config::RAM_DISK_SIZE;
+use crate::sync::SpinLock;
+
+/// A disk whose blocks live in RAM; its contents are lost on shutdown
+pub struct RamDisk {
+    data: SpinLock<[u8; RAM_DISK_SIZE]>,
+}
+
+/// The RAM disk, zero-filled at boot as it lives in .bss
+pub static RAM_DISK: RamDisk = RamDisk::new();
+
+impl RamDisk {
+    const fn new() -> Self {
+        Self {
+            data: SpinLock::new([0; RAM_DISK_SIZE]),
+        }
+    }
+
+    /// Byte range of block `block_id` within the disk
+    fn block_range(block_id: usize) -> core::ops::Range<usize> {
+        assert!(
+            block_id < RAM_DISK_SIZE / BLOCK_SZ,
+            "RAM disk block {} out of range",
+            block_id
+        );
+        block_id * BLOCK_SZ..(block_id + 1) * BLOCK_SZ
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        assert_eq!(buf.len(), BLOCK_SZ);
+        buf.copy_from_slice(&self.data.lock()[Self::block_range(block_id)]);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        assert_eq!(buf.len(), BLOCK_SZ);
+        self.data.lock()[Self::block_range(block_id)].copy_from_slice(buf);
+    }
+}
+
+/// Write a block of the RAM disk and read it back
+pub fn block_device_test() {
+    let device: &dyn BlockDevice = &RAM_DISK;
+    let last_block = RAM_DISK_SIZE / BLOCK_SZ - 1;
+    let mut block = [0u8; BLOCK_SZ];
+    for (i, byte) in block.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    device.write_block(last_block, &block);
+    let mut read_back = [0xffu8; BLOCK_SZ];
+    device.read_block(last_block, &mut read_back);
+    assert_eq!(block, read_back);
+    // Neighbouring blocks are untouched
+    device.read_block(last_block - 1, &mut read_back);
+    assert!(read_back.iter().all(|&byte| byte == 0));
+    // Leave the disk blank for whoever uses it next
+    device.write_block(last_block, &[0; BLOCK_SZ]);
+    println!("block_device_test passed!");
+}
@@ -0,0 +1,313 @@
+//! virtio-blk driver over the virtio MMIO transport
+//!
+//! Supports both the legacy (version 1) interface QEMU exposes by default and
+//! the modern (version 2) one. Requests are issued one at a time through a
+//! single virtqueue and completed by polling its used ring, so the driver
+//! works without external interrupts.
+
+use super::{BlockDevice, BLOCK_SZ};
+use crate::config::PAGE_SIZE;
+use crate::drivers::mmio;
+use crate::mm::DmaBuffer;
+use crate::sync::SpinLock;
+use core::mem::size_of;
+use core::ptr::{addr_of, addr_of_mut, read_volatile, write_volatile};
+
+const VIRTIO_MAGIC: u32 = 0x7472_6976; // "virt"
+const VIRTIO_DEVICE_BLOCK: u32 = 2;
+
+// Register offsets of the MMIO transport
+const MAGIC_VALUE: usize = 0x000;
+const VERSION: usize = 0x004;
+const DEVICE_ID: usize = 0x008;
+const DRIVER_FEATURES: usize = 0x020;
+const DRIVER_FEATURES_SEL: usize = 0x024;
+const GUEST_PAGE_SIZE: usize = 0x028; // legacy only
+const QUEUE_SEL: usize = 0x030;
+const QUEUE_NUM_MAX: usize = 0x034;
+const QUEUE_NUM: usize = 0x038;
+const QUEUE_ALIGN: usize = 0x03c; // legacy only
+const QUEUE_PFN: usize = 0x040; // legacy only
+const QUEUE_READY: usize = 0x044;
+const QUEUE_NOTIFY: usize = 0x050;
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+const STATUS: usize = 0x070;
+const QUEUE_DESC_LOW: usize = 0x080;
+const QUEUE_DESC_HIGH: usize = 0x084;
+const QUEUE_DRIVER_LOW: usize = 0x090;
+const QUEUE_DRIVER_HIGH: usize = 0x094;
+const QUEUE_DEVICE_LOW: usize = 0x0a0;
+const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+const CONFIG_CAPACITY: usize = 0x100;
+
+// Device status bits
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+
+/// Feature bit 32, which modern devices require the driver to accept
+const VIRTIO_F_VERSION_1_HIGH: u32 = 1;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_S_OK: u8 = 0;
+
+/// Number of descriptors; a request takes three of them
+const QUEUE_SIZE: usize = 8;
+/// Blocks are addressed in 512-byte sectors regardless of `BLOCK_SZ`
+const SECTOR_SIZE: usize = 512;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct VirtqAvail {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+    used_event: u16,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VirtqUsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// The used ring starts on its own page, as the legacy interface requires
+#[repr(C, align(4096))]
+struct VirtqUsed {
+    flags: u16,
+    idx: u16,
+    ring: [VirtqUsedElem; QUEUE_SIZE],
+    avail_event: u16,
+}
+
+#[repr(C)]
+struct BlkReqHeader {
+    req_type: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// Everything the device accesses by DMA.
+///
+/// It lives in a [`DmaBuffer`], which gives the device address of each
+/// field. Data goes through `buffer` because callers may pass buffers on
+/// kernel stacks, which are not identity mapped.
+#[repr(C, align(4096))]
+struct VirtioBlkDma {
+    desc: [VirtqDesc; QUEUE_SIZE],
+    avail: VirtqAvail,
+    used: VirtqUsed,
+    header: BlkReqHeader,
+    buffer: [u8; BLOCK_SZ],
+    status: u8,
+}
+
+/// A virtio block device behind an MMIO transport
+pub struct VirtIOBlock {
+    inner: SpinLock<VirtIOBlockInner>,
+}
+
+struct VirtIOBlockInner {
+    base: usize,
+    dma: &'static mut VirtioBlkDma,
+    /// Frames holding `dma`
+    dma_buffer: DmaBuffer,
+    /// Value of `used.idx` after the last completed request
+    last_used_idx: u16,
+}
+
+fn read_reg(base: usize, offset: usize) -> u32 {
+    // `base` is a virtio-mmio slot in MMIO, and the offsets above are its
+    // 32-bit registers
+    unsafe { mmio::read_volatile(base + offset) }
+}
+
+fn write_reg(base: usize, offset: usize, value: u32) {
+    // The device only gets addresses inside the driver's DmaBuffer
+    unsafe { mmio::write_volatile(base + offset, value) }
+}
+
+fn write_reg_addr(base: usize, low: usize, high: usize, addr: usize) {
+    write_reg(base, low, addr as u32);
+    write_reg(base, high, (addr >> 32) as u32);
+}
+
+impl VirtIOBlock {
+    /// Initialize the virtio-blk device at `base`, or return `None` if the
+    /// slot holds no block device, the device rejects the driver or there is
+    /// no memory for its DMA area.
+    pub fn new(base: usize) -> Option<Self> {
+        if read_reg(base, MAGIC_VALUE) != VIRTIO_MAGIC
+            || read_reg(base, DEVICE_ID) != VIRTIO_DEVICE_BLOCK
+        {
+            return None;
+        }
+        let version = read_reg(base, VERSION);
+        let dma_buffer = DmaBuffer::new(size_of::<VirtioBlkDma>().div_ceil(PAGE_SIZE))?;
+        // The frames are zeroed, which is a valid initial state for all rings
+        let dma: &'static mut VirtioBlkDma = dma_buffer.ppn().get_mut();
+
+        // Reset, then announce that we found the device and can drive it
+        write_reg(base, STATUS, 0);
+        let mut status = STATUS_ACKNOWLEDGE | STATUS_DRIVER;
+        write_reg(base, STATUS, status);
+
+        // No optional features are needed
+        write_reg(base, DRIVER_FEATURES_SEL, 0);
+        write_reg(base, DRIVER_FEATURES, 0);
+        if version >= 2 {
+            write_reg(base, DRIVER_FEATURES_SEL, 1);
+            write_reg(base, DRIVER_FEATURES, VIRTIO_F_VERSION_1_HIGH);
+            status |= STATUS_FEATURES_OK;
+            write_reg(base, STATUS, status);
+            if read_reg(base, STATUS) & STATUS_FEATURES_OK == 0 {
+                return None;
+            }
+        } else {
+            write_reg(base, GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        }
+
+        write_reg(base, QUEUE_SEL, 0);
+        if (read_reg(base, QUEUE_NUM_MAX) as usize) < QUEUE_SIZE {
+            return None;
+        }
+        write_reg(base, QUEUE_NUM, QUEUE_SIZE as u32);
+        if version >= 2 {
+            write_reg_addr(
+                base,
+                QUEUE_DESC_LOW,
+                QUEUE_DESC_HIGH,
+                dma_buffer.device_addr(addr_of!(dma.desc)),
+            );
+            write_reg_addr(
+                base,
+                QUEUE_DRIVER_LOW,
+                QUEUE_DRIVER_HIGH,
+                dma_buffer.device_addr(addr_of!(dma.avail)),
+            );
+            write_reg_addr(
+                base,
+                QUEUE_DEVICE_LOW,
+                QUEUE_DEVICE_HIGH,
+                dma_buffer.device_addr(addr_of!(dma.used)),
+            );
+            write_reg(base, QUEUE_READY, 1);
+        } else {
+            // The legacy layout derives the rings from the page of the table
+            write_reg(base, QUEUE_ALIGN, PAGE_SIZE as u32);
+            write_reg(base, QUEUE_PFN, dma_buffer.ppn().0 as u32);
+        }
+
+        status |= STATUS_DRIVER_OK;
+        write_reg(base, STATUS, status);
+        info!(
+            "virtio-blk at {:#x}: version {}, {} sectors",
+            base,
+            version,
+            read_reg(base, CONFIG_CAPACITY) as u64
+                | (read_reg(base, CONFIG_CAPACITY + 4) as u64) << 32
+        );
+        Some(Self {
+            inner: SpinLock::new(VirtIOBlockInner {
+                base,
+                dma,
+                dma_buffer,
+                last_used_idx: 0,
+            }),
+        })
+    }
+}
+
+impl VirtIOBlockInner {
+    /// Issue one request for block `block_id` through `dma.buffer` and
+    /// poll until the device has completed it
+    fn request(&mut self, req_type: u32, block_id: usize) {
+        let dma = &mut *self.dma;
+        let dma_buffer = &self.dma_buffer;
+        dma.header = BlkReqHeader {
+            req_type,
+            reserved: 0,
+            sector: (block_id * (BLOCK_SZ / SECTOR_SIZE)) as u64,
+        };
+        dma.status = u8::MAX;
+        let buffer_flags = if req_type == VIRTIO_BLK_T_IN {
+            VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE
+        } else {
+            VIRTQ_DESC_F_NEXT
+        };
+        dma.desc[0] = VirtqDesc {
+            addr: dma_buffer.device_addr(addr_of!(dma.header)) as u64,
+            len: size_of::<BlkReqHeader>() as u32,
+            flags: VIRTQ_DESC_F_NEXT,
+            next: 1,
+        };
+        dma.desc[1] = VirtqDesc {
+            addr: dma_buffer.device_addr(addr_of!(dma.buffer)) as u64,
+            len: BLOCK_SZ as u32,
+            flags: buffer_flags,
+            next: 2,
+        };
+        dma.desc[2] = VirtqDesc {
+            addr: dma_buffer.device_addr(addr_of!(dma.status)) as u64,
+            len: 1,
+            flags: VIRTQ_DESC_F_WRITE,
+            next: 0,
+        };
+        let avail_idx = dma.avail.idx;
+        dma.avail.ring[avail_idx as usize % QUEUE_SIZE] = 0;
+        // The descriptors must be visible before the device sees the new index
+        mmio::fence();
+        unsafe { write_volatile(addr_of_mut!(dma.avail.idx), avail_idx.wrapping_add(1)) };
+        mmio::fence();
+        write_reg(self.base, QUEUE_NOTIFY, 0);
+
+        while unsafe { read_volatile(addr_of!(dma.used.idx)) } == self.last_used_idx {
+            core::hint::spin_loop();
+        }
+        mmio::fence();
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        // Acknowledge the completion interrupt, which nobody listens to
+        write_reg(
+            self.base,
+            INTERRUPT_ACK,
+            read_reg(self.base, INTERRUPT_STATUS),
+        );
+        let status = unsafe { read_volatile(addr_of!(dma.status)) };
+        assert_eq!(
+            status, VIRTIO_BLK_S_OK,
+            "virtio-blk request for block {} failed",
+            block_id
+        );
+    }
+}
+
+impl BlockDevice for VirtIOBlock {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        assert_eq!(buf.len(), BLOCK_SZ);
+        let mut inner = self.inner.lock();
+        inner.request(VIRTIO_BLK_T_IN, block_id);
+        buf.copy_from_slice(&inner.dma.buffer);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        assert_eq!(buf.len(), BLOCK_SZ);
+        let mut inner = self.inner.lock();
+        inner.dma.buffer.copy_from_slice(buf);
+        inner.request(VIRTIO_BLK_T_OUT, block_id);
+    }
+}
@@ -0,0 +1,38 @@
+//! Access to memory-mapped device registers
+//!
+//! Registers must be accessed with volatile operations, or the compiler may
+//! merge, reorder or drop them like ordinary memory accesses. Plain fences
+//! do not order device accesses on RISC-V, so [`fence`] covers both.
+
+use core::arch::asm;
+
+/// Read the register of type `T` at `addr`
+///
+/// # Safety
+///
+/// `addr` must be a device register of type `T`, suitably aligned and inside
+/// one of the regions in [`MMIO`], which the kernel maps. Reading may have
+/// side effects on the device, e.g. taking a byte from a receive FIFO.
+///
+/// [`MMIO`]: crate::config::MMIO
+pub unsafe fn read_volatile<T>(addr: usize) -> T {
+    core::ptr::read_volatile(addr as *const T)
+}
+
+/// Write `value` to the register of type `T` at `addr`
+///
+/// # Safety
+///
+/// The same as for [`read_volatile`]. Besides, the write must not make the
+/// device access memory the kernel does not own, e.g. through DMA.
+pub unsafe fn write_volatile<T>(addr: usize, value: T) {
+    core::ptr::write_volatile(addr as *mut T, value)
+}
+
+/// Complete all memory and device accesses before the fence before any
+/// after it, e.g. filling a DMA buffer before telling the device about it
+pub fn fence() {
+    unsafe {
+        asm!("fence iorw, iorw");
+    }
+}
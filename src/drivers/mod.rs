@@ -0,0 +1,5 @@
+//! Device drivers
+
+pub mod block;
+pub mod mmio;
+pub mod uart;
@@ -0,0 +1,109 @@
+//! Interrupt-driven receiver of QEMU's NS16550A UART
+//!
+//! Output still goes through the SBI console. Input arrives through the RX
+//! interrupt, whose handler moves received bytes into a ring buffer that
+//! readers such as `Stdin` drain with [`getchar`], and wakes the readers
+//! blocked in [`wait_for_input`].
+
+use super::mmio::{read_volatile, write_volatile};
+use crate::config::{UART0, UART0_IRQ};
+use crate::plic::register_irq_handler;
+use crate::sync::{SpinLock, WaitQueue};
+use alloc::collections::VecDeque;
+
+// Register offsets, with DLAB cleared
+const RBR: usize = 0; // receive buffer
+const IER: usize = 1; // interrupt enable
+const FCR: usize = 2; // FIFO control
+const LCR: usize = 3; // line control
+const MCR: usize = 4; // modem control
+const LSR: usize = 5; // line status
+
+const IER_RX_AVAILABLE: u8 = 1 << 0;
+const FCR_FIFO_ENABLE: u8 = 1 << 0;
+const FCR_FIFO_CLEAR: u8 = 3 << 1;
+const LCR_EIGHT_BITS: u8 = 3;
+const MCR_OUT2: u8 = 1 << 3; // gates the interrupt line on a real 16550
+const LSR_DATA_READY: u8 = 1 << 0;
+
+/// Bytes beyond this are dropped until a reader catches up
+const RX_BUFFER_SIZE: usize = 256;
+
+static RX_BUFFER: SpinLock<VecDeque<u8>> = SpinLock::new(VecDeque::new());
+
+/// Readers waiting for the next received byte
+static RX_WAITERS: WaitQueue = WaitQueue::new();
+
+fn read_reg(offset: usize) -> u8 {
+    // The offsets above are byte registers in the UART's MMIO region
+    unsafe { read_volatile(UART0 + offset) }
+}
+
+fn write_reg(offset: usize, value: u8) {
+    // The UART does no DMA
+    unsafe { write_volatile(UART0 + offset, value) }
+}
+
+/// Enable the RX interrupt and route it through the PLIC
+pub fn init() {
+    write_reg(LCR, LCR_EIGHT_BITS);
+    write_reg(FCR, FCR_FIFO_ENABLE | FCR_FIFO_CLEAR);
+    write_reg(MCR, MCR_OUT2);
+    register_irq_handler(UART0_IRQ, handle_irq);
+    write_reg(IER, IER_RX_AVAILABLE);
+    info!("UART RX interrupt enabled at {:#x}", UART0);
+}
+
+/// Move everything in the receive FIFO into the ring buffer
+fn drain_rx_fifo() {
+    let mut rx_buffer = RX_BUFFER.lock();
+    while read_reg(LSR) & LSR_DATA_READY != 0 {
+        let byte = read_reg(RBR);
+        if rx_buffer.len() < RX_BUFFER_SIZE {
+            rx_buffer.push_back(byte);
+        }
+    }
+}
+
+/// RX interrupt handler
+fn handle_irq() {
+    drain_rx_fifo();
+    if !RX_BUFFER.lock().is_empty() {
+        RX_WAITERS.wake_all();
+    }
+}
+
+/// Whether a received byte is waiting to be taken by [`getchar`]
+pub fn has_input() -> bool {
+    drain_rx_fifo();
+    !RX_BUFFER.lock().is_empty()
+}
+
+/// Take the next received byte, if any.
+///
+/// Interrupts are off while the kernel runs, so a task waiting for input in
+/// the kernel would never see the RX interrupt; check the FIFO directly
+/// before giving up.
+pub fn getchar() -> Option<u8> {
+    if let Some(byte) = RX_BUFFER.lock().pop_front() {
+        return Some(byte);
+    }
+    drain_rx_fifo();
+    RX_BUFFER.lock().pop_front()
+}
+
+/// Block the current thread until the RX interrupt reports input, after
+/// [`getchar`] came up empty
+pub fn wait_for_input() {
+    RX_WAITERS.wait();
+}
+
+/// The readers woken up whenever input arrives
+pub fn rx_wait_queue() -> &'static WaitQueue {
+    &RX_WAITERS
+}
+
+/// Whether a reader is blocked until input arrives, which may take forever
+pub fn has_waiting_readers() -> bool {
+    !RX_WAITERS.is_empty()
+}
@@ -1,8 +1,12 @@
     .section .text.entry
     .globl _start
 _start:
+    # Keep the hart id the SBI passes in a0 in tp, see percpu.rs
+    mv tp, a0
     # Setup stack pointer
     la sp, boot_stack_top
+    # A null frame pointer ends backtraces, see lang_items.rs
+    li s0, 0
     call rust_main
 
     .section .bss.stack
@@ -0,0 +1,161 @@
+//! Virtual file system layer
+//!
+//! Every open file is an `Arc<dyn File>` stored in the fd table of a process
+//! as a [`FileDescriptor`], so `sys_read`/`sys_write` only need to look the
+//! descriptor up.
+//!
+//! # Directory entries
+//!
+//! `sys_getdents64` fills its buffer with records laid out like Linux's
+//! `struct linux_dirent64`, all fields little-endian:
+//!
+//! | offset | size | field      | meaning                                      |
+//! |--------|------|------------|----------------------------------------------|
+//! | 0      | 8    | `d_ino`    | inode number                                 |
+//! | 8      | 8    | `d_off`    | directory offset of the next record          |
+//! | 16     | 2    | `d_reclen` | length of this record, a multiple of 8       |
+//! | 18     | 1    | `d_type`   | [`DT_DIR`] or [`DT_REG`]                     |
+//! | 19     | -    | `d_name`   | NUL-terminated name, padded up to `d_reclen` |
+
+mod path;
+mod pipe;
+mod procfs;
+mod ramfs;
+mod stdio;
+
+use crate::mm::UserBuffer;
+use crate::sync::WaitQueue;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+
+/// `d_type` of a directory
+pub const DT_DIR: u8 = 4;
+/// `d_type` of a regular file
+pub const DT_REG: u8 = 8;
+
+/// A file as seen by the syscall layer
+pub trait File: Send + Sync {
+    /// Whether the file was opened for reading
+    fn readable(&self) -> bool;
+    /// Whether the file was opened for writing
+    fn writable(&self) -> bool;
+    /// Read into a user buffer, returning the number of bytes read
+    fn read(&self, buf: UserBuffer) -> usize;
+    /// Write from a user buffer, returning the number of bytes written
+    fn write(&self, buf: UserBuffer) -> usize;
+    /// Get the metadata of the file
+    fn stat(&self) -> Stat;
+    /// Fill a user buffer with the directory entries after the ones read so
+    /// far, returning the number of bytes filled, 0 at the end. Fails if the
+    /// file is no directory or the next entry does not fit.
+    fn getdents(&self, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// Whether the file was opened with `O_NONBLOCK`, so that reads and
+    /// writes fail with -EAGAIN rather than block
+    fn nonblocking(&self) -> bool {
+        false
+    }
+    /// Report, without consuming anything, whether a read or a write would
+    /// make progress right away and whether the other end hung up. By
+    /// default neither ever blocks.
+    fn poll(&self) -> PollEvents {
+        let mut events = PollEvents::empty();
+        if self.readable() {
+            events |= PollEvents::POLLIN;
+        }
+        if self.writable() {
+            events |= PollEvents::POLLOUT;
+        }
+        events
+    }
+    /// Queue woken up whenever [`File::poll`] may report new events, which
+    /// pollers wait on; `None` if the events never change
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        None
+    }
+    /// Move the offset reads start at to `offset`, returning the old one;
+    /// `None` if the file has no offset, e.g. a pipe
+    fn seek(&self, _offset: usize) -> Option<usize> {
+        None
+    }
+}
+
+/// An entry of a process's fd table
+#[derive(Clone)]
+pub struct FileDescriptor {
+    pub file: Arc<dyn File>,
+    /// Close the descriptor when the process execs another program
+    pub cloexec: bool,
+}
+
+impl FileDescriptor {
+    pub fn new(file: Arc<dyn File>, cloexec: bool) -> Self {
+        Self { file, cloexec }
+    }
+}
+
+/// File metadata as filled in by `sys_fstat`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Stat {
+    /// Device the file lives on; 0 for everything but the ramfs
+    pub dev: u64,
+    /// Inode number, 0 for files outside any filesystem
+    pub ino: u64,
+    /// File type
+    pub mode: StatMode,
+    /// Number of hard links
+    pub nlink: u32,
+    /// Size in bytes
+    pub size: u64,
+}
+
+bitflags! {
+    /// File type bits of [`Stat::mode`], as in Linux
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    pub struct StatMode: u32 {
+        const FIFO = 0o010000;
+        const CHR = 0o020000;
+        const DIR = 0o040000;
+        const FILE = 0o100000;
+    }
+}
+
+bitflags! {
+    /// Events of a file reported by `sys_ppoll`, as in Linux
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    pub struct PollEvents: u16 {
+        /// A read would not block
+        const POLLIN = 0x001;
+        /// A write would not block
+        const POLLOUT = 0x004;
+        /// An error occurred, reported even if not asked for
+        const POLLERR = 0x008;
+        /// The other end hung up, reported even if not asked for
+        const POLLHUP = 0x010;
+        /// The fd is not open, reported even if not asked for
+        const POLLNVAL = 0x020;
+    }
+}
+
+/// Append the directory entry record for `name` to `records`, see the
+/// module docs for its layout
+pub fn push_dirent(records: &mut Vec<u8>, ino: u64, next_off: i64, d_type: u8, name: &str) {
+    let reclen = (19 + name.len() + 1 + 7) & !7;
+    records.extend_from_slice(&ino.to_le_bytes());
+    records.extend_from_slice(&next_off.to_le_bytes());
+    records.extend_from_slice(&(reclen as u16).to_le_bytes());
+    records.push(d_type);
+    records.extend_from_slice(name.as_bytes());
+    records.resize(records.len() + reclen - 19 - name.len(), 0);
+}
+
+pub use path::{absolute_path, absolute_path_test};
+pub use pipe::{make_pipe, pipe_nonblocking_test};
+pub use procfs::{open_proc, procfs_test, read_all};
+pub use ramfs::{
+    getdents_test, link, link_test, lookup, open_file, open_file_test, unlink, OpenFlags,
+};
+pub use stdio::{Stdin, Stdout};
@@ -0,0 +1,40 @@
+//! Path resolution
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Resolve `path` against the absolute directory `cwd` into an absolute
+/// path without `.`, `..` or repeated slashes. `..` at the root stays there.
+pub fn absolute_path(cwd: &str, path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    let base = if path.starts_with('/') { "" } else { cwd };
+    for component in base.split('/').chain(path.split('/')) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            name => components.push(name),
+        }
+    }
+    let mut absolute = String::new();
+    for name in components {
+        absolute.push('/');
+        absolute.push_str(name);
+    }
+    if absolute.is_empty() {
+        absolute.push('/');
+    }
+    absolute
+}
+
+/// Resolve relative and absolute paths with `.` and `..`
+pub fn absolute_path_test() {
+    assert_eq!(absolute_path("/", "hello.txt"), "/hello.txt");
+    assert_eq!(absolute_path("/etc", "hostname"), "/etc/hostname");
+    assert_eq!(absolute_path("/etc", "/motd"), "/motd");
+    assert_eq!(absolute_path("/etc", "./../etc//hostname"), "/etc/hostname");
+    assert_eq!(absolute_path("/etc", ".."), "/");
+    assert_eq!(absolute_path("/", "../.."), "/");
+    println!("absolute_path_test passed!");
+}
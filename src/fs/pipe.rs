@@ -0,0 +1,325 @@
+//! Anonymous pipes backed by a ring buffer
+
+use super::{File, PollEvents, Stat, StatMode};
+use crate::mm::UserBuffer;
+use crate::sync::{UPSafeCell, WaitQueue};
+use alloc::sync::{Arc, Weak};
+
+/// One end of a pipe
+pub struct Pipe {
+    readable: bool,
+    writable: bool,
+    /// Return what could be transferred instead of waiting for the other end
+    nonblocking: bool,
+    buffer: Arc<UPSafeCell<PipeRingBuffer>>,
+    /// Shared by both ends, woken up whenever data goes in or out of the
+    /// buffer or an end is closed
+    wait_queue: Arc<WaitQueue>,
+}
+
+impl Pipe {
+    /// Create the read end of a pipe
+    pub fn read_end_with_buffer(
+        buffer: Arc<UPSafeCell<PipeRingBuffer>>,
+        wait_queue: Arc<WaitQueue>,
+        nonblocking: bool,
+    ) -> Self {
+        Self {
+            readable: true,
+            writable: false,
+            nonblocking,
+            buffer,
+            wait_queue,
+        }
+    }
+
+    /// Create the write end of a pipe
+    pub fn write_end_with_buffer(
+        buffer: Arc<UPSafeCell<PipeRingBuffer>>,
+        wait_queue: Arc<WaitQueue>,
+        nonblocking: bool,
+    ) -> Self {
+        Self {
+            readable: false,
+            writable: true,
+            nonblocking,
+            buffer,
+            wait_queue,
+        }
+    }
+}
+
+impl Drop for Pipe {
+    /// Let the other end see the hangup
+    fn drop(&mut self) {
+        self.wait_queue.wake_all();
+    }
+}
+
+const RING_BUFFER_SIZE: usize = 32;
+
+#[derive(Copy, Clone, PartialEq)]
+enum RingBufferStatus {
+    Full,
+    Empty,
+    Normal,
+}
+
+/// Ring buffer shared by both ends of a pipe
+pub struct PipeRingBuffer {
+    arr: [u8; RING_BUFFER_SIZE],
+    head: usize,
+    tail: usize,
+    status: RingBufferStatus,
+    /// Used to detect that every write end has been closed
+    write_end: Option<Weak<Pipe>>,
+    /// Used to detect that every read end has been closed
+    read_end: Option<Weak<Pipe>>,
+}
+
+impl PipeRingBuffer {
+    pub fn new() -> Self {
+        Self {
+            arr: [0; RING_BUFFER_SIZE],
+            head: 0,
+            tail: 0,
+            status: RingBufferStatus::Empty,
+            write_end: None,
+            read_end: None,
+        }
+    }
+
+    fn set_write_end(&mut self, write_end: &Arc<Pipe>) {
+        self.write_end = Some(Arc::downgrade(write_end));
+    }
+
+    fn set_read_end(&mut self, read_end: &Arc<Pipe>) {
+        self.read_end = Some(Arc::downgrade(read_end));
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        self.status = RingBufferStatus::Normal;
+        self.arr[self.tail] = byte;
+        self.tail = (self.tail + 1) % RING_BUFFER_SIZE;
+        if self.tail == self.head {
+            self.status = RingBufferStatus::Full;
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        self.status = RingBufferStatus::Normal;
+        let c = self.arr[self.head];
+        self.head = (self.head + 1) % RING_BUFFER_SIZE;
+        if self.head == self.tail {
+            self.status = RingBufferStatus::Empty;
+        }
+        c
+    }
+
+    fn available_read(&self) -> usize {
+        if self.status == RingBufferStatus::Empty {
+            0
+        } else if self.tail > self.head {
+            self.tail - self.head
+        } else {
+            self.tail + RING_BUFFER_SIZE - self.head
+        }
+    }
+
+    fn available_write(&self) -> usize {
+        if self.status == RingBufferStatus::Full {
+            0
+        } else {
+            RING_BUFFER_SIZE - self.available_read()
+        }
+    }
+
+    fn all_write_ends_closed(&self) -> bool {
+        self.write_end.as_ref().unwrap().upgrade().is_none()
+    }
+
+    fn all_read_ends_closed(&self) -> bool {
+        self.read_end.as_ref().unwrap().upgrade().is_none()
+    }
+}
+
+/// Create a pipe, returning its (read end, write end).
+///
+/// Each end is closed once the last descriptor referring to it is gone, in
+/// whichever process: forked children share the ends with their parent.
+/// With `nonblocking`, neither end ever waits for the other.
+pub fn make_pipe(nonblocking: bool) -> (Arc<Pipe>, Arc<Pipe>) {
+    let buffer = Arc::new(unsafe { UPSafeCell::new(PipeRingBuffer::new()) });
+    let wait_queue = Arc::new(WaitQueue::new());
+    let read_end = Arc::new(Pipe::read_end_with_buffer(
+        buffer.clone(),
+        wait_queue.clone(),
+        nonblocking,
+    ));
+    let write_end = Arc::new(Pipe::write_end_with_buffer(
+        buffer.clone(),
+        wait_queue,
+        nonblocking,
+    ));
+    buffer.exclusive_access().set_write_end(&write_end);
+    buffer.exclusive_access().set_read_end(&read_end);
+    (read_end, write_end)
+}
+
+impl File for Pipe {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+
+    fn writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Block until some data is available, then read as much as fits.
+    /// Returns 0 once the buffer is drained and every write end is closed.
+    /// A nonblocking end returns as soon as the buffer is drained.
+    fn read(&self, buf: UserBuffer) -> usize {
+        assert!(self.readable());
+        let want_to_read = buf.len();
+        let mut buf_iter = buf.into_iter();
+        let mut already_read = 0usize;
+        loop {
+            let mut ring_buffer = self.buffer.exclusive_access();
+            let loop_read = ring_buffer.available_read();
+            if loop_read == 0 {
+                if ring_buffer.all_write_ends_closed() || self.nonblocking {
+                    return already_read;
+                }
+                drop(ring_buffer);
+                self.wait_queue.wait();
+                continue;
+            }
+            let chunk = loop_read.min(want_to_read - already_read);
+            for byte_ref in buf_iter.by_ref().take(chunk) {
+                unsafe {
+                    *byte_ref = ring_buffer.read_byte();
+                }
+            }
+            already_read += chunk;
+            drop(ring_buffer);
+            if chunk > 0 {
+                // Room for writers
+                self.wait_queue.wake_all();
+            }
+            if already_read == want_to_read {
+                return want_to_read;
+            }
+        }
+    }
+
+    /// Block until the whole user buffer has been written. Stops early once
+    /// every read end is closed, as nobody would ever read the rest, and for
+    /// a nonblocking end once the buffer is full.
+    fn write(&self, buf: UserBuffer) -> usize {
+        assert!(self.writable());
+        let want_to_write = buf.len();
+        let mut buf_iter = buf.into_iter();
+        let mut already_write = 0usize;
+        loop {
+            let mut ring_buffer = self.buffer.exclusive_access();
+            if ring_buffer.all_read_ends_closed() {
+                return already_write;
+            }
+            let loop_write = ring_buffer.available_write();
+            if loop_write == 0 {
+                if self.nonblocking {
+                    return already_write;
+                }
+                drop(ring_buffer);
+                self.wait_queue.wait();
+                continue;
+            }
+            let chunk = loop_write.min(want_to_write - already_write);
+            for byte_ref in buf_iter.by_ref().take(chunk) {
+                ring_buffer.write_byte(unsafe { *byte_ref });
+            }
+            already_write += chunk;
+            drop(ring_buffer);
+            if chunk > 0 {
+                // Data for readers
+                self.wait_queue.wake_all();
+            }
+            if already_write == want_to_write {
+                return want_to_write;
+            }
+        }
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.nonblocking
+    }
+
+    /// As in Linux, the read end reports `POLLHUP` once every write end is
+    /// closed, and the write end `POLLERR` once every read end is
+    fn poll(&self) -> PollEvents {
+        let ring_buffer = self.buffer.exclusive_access();
+        let mut events = PollEvents::empty();
+        if self.readable {
+            if ring_buffer.available_read() > 0 {
+                events |= PollEvents::POLLIN;
+            }
+            if ring_buffer.all_write_ends_closed() {
+                events |= PollEvents::POLLHUP;
+            }
+        }
+        if self.writable {
+            if ring_buffer.available_write() > 0 {
+                events |= PollEvents::POLLOUT;
+            }
+            if ring_buffer.all_read_ends_closed() {
+                events |= PollEvents::POLLERR;
+            }
+        }
+        events
+    }
+
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        Some(&self.wait_queue)
+    }
+
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::FIFO,
+            nlink: 1,
+            size: 0,
+        }
+    }
+}
+
+/// Fill a nonblocking pipe until a write comes up short, then drain it in
+/// small reads until one comes up empty
+pub fn pipe_nonblocking_test() {
+    use alloc::boxed::Box;
+    use alloc::vec;
+    // User buffers live as long as the kernel, so the test leaks its own
+    let user_buffer =
+        |len: usize| UserBuffer::new(vec![Box::leak(vec![0xa5u8; len].into_boxed_slice())]);
+    let (read_end, write_end) = make_pipe(true);
+    assert_eq!(read_end.poll(), PollEvents::empty());
+    assert_eq!(write_end.poll(), PollEvents::POLLOUT);
+    assert_eq!(
+        write_end.write(user_buffer(RING_BUFFER_SIZE + 8)),
+        RING_BUFFER_SIZE
+    );
+    assert_eq!(write_end.poll(), PollEvents::empty());
+    let mut drained = 0;
+    while read_end.poll().contains(PollEvents::POLLIN) {
+        drained += read_end.read(user_buffer(8));
+    }
+    assert_eq!(drained, RING_BUFFER_SIZE);
+    assert_eq!(read_end.read(user_buffer(8)), 0);
+    drop(write_end);
+    // Without a writer, reading is ready to report EOF
+    assert_eq!(read_end.poll(), PollEvents::POLLHUP);
+    let (read_end, write_end) = make_pipe(true);
+    drop(read_end);
+    assert_eq!(write_end.poll(), PollEvents::POLLOUT | PollEvents::POLLERR);
+    println!("pipe_nonblocking_test passed!");
+}
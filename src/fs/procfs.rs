@@ -0,0 +1,160 @@
+//! Pseudo-files under `/proc` reporting kernel statistics
+//!
+//! A [`ProcFs`] file has no stored contents: its first read formats the
+//! current statistics, and further reads go on through that snapshot, so a
+//! file read in small chunks stays consistent.
+
+use super::{File, OpenFlags, Stat, StatMode};
+use crate::config::PAGE_SIZE;
+use crate::mm::{frames_total, frames_used, heap_peak_usage, heap_stats, UserBuffer};
+use crate::sync::UPSafeCell;
+use crate::syscall::syscall_count;
+use crate::task::task_counts;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// The files of `/proc`
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum ProcEntry {
+    /// `/proc/meminfo`: physical memory and kernel heap usage
+    MemInfo,
+    /// `/proc/stat`: tasks by state and the number of syscalls made
+    Stat,
+}
+
+impl ProcEntry {
+    fn from_path(path: &str) -> Option<Self> {
+        match path {
+            "/proc/meminfo" => Some(Self::MemInfo),
+            "/proc/stat" => Some(Self::Stat),
+            _ => None,
+        }
+    }
+
+    /// Format the current statistics as `name value` lines
+    fn generate(self) -> String {
+        let mut contents = String::new();
+        match self {
+            Self::MemInfo => {
+                let heap = heap_stats();
+                let total = frames_total() * PAGE_SIZE / 1024;
+                let used = frames_used() * PAGE_SIZE / 1024;
+                writeln!(contents, "MemTotal: {} kB", total).unwrap();
+                writeln!(contents, "MemUsed: {} kB", used).unwrap();
+                writeln!(contents, "MemFree: {} kB", total - used).unwrap();
+                writeln!(contents, "HeapUsed: {} kB", heap.allocated / 1024).unwrap();
+                writeln!(contents, "HeapFree: {} kB", heap.free / 1024).unwrap();
+                writeln!(contents, "HeapPeak: {} kB", heap_peak_usage() / 1024).unwrap();
+            }
+            Self::Stat => {
+                let tasks = task_counts();
+                let total = tasks.ready + tasks.running + tasks.blocked + tasks.zombie;
+                writeln!(contents, "tasks {}", total).unwrap();
+                writeln!(contents, "tasks_ready {}", tasks.ready).unwrap();
+                writeln!(contents, "tasks_running {}", tasks.running).unwrap();
+                writeln!(contents, "tasks_blocked {}", tasks.blocked).unwrap();
+                writeln!(contents, "tasks_zombie {}", tasks.zombie).unwrap();
+                writeln!(contents, "syscalls {}", syscall_count()).unwrap();
+            }
+        }
+        contents
+    }
+}
+
+/// An open file of `/proc`
+pub struct ProcFs {
+    entry: ProcEntry,
+    /// Snapshot taken by the first read, and the bytes read of it
+    contents: UPSafeCell<(Option<Vec<u8>>, usize)>,
+}
+
+impl File for ProcFs {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        let mut inner = self.contents.exclusive_access();
+        let (contents, offset) = &mut *inner;
+        let data = contents.get_or_insert_with(|| self.entry.generate().into_bytes());
+        let mut read = 0;
+        for slice in user_buf.buffers {
+            let remaining = &data[*offset..];
+            let len = slice.len().min(remaining.len());
+            slice[..len].copy_from_slice(&remaining[..len]);
+            *offset += len;
+            read += len;
+            if len < slice.len() {
+                break;
+            }
+        }
+        read
+    }
+
+    fn write(&self, _user_buf: UserBuffer) -> usize {
+        panic!("Cannot write to a file of /proc!");
+    }
+
+    /// The size is unknown until the contents are generated, so it is 0 as
+    /// on Linux
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::FILE,
+            nlink: 1,
+            size: 0,
+        }
+    }
+}
+
+/// Open the `/proc` file at the absolute `path` for reading
+pub fn open_proc(path: &str, flags: OpenFlags) -> Option<Arc<ProcFs>> {
+    if flags.intersects(OpenFlags::WRONLY | OpenFlags::RDWR | OpenFlags::CREATE | OpenFlags::TRUNC)
+    {
+        return None;
+    }
+    Some(Arc::new(ProcFs {
+        entry: ProcEntry::from_path(path)?,
+        contents: unsafe { UPSafeCell::new((None, 0)) },
+    }))
+}
+
+/// Read all of `file`
+pub fn read_all(file: &dyn File) -> Vec<u8> {
+    let mut contents = vec![];
+    let mut chunk = [0u8; 64];
+    loop {
+        // The chunk outlives every read, even though UserBuffer wants 'static
+        let slice = unsafe { core::slice::from_raw_parts_mut(chunk.as_mut_ptr(), chunk.len()) };
+        let read = file.read(UserBuffer::new(vec![slice]));
+        if read == 0 {
+            return contents;
+        }
+        contents.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// Read both files and check their fields
+pub fn procfs_test() {
+    assert!(open_proc("/proc/missing", OpenFlags::RDONLY).is_none());
+    assert!(open_proc("/proc/stat", OpenFlags::WRONLY).is_none());
+    let meminfo = read_all(&*open_proc("/proc/meminfo", OpenFlags::RDONLY).unwrap());
+    let meminfo = String::from_utf8(meminfo).unwrap();
+    let field = |name: &str| -> usize {
+        let line = meminfo.lines().find(|line| line.starts_with(name)).unwrap();
+        line.split_whitespace().nth(1).unwrap().parse().unwrap()
+    };
+    assert_eq!(field("MemTotal:"), field("MemUsed:") + field("MemFree:"));
+    let stat = read_all(&*open_proc("/proc/stat", OpenFlags::RDONLY).unwrap());
+    let stat = String::from_utf8(stat).unwrap();
+    assert!(stat.lines().any(|line| line.starts_with("syscalls ")));
+    println!("procfs_test passed!");
+}
@@ -0,0 +1,396 @@
+//! In-memory filesystem with read-only files
+//!
+//! Its files are compiled into the kernel image and arranged into a tree of
+//! [`Inode`]s at first use; opening a file yields a [`RamFile`] with its own
+//! read offset. Directory entries may be added and removed with [`link`] and
+//! [`unlink`]. Entries and open files hold their inode through an `Arc`, so
+//! an inode goes away once its last link is removed and its last open file
+//! closed, in whatever order.
+
+use super::{absolute_path, push_dirent, File, Stat, StatMode, DT_DIR, DT_REG};
+use crate::mm::UserBuffer;
+use crate::sync::UPSafeCell;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use bitflags::bitflags;
+use lazy_static::*;
+
+/// Files as (path relative to the root, contents) pairs; directories are
+/// created along the paths
+static FILES: &[(&str, &[u8])] = &[
+    ("hello.txt", b"Hello from the RPOS root filesystem!\n"),
+    (
+        "motd",
+        b"RPOS: a POSIX-compatible kernel for RISC-V, written in Rust.\n",
+    ),
+    ("etc/hostname", b"rpos\n"),
+];
+
+/// Device number reported for files of the filesystem
+const RAMFS_DEV: u64 = 1;
+
+bitflags! {
+    /// Flags accepted by `sys_open`, as in Linux
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    pub struct OpenFlags: u32 {
+        const RDONLY = 0;
+        const WRONLY = 1 << 0;
+        const RDWR = 1 << 1;
+        const CREATE = 1 << 6;
+        const TRUNC = 1 << 10;
+        const NONBLOCK = 1 << 11;
+        const CLOEXEC = 1 << 19;
+    }
+}
+
+/// A file or directory of the filesystem
+pub struct Inode {
+    /// Inode number, unique within the filesystem; the root has 1
+    pub ino: usize,
+    /// Number of directory entries referring to the inode
+    nlink: UPSafeCell<u32>,
+    kind: InodeKind,
+}
+
+enum InodeKind {
+    File(&'static [u8]),
+    /// Entries as (name, inode) pairs in creation order
+    Dir(UPSafeCell<Vec<(String, Arc<Inode>)>>),
+}
+
+impl Inode {
+    fn new_dir(ino: usize) -> Self {
+        Self {
+            ino,
+            nlink: unsafe { UPSafeCell::new(1) },
+            kind: InodeKind::Dir(unsafe { UPSafeCell::new(Vec::new()) }),
+        }
+    }
+
+    fn new_file(ino: usize, data: &'static [u8]) -> Self {
+        Self {
+            ino,
+            nlink: unsafe { UPSafeCell::new(1) },
+            kind: InodeKind::File(data),
+        }
+    }
+
+    /// Whether the inode is a directory
+    pub fn is_dir(&self) -> bool {
+        matches!(self.kind, InodeKind::Dir(_))
+    }
+
+    /// Look up the entry `name` of this directory
+    pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
+        match &self.kind {
+            InodeKind::Dir(entries) => entries
+                .exclusive_access()
+                .iter()
+                .find(|(entry_name, _)| entry_name == name)
+                .map(|(_, inode)| inode.clone()),
+            InodeKind::File(_) => None,
+        }
+    }
+
+    /// Add `inode` to this directory as `name`
+    fn insert(&self, name: &str, inode: Arc<Inode>) {
+        if let InodeKind::Dir(entries) = &self.kind {
+            entries.exclusive_access().push((String::from(name), inode));
+        }
+    }
+}
+
+lazy_static! {
+    /// Root directory, with [`FILES`] and their directories below it
+    static ref ROOT_INODE: Arc<Inode> = {
+        let root = Arc::new(Inode::new_dir(1));
+        let mut next_ino = 2;
+        for (path, data) in FILES {
+            let (dir_path, name) = path.rsplit_once('/').unwrap_or(("", path));
+            let mut dir = root.clone();
+            for dir_name in dir_path.split('/').filter(|name| !name.is_empty()) {
+                dir = match dir.find(dir_name) {
+                    Some(sub_dir) => sub_dir,
+                    None => {
+                        let sub_dir = Arc::new(Inode::new_dir(next_ino));
+                        next_ino += 1;
+                        dir.insert(dir_name, sub_dir.clone());
+                        sub_dir
+                    }
+                };
+            }
+            dir.insert(name, Arc::new(Inode::new_file(next_ino, data)));
+            next_ino += 1;
+        }
+        root
+    };
+}
+
+/// Look up the inode at `path`, which is taken relative to the root
+/// whether or not it starts with a slash
+pub fn lookup(path: &str) -> Option<Arc<Inode>> {
+    let mut inode = ROOT_INODE.clone();
+    for name in absolute_path("/", path)
+        .split('/')
+        .filter(|name| !name.is_empty())
+    {
+        inode = inode.find(name)?;
+    }
+    Some(inode)
+}
+
+/// Split `path` into the inode of its parent directory and its last
+/// component; fails for the root, which has neither
+fn lookup_parent(path: &str) -> Option<(Arc<Inode>, String)> {
+    let path = absolute_path("/", path);
+    let (parent, name) = path.rsplit_once('/')?;
+    if name.is_empty() {
+        return None;
+    }
+    let parent = lookup(parent).filter(|parent| parent.is_dir())?;
+    Some((parent, String::from(name)))
+}
+
+/// Add `new_path` as another link to the file at `old_path`. Fails if there
+/// is no such file, it is a directory, or `new_path` exists or has no parent
+/// directory.
+pub fn link(old_path: &str, new_path: &str) -> bool {
+    let inode = match lookup(old_path) {
+        Some(inode) if !inode.is_dir() => inode,
+        _ => return false,
+    };
+    match lookup_parent(new_path) {
+        Some((parent, name)) if parent.find(&name).is_none() => {
+            *inode.nlink.exclusive_access() += 1;
+            parent.insert(&name, inode);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Remove the directory entry at `path`. With `remove_dir` it must name an
+/// empty directory, otherwise anything but a directory. Files opened
+/// through the entry stay readable.
+pub fn unlink(path: &str, remove_dir: bool) -> bool {
+    let (parent, name) = match lookup_parent(path) {
+        Some(found) => found,
+        None => return false,
+    };
+    let inode = match parent.find(&name) {
+        Some(inode) => inode,
+        None => return false,
+    };
+    match &inode.kind {
+        InodeKind::Dir(entries) if remove_dir && entries.exclusive_access().is_empty() => {}
+        InodeKind::File(_) if !remove_dir => {}
+        _ => return false,
+    }
+    if let InodeKind::Dir(entries) = &parent.kind {
+        entries
+            .exclusive_access()
+            .retain(|(entry_name, _)| *entry_name != name);
+    }
+    *inode.nlink.exclusive_access() -= 1;
+    true
+}
+
+/// An open file or directory of the filesystem
+pub struct RamFile {
+    inode: Arc<Inode>,
+    /// Bytes read of a file, entries read of a directory
+    offset: UPSafeCell<usize>,
+}
+
+impl RamFile {
+    fn new(inode: Arc<Inode>) -> Self {
+        Self {
+            inode,
+            offset: unsafe { UPSafeCell::new(0) },
+        }
+    }
+}
+
+impl File for RamFile {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    /// Read from the current offset on, advancing it past the bytes read;
+    /// directories read as empty
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        let data = match self.inode.kind {
+            InodeKind::File(data) => data,
+            InodeKind::Dir(_) => return 0,
+        };
+        let mut offset = self.offset.exclusive_access();
+        let mut read = 0;
+        for slice in user_buf.buffers {
+            let remaining = &data[*offset..];
+            let len = slice.len().min(remaining.len());
+            slice[..len].copy_from_slice(&remaining[..len]);
+            *offset += len;
+            read += len;
+            if len < slice.len() {
+                break;
+            }
+        }
+        read
+    }
+
+    fn write(&self, _user_buf: UserBuffer) -> usize {
+        panic!("Cannot write to a file of the read-only root filesystem!");
+    }
+
+    /// Offsets past the end of a file are clamped to it
+    fn seek(&self, offset: usize) -> Option<usize> {
+        let len = match &self.inode.kind {
+            InodeKind::File(data) => data.len(),
+            InodeKind::Dir(_) => return None,
+        };
+        let mut current = self.offset.exclusive_access();
+        Some(core::mem::replace(&mut *current, offset.min(len)))
+    }
+
+    fn stat(&self) -> Stat {
+        let (mode, size) = match &self.inode.kind {
+            InodeKind::File(data) => (StatMode::FILE, data.len()),
+            InodeKind::Dir(_) => (StatMode::DIR, 0),
+        };
+        Stat {
+            dev: RAMFS_DEV,
+            ino: self.inode.ino as u64,
+            mode,
+            nlink: *self.inode.nlink.exclusive_access(),
+            size: size as u64,
+        }
+    }
+
+    /// Fill in as many whole entries as fit, advancing the offset past them
+    fn getdents(&self, user_buf: UserBuffer) -> Option<usize> {
+        let entries = match &self.inode.kind {
+            InodeKind::Dir(entries) => entries.exclusive_access(),
+            InodeKind::File(_) => return None,
+        };
+        let mut offset = self.offset.exclusive_access();
+        let capacity = user_buf.len();
+        let mut records = Vec::new();
+        for (name, inode) in entries.iter().skip(*offset) {
+            let filled = records.len();
+            let d_type = if inode.is_dir() { DT_DIR } else { DT_REG };
+            push_dirent(
+                &mut records,
+                inode.ino as u64,
+                *offset as i64 + 1,
+                d_type,
+                name,
+            );
+            if records.len() > capacity {
+                records.truncate(filled);
+                break;
+            }
+            *offset += 1;
+        }
+        if records.is_empty() && *offset < entries.len() {
+            return None;
+        }
+        let mut copied = 0;
+        for slice in user_buf.buffers {
+            let len = slice.len().min(records.len() - copied);
+            slice[..len].copy_from_slice(&records[copied..copied + len]);
+            copied += len;
+        }
+        Some(records.len())
+    }
+}
+
+/// Open the file or directory at `path`, see [`lookup`]. Fails if there is
+/// no such file or `flags` ask for write access, since the filesystem is
+/// read-only.
+pub fn open_file(path: &str, flags: OpenFlags) -> Option<Arc<RamFile>> {
+    if flags.intersects(OpenFlags::WRONLY | OpenFlags::RDWR | OpenFlags::CREATE | OpenFlags::TRUNC)
+    {
+        return None;
+    }
+    Some(Arc::new(RamFile::new(lookup(path)?)))
+}
+
+/// Read a bundled file back in small chunks
+pub fn open_file_test() {
+    assert!(open_file("missing", OpenFlags::RDONLY).is_none());
+    assert!(open_file("hello.txt", OpenFlags::WRONLY).is_none());
+    assert!(lookup("/etc").unwrap().is_dir());
+    assert!(lookup("etc/../etc/hostname").is_some());
+    let file = open_file("/hello.txt", OpenFlags::RDONLY).unwrap();
+    let mut contents = vec![];
+    let mut chunk = [0u8; 8];
+    loop {
+        // The chunk outlives every read, even though UserBuffer wants 'static
+        let slice = unsafe { core::slice::from_raw_parts_mut(chunk.as_mut_ptr(), chunk.len()) };
+        let read = file.read(UserBuffer::new(vec![slice]));
+        if read == 0 {
+            break;
+        }
+        contents.extend_from_slice(&chunk[..read]);
+    }
+    assert_eq!(contents.as_slice(), FILES[0].1);
+    println!("open_file_test passed!");
+}
+
+/// List the root directory, one entry per call
+pub fn getdents_test() {
+    let root = open_file("/", OpenFlags::RDONLY).unwrap();
+    let mut names = vec![];
+    let mut records = [0u8; 32];
+    loop {
+        // The records outlive every call, even though UserBuffer wants 'static
+        let slice = unsafe { core::slice::from_raw_parts_mut(records.as_mut_ptr(), records.len()) };
+        let filled = root.getdents(UserBuffer::new(vec![slice])).unwrap();
+        if filled == 0 {
+            break;
+        }
+        let reclen = u16::from_le_bytes([records[16], records[17]]) as usize;
+        assert_eq!(filled, reclen);
+        let name_len = records[19..reclen]
+            .iter()
+            .position(|&byte| byte == 0)
+            .unwrap();
+        names.push(String::from_utf8(records[19..19 + name_len].to_vec()).unwrap());
+    }
+    assert_eq!(names, ["hello.txt", "motd", "etc"]);
+    // Files have no entries, and an entry must fit as a whole
+    let file = open_file("/motd", OpenFlags::RDONLY).unwrap();
+    assert!(file.getdents(UserBuffer::new(vec![])).is_none());
+    let root = open_file("/", OpenFlags::RDONLY).unwrap();
+    assert!(root.getdents(UserBuffer::new(vec![])).is_none());
+    println!("getdents_test passed!");
+}
+
+/// Link a file elsewhere and unlink it again while it is open
+pub fn link_test() {
+    assert!(link("/hello.txt", "/etc/hello.txt"));
+    assert!(!link("/hello.txt", "/etc/hello.txt"));
+    assert!(!link("/etc", "/etc2"));
+    assert!(!link("/missing", "/etc/missing"));
+    let file = open_file("/etc/hello.txt", OpenFlags::RDONLY).unwrap();
+    assert_eq!(file.stat().nlink, 2);
+    assert_eq!(file.stat().ino, lookup("/hello.txt").unwrap().ino as u64);
+    assert!(!unlink("/etc", false));
+    assert!(!unlink("/etc", true));
+    assert!(!unlink("/etc/hello.txt", true));
+    assert!(unlink("/etc/hello.txt", false));
+    assert!(lookup("/etc/hello.txt").is_none());
+    assert_eq!(file.stat().nlink, 1);
+    let mut chunk = [0u8; 5];
+    // The chunk outlives the read, even though UserBuffer wants 'static
+    let slice = unsafe { core::slice::from_raw_parts_mut(chunk.as_mut_ptr(), chunk.len()) };
+    assert_eq!(file.read(UserBuffer::new(vec![slice])), 5);
+    assert_eq!(&chunk, b"Hello");
+    println!("link_test passed!");
+}
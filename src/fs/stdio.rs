@@ -0,0 +1,171 @@
+//! Standard input from the UART and output to the SBI console
+//!
+//! Input is line-buffered: [`read_line`] echoes what is typed and handles
+//! backspace, and reads hand out a line only once it is complete.
+
+use super::{File, PollEvents, Stat, StatMode};
+use crate::console::{console_flush, console_write_bytes};
+use crate::drivers::uart;
+use crate::mm::UserBuffer;
+use crate::sync::{SpinLock, WaitQueue};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// Characters beyond this are dropped until the line ends
+const MAX_LINE: usize = 256;
+
+/// Input of completed lines that no read has taken yet
+static PENDING_INPUT: SpinLock<VecDeque<u8>> = SpinLock::new(VecDeque::new());
+
+/// Wait for the next byte from the UART, blocked until the RX interrupt
+/// signals input
+fn getchar() -> u8 {
+    loop {
+        if let Some(c) = uart::getchar() {
+            return c;
+        }
+        uart::wait_for_input();
+    }
+}
+
+/// Read a line from the UART, echoing it as it is typed.
+///
+/// Backspace (0x7f or 0x08) erases the last character, `\r` or `\n` ends
+/// the line, which is returned ending in `\n`.
+pub fn read_line() -> Vec<u8> {
+    let mut line = Vec::new();
+    loop {
+        match getchar() {
+            b'\r' | b'\n' => {
+                console_write_bytes(b"\r\n");
+                line.push(b'\n');
+                return line;
+            }
+            0x7f | 0x08 => {
+                // Erase a whole UTF-8 sequence, continuation bytes first
+                while let Some(byte) = line.pop() {
+                    if byte & 0xc0 != 0x80 {
+                        console_write_bytes(b"\x08 \x08");
+                        break;
+                    }
+                }
+            }
+            c if line.len() < MAX_LINE => {
+                line.push(c);
+                console_write_bytes(&[c]);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Standard input, read from the UART
+pub struct Stdin;
+
+/// Standard output, written to the SBI console
+pub struct Stdout;
+
+impl File for Stdin {
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    /// Read until the buffer is full or the line ends, waiting for a whole
+    /// line to be typed if none is pending
+    fn read(&self, mut user_buf: UserBuffer) -> usize {
+        if PENDING_INPUT.lock().is_empty() {
+            // Make sure a prompt without a trailing newline is visible
+            console_flush();
+            // Typing may take long, so the lock is not held meanwhile
+            let line = read_line();
+            PENDING_INPUT.lock().extend(line);
+        }
+        let mut pending = PENDING_INPUT.lock();
+        let mut read = 0;
+        for byte in user_buf
+            .buffers
+            .iter_mut()
+            .flat_map(|slice| slice.iter_mut())
+        {
+            match pending.pop_front() {
+                Some(c) => {
+                    *byte = c;
+                    read += 1;
+                    if c == b'\n' {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        read
+    }
+
+    fn write(&self, _user_buf: UserBuffer) -> usize {
+        panic!("Cannot write to stdin!");
+    }
+
+    /// Input counts as available as soon as a character has been typed, even
+    /// though a read waits for the line to end
+    fn poll(&self) -> PollEvents {
+        if !PENDING_INPUT.lock().is_empty() || uart::has_input() {
+            PollEvents::POLLIN
+        } else {
+            PollEvents::empty()
+        }
+    }
+
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        Some(uart::rx_wait_queue())
+    }
+
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::CHR,
+            nlink: 1,
+            size: 0,
+        }
+    }
+}
+
+impl File for Stdout {
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn read(&self, _user_buf: UserBuffer) -> usize {
+        panic!("Cannot read from stdout!");
+    }
+
+    fn write(&self, user_buf: UserBuffer) -> usize {
+        for buffer in user_buf.buffers.iter() {
+            print!(
+                "{}",
+                core::str::from_utf8(buffer).unwrap_or("[Invalid UTF-8]")
+            );
+        }
+        // User programs expect a write to be visible once it returns
+        console_flush();
+        user_buf.len()
+    }
+
+    fn stat(&self) -> Stat {
+        Stat {
+            dev: 0,
+            ino: 0,
+            mode: StatMode::CHR,
+            nlink: 1,
+            size: 0,
+        }
+    }
+}
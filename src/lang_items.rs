@@ -1,10 +1,126 @@
 //! Language items for #![no_std] kernel
 
+use crate::config::{kernel_stack_position, KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE};
+use crate::console::{console_flush, console_force_unlock};
 use crate::sbi::shutdown;
+use core::arch::asm;
 use core::panic::PanicInfo;
+use riscv::register::{scause, sepc, stval};
+
+/// Kernel assertion that stays enabled in release builds. On failure it
+/// panics with the stringified condition, so the panic handler reports it
+/// together with a dump of the trap CSRs.
+#[macro_export]
+macro_rules! kassert {
+    ($cond:expr $(,)?) => {
+        if !$cond {
+            panic!(concat!("kassert failed: ", stringify!($cond)));
+        }
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        if !$cond {
+            panic!(
+                "kassert failed: {}: {}",
+                stringify!($cond),
+                format_args!($($arg)+)
+            );
+        }
+    };
+}
+
+/// Print the trap CSRs and the stack pointer of the panicking code. The
+/// CSRs describe the most recent trap, which is not necessarily what led
+/// to the panic.
+fn dump_registers() {
+    let sp: usize;
+    // riscv 0.10 does not expose the raw bits of sstatus, so read the CSR
+    let sstatus: usize;
+    unsafe {
+        asm!("mv {}, sp", out(reg) sp);
+        asm!("csrr {}, sstatus", out(reg) sstatus);
+    }
+    println!(
+        "[KERNEL PANIC] sepc={:#x} sstatus={:#x} stval={:#x} scause={:#x} sp={:#x}",
+        sepc::read(),
+        sstatus,
+        stval::read(),
+        scause::read().bits(),
+        sp
+    );
+}
+
+/// Deepest call chain a backtrace follows
+const MAX_BACKTRACE_DEPTH: usize = 32;
+
+/// Get the `[bottom, top)` bounds of the kernel stack `fp` lies on: the boot
+/// stack or the kernel stack of a task
+fn stack_bounds(fp: usize) -> Option<(usize, usize)> {
+    extern "C" {
+        fn boot_stack();
+        fn boot_stack_top();
+    }
+    if (boot_stack as usize..=boot_stack_top as usize).contains(&fp) {
+        return Some((boot_stack as usize, boot_stack_top as usize));
+    }
+    let pid = TRAMPOLINE.checked_sub(fp)? / (KERNEL_STACK_SIZE + PAGE_SIZE);
+    let (bottom, top) = kernel_stack_position(pid);
+    (bottom..=top).contains(&fp).then_some((bottom, top))
+}
+
+/// Call `f` with the return address of every frame of the calling code,
+/// innermost first.
+///
+/// The kernel is built with frame pointers, so each function saves `ra` at
+/// `fp - 8` and the `fp` of its caller at `fp - 16`. Kernel entry points
+/// start with a null `fp`, which ends the walk; so does any `fp` that leaves
+/// the stack or does not move toward its top, as code in `core` may not
+/// keep frame pointers.
+#[inline(never)]
+fn walk_stack(mut f: impl FnMut(usize)) {
+    let mut fp: usize;
+    unsafe {
+        asm!("mv {}, s0", out(reg) fp);
+    }
+    let (bottom, top) = match stack_bounds(fp) {
+        Some(bounds) => bounds,
+        None => return,
+    };
+    for _ in 0..MAX_BACKTRACE_DEPTH {
+        if fp % 8 != 0 || fp < bottom + 16 || fp > top {
+            return;
+        }
+        let (ra, prev_fp) = unsafe { (*((fp - 8) as *const usize), *((fp - 16) as *const usize)) };
+        if ra == 0 {
+            return;
+        }
+        f(ra);
+        if prev_fp <= fp {
+            return;
+        }
+        fp = prev_fp;
+    }
+}
+
+/// Print the return addresses of the calling code, to be looked up in the
+/// output of `make disasm`
+fn print_backtrace() {
+    println!("[KERNEL PANIC] backtrace:");
+    let mut depth = 0;
+    walk_stack(|ra| {
+        println!("  #{} {:#x}", depth, ra);
+        depth += 1;
+    });
+}
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // A panic inside print!() would otherwise spin on the lock it holds.
+    // The panicking code never resumes, so it cannot see the buffer change.
+    unsafe {
+        console_force_unlock();
+    }
+    // Emit whatever was printed before the panic ahead of the panic message
+    console_flush();
     if let Some(location) = info.location() {
         println!("[KERNEL PANIC] at {}:{}", location.file(), location.line());
     } else {
@@ -13,7 +129,10 @@ fn panic(info: &PanicInfo) -> ! {
     if let Some(message) = info.message() {
         println!("{}", message);
     }
+    dump_registers();
+    print_backtrace();
     println!("[KERNEL] Shutting down...");
+    console_flush();
     shutdown()
 }
 
@@ -22,3 +141,27 @@ fn panic(info: &PanicInfo) -> ! {
 pub extern "C" fn abort() -> ! {
     panic!("abort!");
 }
+
+/// Walk the stack from two nested calls deep
+pub fn backtrace_test() {
+    extern "C" {
+        fn stext();
+        fn etext();
+    }
+    #[inline(never)]
+    fn inner(return_addresses: &mut alloc::vec::Vec<usize>) {
+        walk_stack(|ra| return_addresses.push(ra));
+    }
+    #[inline(never)]
+    fn outer(return_addresses: &mut alloc::vec::Vec<usize>) {
+        inner(return_addresses);
+    }
+    let mut return_addresses = alloc::vec::Vec::new();
+    outer(&mut return_addresses);
+    // walk_stack into inner, inner into outer, outer into this test
+    assert!(return_addresses.len() >= 3);
+    assert!(return_addresses
+        .iter()
+        .all(|&ra| (stext as usize..etext as usize).contains(&ra)));
+    println!("backtrace_test passed!");
+}
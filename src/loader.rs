@@ -0,0 +1,73 @@
+//! User applications linked into the kernel image
+//!
+//! `build.rs` generates `link_app.S`, which embeds the ELF of every
+//! application and describes them in two tables: `_num_app` holds the
+//! number of applications `n`, followed by the `n` start addresses of their
+//! ELFs and the end address of the last one; `_app_names` holds their names
+//! as `n` NUL-terminated strings, in the same order.
+
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use lazy_static::*;
+
+global_asm!(include_str!(concat!(env!("OUT_DIR"), "/link_app.S")));
+
+extern "C" {
+    fn _num_app();
+    fn _app_names();
+}
+
+/// Get the number of linked applications
+pub fn get_num_app() -> usize {
+    unsafe { (_num_app as usize as *const usize).read_volatile() }
+}
+
+/// Get the ELF of application `app_id`
+pub fn get_app_data(app_id: usize) -> &'static [u8] {
+    let num_app = get_num_app();
+    assert!(app_id < num_app, "no application {}", app_id);
+    let app_start = unsafe {
+        core::slice::from_raw_parts((_num_app as usize as *const usize).add(1), num_app + 1)
+    };
+    unsafe {
+        core::slice::from_raw_parts(
+            app_start[app_id] as *const u8,
+            app_start[app_id + 1] - app_start[app_id],
+        )
+    }
+}
+
+lazy_static! {
+    /// Names of the linked applications, indexed by application id
+    static ref APP_NAMES: Vec<&'static str> = {
+        let mut start = _app_names as usize as *const u8;
+        (0..get_num_app())
+            .map(|_| unsafe {
+                let mut end = start;
+                while end.read_volatile() != b'\0' {
+                    end = end.add(1);
+                }
+                let slice = core::slice::from_raw_parts(start, end as usize - start as usize);
+                start = end.add(1);
+                core::str::from_utf8(slice).unwrap()
+            })
+            .collect()
+    };
+}
+
+/// Get the ELF of the application called `name`
+pub fn get_app_data_by_name(name: &str) -> Option<&'static [u8]> {
+    APP_NAMES
+        .iter()
+        .position(|&app_name| app_name == name)
+        .map(get_app_data)
+}
+
+/// Print the names of the linked applications
+pub fn list_apps() {
+    println!("/**** APPS ****");
+    for app in APP_NAMES.iter() {
+        println!("{}", app);
+    }
+    println!("**************/");
+}
@@ -0,0 +1,89 @@
+//! Leveled kernel logging over the SBI console
+//!
+//! Messages are prefixed with their level and colored with ANSI escape
+//! codes; anything less severe than [`LOG_LEVEL`] is dropped.
+
+use crate::config::LOG_LEVEL;
+use crate::console::print;
+use core::fmt;
+
+/// Severity of a log message, from most to least severe
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LogLevel {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    /// ANSI foreground color code
+    fn color(self) -> u8 {
+        match self {
+            LogLevel::Error => 31, // red
+            LogLevel::Warn => 93,  // bright yellow
+            LogLevel::Info => 34,  // blue
+            LogLevel::Debug => 32, // green
+            LogLevel::Trace => 90, // bright black
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Trace => "TRACE",
+        }
+    }
+}
+
+/// Print `args` at `level` if it passes the [`LOG_LEVEL`] filter
+pub fn log(level: LogLevel, args: fmt::Arguments) {
+    if level > LOG_LEVEL {
+        return;
+    }
+    print(format_args!(
+        "\x1b[{}m[{:>5}] {}\x1b[0m\n",
+        level.color(),
+        level.name(),
+        args
+    ));
+}
+
+#[macro_export]
+macro_rules! error {
+    ($fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::logging::log($crate::logging::LogLevel::Error, format_args!($fmt $(, $($arg)+)?));
+    }
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::logging::log($crate::logging::LogLevel::Warn, format_args!($fmt $(, $($arg)+)?));
+    }
+}
+
+#[macro_export]
+macro_rules! info {
+    ($fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::logging::log($crate::logging::LogLevel::Info, format_args!($fmt $(, $($arg)+)?));
+    }
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::logging::log($crate::logging::LogLevel::Debug, format_args!($fmt $(, $($arg)+)?));
+    }
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::logging::log($crate::logging::LogLevel::Trace, format_args!($fmt $(, $($arg)+)?));
+    }
+}
@@ -7,12 +7,22 @@ extern crate alloc;
 
 #[macro_use]
 mod console;
-mod config;
+#[macro_use]
 mod lang_items;
+#[macro_use]
+mod logging;
+mod config;
+mod drivers;
+mod fs;
+mod loader;
 mod mm;
+mod percpu;
+mod plic;
 mod sbi;
 mod sync;
 mod syscall;
+mod task;
+mod timer;
 mod trap;
 
 use core::arch::global_asm;
@@ -36,11 +46,36 @@ fn clear_bss() {
 pub fn rust_main() -> ! {
     clear_bss();
     println!("[KERNEL] Rust-based POSIX-compatible Kernel (RPOS)");
-    println!("[KERNEL] Version 1.0.0");
+    println!("[KERNEL] Version {}", config::KERNEL_VERSION);
     println!("[KERNEL] Starting initialization...");
 
     mm::init();
+    mm::remap_test();
+    mm::demand_zero_test();
+    mm::range_ops_test();
+    mm::vpn_range_test();
+    mm::heap_stats_test();
+    mm::heap_growth_test();
+    mm::heap_peak_test();
+    mm::frame_alloc_contiguous_test();
+    mm::shm_test();
     trap::init();
+    plic::init();
+    drivers::uart::init();
+    trap::app_init_context_test();
+    sbi::ipi_test();
+    task::switch_test();
+    fs::absolute_path_test();
+    fs::open_file_test();
+    fs::getdents_test();
+    fs::link_test();
+    fs::pipe_nonblocking_test();
+    fs::procfs_test();
+    drivers::block::block_device_test();
+    sync::deadlock_detector_test();
+    sync::push_off_test();
+    task::kernel_stack_overflow_test();
+    lang_items::backtrace_test();
 
     println!("[KERNEL] All initialization complete!");
     println!(
@@ -48,12 +83,21 @@ pub fn rust_main() -> ! {
         mm::memory_size() / (1024 * 1024)
     );
 
-    // Run demo programs to showcase kernel capabilities
-    run_demos();
+    loader::list_apps();
+    if !task::add_initproc() {
+        // Without user programs, showcase the kernel from kernel space
+        println!("[KERNEL] No initproc linked, running the kernel demos instead");
+        run_demos();
+        println!("[KERNEL] All demos completed successfully!");
+        task::add_task(demo_task_a);
+        task::add_task(demo_task_b);
+        task::add_task(demo_task_c);
+    }
 
-    println!("[KERNEL] All demos completed successfully!");
-    println!("[KERNEL] Shutting down...");
-    sbi::shutdown()
+    // The kernel shuts down once all tasks exit
+    timer::enable_timer_interrupt();
+    timer::set_next_trigger();
+    task::run_first_task()
 }
 
 /// Run demonstration programs showcasing kernel capabilities:
@@ -87,7 +131,11 @@ fn demo_hello_world() {
 /// Demo 2: System Information
 fn demo_system_info() {
     println!("[DEMO 2] System Information");
-    println!("Kernel: RPOS v1.0.0");
+    println!(
+        "Kernel: {} v{}",
+        config::KERNEL_NAME,
+        config::KERNEL_VERSION
+    );
     println!("Architecture: RISC-V 64-bit");
     println!("Page Size: 4096 bytes");
     println!("Status: SUCCESS\n");
@@ -99,22 +147,32 @@ fn demo_memory_stats() {
     let total_mem = mm::memory_size();
     let total_mb = total_mem / (1024 * 1024);
     println!("Total Memory: {} MB ({} bytes)", total_mb, total_mem);
-    println!("Kernel Heap: Initialized with Buddy Allocator");
+    let heap = mm::heap_stats();
+    println!(
+        "Kernel Heap: Buddy Allocator, {} KB used, {} KB free, \
+         largest free block {} KB, peak {} KB",
+        heap.allocated / 1024,
+        heap.free / 1024,
+        heap.largest_free_block / 1024,
+        mm::heap_peak_usage() / 1024
+    );
     println!("Physical Frames: Managed by Stack Allocator");
     println!("Virtual Memory: SV39 Paging Enabled");
-    
-    // Output structured metrics for dashboard
-    // Note: Actual kernel memory usage is complex to calculate at this stage.
-    // This approximation represents minimal kernel overhead (code, stack, heap metadata).
-    // Update this value if significant kernel features are added (e.g., large page caches,
-    // extensive process tables, or additional heap allocations).
-    const APPROX_KERNEL_USAGE_MB: usize = 1;
-    println!("[METRICS] memory_total_mb={}", total_mb);
-    println!("[METRICS] memory_used_mb={}", APPROX_KERNEL_USAGE_MB);
-    println!("[METRICS] memory_free_mb={}", total_mb - APPROX_KERNEL_USAGE_MB);
+
+    print_proc_file("/proc/meminfo");
     println!("Status: SUCCESS\n");
 }
 
+/// Print the statistics in the `/proc` file at `path`, as `cat` would
+fn print_proc_file(path: &str) {
+    let file = fs::open_proc(path, fs::OpenFlags::RDONLY).unwrap();
+    let contents = fs::read_all(&*file);
+    println!("{}:", path);
+    for line in core::str::from_utf8(&contents).unwrap().lines() {
+        println!("  {}", line);
+    }
+}
+
 /// Demo 4: Process Management
 fn demo_process_management() {
     println!("[DEMO 4] Process Management Capabilities");
@@ -123,13 +181,43 @@ fn demo_process_management() {
     println!("  - sys_read (63): Read from file descriptor");
     println!("  - sys_exit (93): Exit process");
     println!("  - sys_yield (124): Yield CPU");
+    println!("  - sys_get_time (169): Get time since boot");
     println!("  - sys_getpid (172): Get process ID");
-    println!("  - sys_fork (220): Fork process [STUB]");
-    println!("  - sys_exec (221): Execute program [STUB]");
-    println!("  - sys_waitpid (260): Wait for process [STUB]");
-    
-    // Output process metrics for dashboard
-    println!("[METRICS] process_count=1");
-    println!("[METRICS] syscall_count=8");
+    println!("  - sys_fork (220): Fork process (copy-on-write)");
+    println!("  - sys_exec (221): Execute program");
+    println!("  - sys_waitpid (260): Wait for process");
+
+    print_proc_file("/proc/stat");
     println!("Status: SUCCESS\n");
 }
+
+/// Number of times each demo task yields before exiting
+const DEMO_TASK_ROUNDS: usize = 3;
+
+/// Priority of demo task A, three times the default, so that the stride
+/// scheduler lets it finish its rounds ahead of B and C
+const DEMO_TASK_A_PRIORITY: usize = 48;
+
+/// Shared body of the scheduler demo tasks
+fn demo_task_body(name: &str) -> ! {
+    for round in 0..DEMO_TASK_ROUNDS {
+        println!("[TASK {}] round {}/{}", name, round + 1, DEMO_TASK_ROUNDS);
+        task::suspend_current_and_run_next();
+    }
+    println!("[TASK {}] exiting", name);
+    task::exit_current_and_run_next(0)
+}
+
+fn demo_task_a() -> ! {
+    task::with_current_task(|task| task.set_priority(DEMO_TASK_A_PRIORITY));
+    println!("[TASK A] priority {}", DEMO_TASK_A_PRIORITY);
+    demo_task_body("A")
+}
+
+fn demo_task_b() -> ! {
+    demo_task_body("B")
+}
+
+fn demo_task_c() -> ! {
+    demo_task_body("C")
+}
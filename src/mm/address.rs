@@ -1,6 +1,6 @@
 //! Physical and Virtual Address types for RISC-V SV39
 
-use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS};
+use crate::config::{MEMORY_END, MEMORY_START, PAGE_SIZE, PAGE_SIZE_BITS};
 use core::fmt::{self, Debug, Formatter};
 
 /// Physical Address
@@ -119,12 +119,30 @@ impl From<VirtPageNum> for VirtAddr {
 
 // PhysPageNum implementations
 impl PhysPageNum {
+    /// In debug builds, check that this frame lies in physical memory and
+    /// that a slice of `len` elements of `T` starting there stays inside it
+    fn check_frame_slice<T>(&self, len: usize) {
+        debug_assert!(
+            (MEMORY_START / PAGE_SIZE..MEMORY_END / PAGE_SIZE).contains(&self.0),
+            "ppn {:#x} lies outside physical memory",
+            self.0
+        );
+        debug_assert!(
+            len * core::mem::size_of::<T>() <= PAGE_SIZE,
+            "slice of {} bytes overflows frame {:#x}",
+            len * core::mem::size_of::<T>(),
+            self.0
+        );
+    }
+
     pub fn get_bytes_array(&self) -> &'static mut [u8] {
+        self.check_frame_slice::<u8>(PAGE_SIZE);
         let pa: PhysAddr = (*self).into();
         unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut u8, PAGE_SIZE) }
     }
 
     pub fn get_pte_array(&self) -> &'static mut [PageTableEntry] {
+        self.check_frame_slice::<PageTableEntry>(512);
         let pa: PhysAddr = (*self).into();
         unsafe { core::slice::from_raw_parts_mut(pa.0 as *mut PageTableEntry, 512) }
     }
@@ -199,6 +217,7 @@ impl StepByOne for VirtPageNum {
     }
 }
 
+/// Half-open range `[start, end)` of page numbers
 #[derive(Copy, Clone)]
 pub struct SimpleRange<T>
 where
@@ -238,6 +257,7 @@ where
     }
 }
 
+/// Yields every page number of a [`SimpleRange`] in ascending order
 pub struct SimpleRangeIterator<T>
 where
     T: StepByOne + Copy + PartialEq + PartialOrd + Debug,
@@ -272,4 +292,24 @@ where
     }
 }
 
+/// Range of virtual page numbers, e.g. the pages of a `MapArea`
 pub type VPNRange = SimpleRange<VirtPageNum>;
+
+/// Iterate ranges that cross page table index boundaries
+pub fn vpn_range_test() {
+    // Crosses from the last level-0 index of one page table to the next
+    let range = VPNRange::new(VirtPageNum(0x1fe), VirtPageNum(0x202));
+    assert_eq!(range.into_iter().count(), 4);
+    let indexes: [[usize; 3]; 4] = [[0, 0, 0x1fe], [0, 0, 0x1ff], [0, 1, 0], [0, 1, 1]];
+    for (vpn, expected) in range.into_iter().zip(indexes) {
+        assert_eq!(vpn.indexes(), expected);
+    }
+    // Crosses a level-1 boundary as well
+    let range = VPNRange::new(VirtPageNum(0x3_fff0), VirtPageNum(0x4_0010));
+    assert_eq!(range.into_iter().count(), 0x20);
+    assert_eq!(range.into_iter().last().unwrap().indexes(), [1, 0, 0xf]);
+    // Empty ranges yield nothing
+    let empty = VPNRange::new(VirtPageNum(0x200), VirtPageNum(0x200));
+    assert_eq!(empty.into_iter().count(), 0);
+    println!("vpn_range_test passed!");
+}
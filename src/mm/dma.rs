@@ -0,0 +1,52 @@
+//! Buffers for device DMA
+//!
+//! Devices access memory by physical address, so a buffer they read or write
+//! must be physically contiguous. The kernel maps all memory identically, so
+//! the same address works for the kernel as well.
+
+use super::{frame_alloc_contiguous, FrameTracker, PhysAddr, PhysPageNum, VirtAddr};
+use crate::config::PAGE_SIZE;
+use alloc::vec::Vec;
+
+/// Physically contiguous, zeroed frames, freed when the buffer is dropped
+pub struct DmaBuffer {
+    frames: Vec<FrameTracker>,
+}
+
+impl DmaBuffer {
+    /// Allocate a buffer of `pages` pages, or `None` if no free run is that long
+    pub fn new(pages: usize) -> Option<Self> {
+        frame_alloc_contiguous(pages).map(|frames| Self { frames })
+    }
+
+    /// First frame of the buffer
+    pub fn ppn(&self) -> PhysPageNum {
+        self.frames[0].ppn
+    }
+
+    /// Address the device uses
+    pub fn paddr(&self) -> PhysAddr {
+        self.ppn().into()
+    }
+
+    /// Address the kernel uses
+    pub fn vaddr(&self) -> VirtAddr {
+        usize::from(self.paddr()).into()
+    }
+
+    /// Size of the buffer in bytes
+    pub fn size(&self) -> usize {
+        self.frames.len() * PAGE_SIZE
+    }
+
+    /// Address the device uses for `ptr`, which points into the buffer
+    pub fn device_addr<T>(&self, ptr: *const T) -> usize {
+        let offset = (ptr as usize).wrapping_sub(self.vaddr().into());
+        assert!(
+            offset < self.size(),
+            "{:p} lies outside the DMA buffer",
+            ptr
+        );
+        usize::from(self.paddr()) + offset
+    }
+}
@@ -1,8 +1,13 @@
-//! Physical frame allocator using stack-based allocation
+//! Physical frame allocator
+//!
+//! Two allocators are available, chosen by [`FRAME_ALLOCATOR_KIND`]: a stack
+//! of recycled frames above a high-water mark, and a bitmap with one bit per
+//! frame.
 
 use super::{PhysAddr, PhysPageNum};
 use crate::config::MEMORY_END;
 use crate::sync::UPSafeCell;
+use alloc::vec;
 use alloc::vec::Vec;
 
 /// Tracker for physical frame - implements RAII
@@ -22,41 +27,77 @@ impl FrameTracker {
     }
 }
 
+impl Clone for FrameTracker {
+    /// Share the frame: bumps its reference count without touching its content
+    fn clone(&self) -> Self {
+        frame_add_ref(self.ppn);
+        Self { ppn: self.ppn }
+    }
+}
+
 impl Drop for FrameTracker {
     fn drop(&mut self) {
         frame_dealloc(self.ppn);
     }
 }
 
+/// Which allocator hands out physical frames
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum FrameAllocatorKind {
+    /// [`StackFrameAllocator`]: O(1) single frames, but contiguous runs need
+    /// a search of the recycled frames
+    Stack,
+    /// [`BitmapFrameAllocator`]: O(frames) searches for both
+    Bitmap,
+}
+
+/// The stack allocator, unless the `bitmap-frame-allocator` feature is enabled
+const FRAME_ALLOCATOR_KIND: FrameAllocatorKind = if cfg!(feature = "bitmap-frame-allocator") {
+    FrameAllocatorKind::Bitmap
+} else {
+    FrameAllocatorKind::Stack
+};
+
 trait FrameAllocator {
     fn new() -> Self;
+    /// Manage the frames `[l, r)`
+    fn init(&mut self, l: PhysPageNum, r: PhysPageNum);
     fn alloc(&mut self) -> Option<PhysPageNum>;
+    /// Allocate `count` consecutive frames, returning the first of them.
+    ///
+    /// Must not allocate memory, as the kernel heap grows through it.
+    fn alloc_contiguous(&mut self, count: usize) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
+    /// Number of frames currently allocated
+    fn used(&self) -> usize;
+    /// Number of frames managed
+    fn total(&self) -> usize;
 }
 
 /// Stack-based frame allocator
 pub struct StackFrameAllocator {
+    start: usize,
     current: usize,
     end: usize,
     recycled: Vec<usize>,
 }
 
-impl StackFrameAllocator {
-    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
-        self.current = l.0;
-        self.end = r.0;
-    }
-}
-
 impl FrameAllocator for StackFrameAllocator {
     fn new() -> Self {
         Self {
+            start: 0,
             current: 0,
             end: 0,
             recycled: Vec::new(),
         }
     }
 
+    fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
+        self.current = l.0;
+        self.end = r.0;
+    }
+
     fn alloc(&mut self) -> Option<PhysPageNum> {
         if let Some(ppn) = self.recycled.pop() {
             Some(ppn.into())
@@ -68,6 +109,47 @@ impl FrameAllocator for StackFrameAllocator {
         }
     }
 
+    fn alloc_contiguous(&mut self, count: usize) -> Option<PhysPageNum> {
+        // The order of the recycled frames does not matter, so sort them in place
+        self.recycled.sort_unstable();
+        let free = &self.recycled;
+        // Best fit among the runs of recycled frames
+        let mut best: Option<(usize, usize)> = None;
+        let mut i = 0;
+        while i < free.len() {
+            let mut j = i + 1;
+            while j < free.len() && free[j] == free[j - 1] + 1 {
+                j += 1;
+            }
+            let len = j - i;
+            if len >= count && best.map_or(true, |(_, best_len)| len < best_len) {
+                best = Some((free[i], len));
+            }
+            i = j;
+        }
+        let start = match best {
+            Some((start, _)) => start,
+            None => {
+                // A run of recycled frames right below the high-water mark
+                // can be extended by never-allocated ones
+                let tail = free
+                    .iter()
+                    .rev()
+                    .zip((0..self.current).rev())
+                    .take_while(|(&ppn, expected)| ppn == *expected)
+                    .count();
+                if self.end - self.current < count - tail {
+                    return None;
+                }
+                self.current += count - tail;
+                self.current - count
+            }
+        };
+        self.recycled
+            .retain(|ppn| !(start..start + count).contains(ppn));
+        Some(start.into())
+    }
+
     fn dealloc(&mut self, ppn: PhysPageNum) {
         let ppn = ppn.0;
         // Validity check
@@ -76,15 +158,205 @@ impl FrameAllocator for StackFrameAllocator {
         }
         self.recycled.push(ppn);
     }
+
+    fn used(&self) -> usize {
+        self.current - self.start - self.recycled.len()
+    }
+
+    fn total(&self) -> usize {
+        self.end - self.start
+    }
 }
 
-type FrameAllocatorImpl = StackFrameAllocator;
+/// Bitmap frame allocator: bit `i` of the map is set while frame `start + i`
+/// is allocated
+pub struct BitmapFrameAllocator {
+    start: usize,
+    end: usize,
+    bitmap: Vec<u64>,
+    used: usize,
+    /// Word to start the next single-frame search at
+    hint: usize,
+}
+
+impl BitmapFrameAllocator {
+    fn is_used(&self, ppn: usize) -> bool {
+        let i = ppn - self.start;
+        self.bitmap[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn set_used(&mut self, ppn: usize, used: bool) {
+        let i = ppn - self.start;
+        if used {
+            self.bitmap[i / 64] |= 1 << (i % 64);
+        } else {
+            self.bitmap[i / 64] &= !(1 << (i % 64));
+        }
+    }
+}
+
+impl FrameAllocator for BitmapFrameAllocator {
+    fn new() -> Self {
+        Self {
+            start: 0,
+            end: 0,
+            bitmap: Vec::new(),
+            used: 0,
+            hint: 0,
+        }
+    }
+
+    fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
+        self.end = r.0;
+        let frames = r.0 - l.0;
+        self.bitmap = vec![0; frames.div_ceil(64)];
+        // Bits past the last frame count as used, so searches skip them
+        if frames % 64 != 0 {
+            *self.bitmap.last_mut().unwrap() = !0 << (frames % 64);
+        }
+    }
+
+    fn alloc(&mut self) -> Option<PhysPageNum> {
+        let words = self.bitmap.len();
+        let word = (0..words)
+            .map(|i| (self.hint + i) % words)
+            .find(|&i| self.bitmap[i] != u64::MAX)?;
+        let ppn = self.start + word * 64 + self.bitmap[word].trailing_ones() as usize;
+        self.set_used(ppn, true);
+        self.used += 1;
+        self.hint = word;
+        Some(ppn.into())
+    }
+
+    fn alloc_contiguous(&mut self, count: usize) -> Option<PhysPageNum> {
+        let mut run_start = self.start;
+        for ppn in self.start..self.end {
+            if self.is_used(ppn) {
+                run_start = ppn + 1;
+            } else if ppn + 1 - run_start == count {
+                for ppn in run_start..=ppn {
+                    self.set_used(ppn, true);
+                }
+                self.used += count;
+                return Some(run_start.into());
+            }
+        }
+        None
+    }
+
+    fn dealloc(&mut self, ppn: PhysPageNum) {
+        let ppn = ppn.0;
+        if ppn < self.start || ppn >= self.end || !self.is_used(ppn) {
+            panic!("Frame ppn={:#x} has not been allocated!", ppn);
+        }
+        self.set_used(ppn, false);
+        self.used -= 1;
+    }
+
+    fn used(&self) -> usize {
+        self.used
+    }
+
+    fn total(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+/// The allocator selected by [`FRAME_ALLOCATOR_KIND`]
+enum FrameAllocatorImpl {
+    Stack(StackFrameAllocator),
+    Bitmap(BitmapFrameAllocator),
+}
+
+impl FrameAllocator for FrameAllocatorImpl {
+    fn new() -> Self {
+        match FRAME_ALLOCATOR_KIND {
+            FrameAllocatorKind::Stack => Self::Stack(StackFrameAllocator::new()),
+            FrameAllocatorKind::Bitmap => Self::Bitmap(BitmapFrameAllocator::new()),
+        }
+    }
+
+    fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        match self {
+            Self::Stack(allocator) => allocator.init(l, r),
+            Self::Bitmap(allocator) => allocator.init(l, r),
+        }
+    }
+
+    fn alloc(&mut self) -> Option<PhysPageNum> {
+        match self {
+            Self::Stack(allocator) => allocator.alloc(),
+            Self::Bitmap(allocator) => allocator.alloc(),
+        }
+    }
+
+    fn alloc_contiguous(&mut self, count: usize) -> Option<PhysPageNum> {
+        match self {
+            Self::Stack(allocator) => allocator.alloc_contiguous(count),
+            Self::Bitmap(allocator) => allocator.alloc_contiguous(count),
+        }
+    }
+
+    fn dealloc(&mut self, ppn: PhysPageNum) {
+        match self {
+            Self::Stack(allocator) => allocator.dealloc(ppn),
+            Self::Bitmap(allocator) => allocator.dealloc(ppn),
+        }
+    }
+
+    fn used(&self) -> usize {
+        match self {
+            Self::Stack(allocator) => allocator.used(),
+            Self::Bitmap(allocator) => allocator.used(),
+        }
+    }
+
+    fn total(&self) -> usize {
+        match self {
+            Self::Stack(allocator) => allocator.total(),
+            Self::Bitmap(allocator) => allocator.total(),
+        }
+    }
+}
+
+/// Reference counts of the managed frames, indexed by `ppn - base`.
+///
+/// 32 bits wide because every untouched page of a lazy area may refer to
+/// the zero frame, and there can be more of those than frames.
+struct FrameRefCounts {
+    base: usize,
+    counts: Vec<u32>,
+}
+
+impl FrameRefCounts {
+    fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.base = l.0;
+        self.counts = vec![0; r.0 - l.0];
+    }
+
+    fn get_mut(&mut self, ppn: PhysPageNum) -> &mut u32 {
+        let base = self.base;
+        self.counts
+            .get_mut(ppn.0.wrapping_sub(base))
+            .unwrap_or_else(|| panic!("Frame ppn={:#x} is not managed!", ppn.0))
+    }
+}
 
 use lazy_static::lazy_static;
 
 lazy_static! {
     static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> =
         unsafe { UPSafeCell::new(FrameAllocatorImpl::new()) };
+    static ref FRAME_REF_COUNTS: UPSafeCell<FrameRefCounts> = unsafe {
+        UPSafeCell::new(FrameRefCounts {
+            base: 0,
+            counts: Vec::new(),
+        })
+    };
+    /// Frame of zeroes shared read-only by every lazy page that has been
+    /// read but not yet written; holding it here keeps it from being freed
+    static ref ZERO_FRAME: FrameTracker = frame_alloc().unwrap();
 }
 
 /// Initialize frame allocator
@@ -92,23 +364,94 @@ pub fn init_frame_allocator() {
     extern "C" {
         fn ekernel();
     }
-    FRAME_ALLOCATOR.exclusive_access().init(
-        PhysAddr::from(ekernel as usize).ceil(),
-        PhysAddr::from(MEMORY_END).floor(),
-    );
+    let start = PhysAddr::from(ekernel as usize).ceil();
+    let end = PhysAddr::from(MEMORY_END).floor();
+    FRAME_ALLOCATOR.exclusive_access().init(start, end);
+    FRAME_REF_COUNTS.exclusive_access().init(start, end);
 }
 
-/// Allocate a physical frame
+/// Allocate a physical frame with a reference count of 1
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR
-        .exclusive_access()
-        .alloc()
-        .map(FrameTracker::new)
+    let ppn = FRAME_ALLOCATOR.exclusive_access().alloc()?;
+    *FRAME_REF_COUNTS.exclusive_access().get_mut(ppn) = 1;
+    Some(FrameTracker::new(ppn))
+}
+
+/// Allocate `count` physically consecutive frames, each with a reference
+/// count of 1, or `None` if there is no free run that long
+pub fn frame_alloc_contiguous(count: usize) -> Option<Vec<FrameTracker>> {
+    if count == 0 {
+        return None;
+    }
+    let start = FRAME_ALLOCATOR.exclusive_access().alloc_contiguous(count)?;
+    let mut ref_counts = FRAME_REF_COUNTS.exclusive_access();
+    Some(
+        (start.0..start.0 + count)
+            .map(|ppn| {
+                *ref_counts.get_mut(ppn.into()) = 1;
+                FrameTracker::new(ppn.into())
+            })
+            .collect(),
+    )
+}
+
+/// Take `count` consecutive frames for the kernel heap, which keeps them for
+/// good. Unlike [`frame_alloc_contiguous`], this neither allocates memory
+/// nor zeroes the frames, and it fails instead of panicking if the allocator
+/// is in use, e.g. because the heap ran out while it grew the recycle list.
+pub fn frame_alloc_for_heap(count: usize) -> Option<PhysPageNum> {
+    let mut allocator = FRAME_ALLOCATOR.try_exclusive_access()?;
+    let mut ref_counts = FRAME_REF_COUNTS.try_exclusive_access()?;
+    let start = allocator.alloc_contiguous(count)?;
+    for ppn in start.0..start.0 + count {
+        *ref_counts.get_mut(ppn.into()) = 1;
+    }
+    Some(start)
+}
+
+/// Take a reference to the shared zero frame, which must never be written
+pub fn zero_frame() -> FrameTracker {
+    ZERO_FRAME.clone()
+}
+
+/// Whether `ppn` is the shared zero frame
+pub fn is_zero_frame(ppn: PhysPageNum) -> bool {
+    ZERO_FRAME.ppn == ppn
+}
+
+/// Number of frames currently allocated
+pub fn frames_used() -> usize {
+    FRAME_ALLOCATOR.exclusive_access().used()
+}
+
+/// Number of frames available to the allocator in total
+pub fn frames_total() -> usize {
+    FRAME_ALLOCATOR.exclusive_access().total()
 }
 
-/// Deallocate a physical frame
+/// Add a reference to an allocated frame
+pub fn frame_add_ref(ppn: PhysPageNum) {
+    let mut ref_counts = FRAME_REF_COUNTS.exclusive_access();
+    let count = ref_counts.get_mut(ppn);
+    assert!(*count > 0, "Frame ppn={:#x} has not been allocated!", ppn.0);
+    *count += 1;
+}
+
+/// Drop a reference to a frame, freeing it once no reference is left
 pub fn frame_dealloc(ppn: PhysPageNum) {
-    FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
+    let mut ref_counts = FRAME_REF_COUNTS.exclusive_access();
+    let count = ref_counts.get_mut(ppn);
+    assert!(*count > 0, "Frame ppn={:#x} has not been allocated!", ppn.0);
+    *count -= 1;
+    if *count == 0 {
+        drop(ref_counts);
+        FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
+    }
+}
+
+/// Get the number of references to a frame, for debugging
+pub fn frame_ref_count(ppn: PhysPageNum) -> usize {
+    *FRAME_REF_COUNTS.exclusive_access().get_mut(ppn) as usize
 }
 
 #[allow(unused)]
@@ -126,5 +469,27 @@ pub fn frame_allocator_test() {
         v.push(frame);
     }
     drop(v);
+    assert!(frames_used() <= frames_total());
     println!("frame_allocator_test passed!");
 }
+
+pub fn frame_alloc_contiguous_test() {
+    let is_run = |frames: &[FrameTracker]| frames.windows(2).all(|w| w[1].ppn.0 == w[0].ppn.0 + 1);
+    let mut singles: Vec<FrameTracker> = (0..6).map(|_| frame_alloc().unwrap()).collect();
+    // Free frames 1..3 and 4..6 of the six, leaving two recycled runs
+    singles.drain(4..6);
+    singles.drain(1..3);
+    let run = frame_alloc_contiguous(2).unwrap();
+    assert!(is_run(&run));
+    // Longer than any recycled run
+    let long = frame_alloc_contiguous(5).unwrap();
+    assert!(is_run(&long));
+    assert!(frame_alloc_contiguous(0).is_none());
+    assert!(frame_alloc_contiguous(usize::MAX / 2).is_none());
+    let used = frames_used();
+    drop(long);
+    drop(run);
+    drop(singles);
+    assert_eq!(frames_used(), used - 9);
+    println!("frame_alloc_contiguous_test passed!");
+}
@@ -1,10 +1,51 @@
 //! Kernel heap allocator using buddy system
+//!
+//! The heap starts out as a static arena of `KERNEL_HEAP_SIZE` bytes. When an
+//! allocation fails, it grows by frames from the frame allocator, up to
+//! `KERNEL_HEAP_MAX_SIZE` bytes in total, before giving up.
 
-use crate::config::KERNEL_HEAP_SIZE;
-use buddy_system_allocator::LockedHeap;
+use super::{frame_alloc_for_heap, PhysAddr};
+use crate::config::{KERNEL_HEAP_MAX_SIZE, KERNEL_HEAP_SIZE, PAGE_SIZE};
+use crate::console::console_flush;
+use crate::sbi::shutdown;
+use buddy_system_allocator::{Heap, LockedHeapWithRescue};
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Smallest step the heap grows by, to keep the number of regions low
+const HEAP_GROW_MIN: usize = 16 * PAGE_SIZE;
+
+/// The buddy allocator, counting the bytes requested from it
+struct TrackedHeap {
+    heap: LockedHeapWithRescue<32>,
+    /// Bytes currently allocated, as requested, i.e. not rounded up
+    current: AtomicUsize,
+    /// Highest value `current` ever had
+    peak: AtomicUsize,
+}
+
+unsafe impl GlobalAlloc for TrackedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.heap.alloc(layout);
+        if !ptr.is_null() {
+            let current = self.current.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            self.peak.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap.dealloc(ptr, layout);
+        self.current.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
 
 #[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap<32> = LockedHeap::empty();
+static HEAP_ALLOCATOR: TrackedHeap = TrackedHeap {
+    heap: LockedHeapWithRescue::new(grow_heap),
+    current: AtomicUsize::new(0),
+    peak: AtomicUsize::new(0),
+};
 
 static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 
@@ -12,14 +53,100 @@ static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 pub fn init_heap() {
     unsafe {
         HEAP_ALLOCATOR
+            .heap
             .lock()
             .init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
     }
 }
 
+/// Add frames to `heap` after it failed to allocate `layout`.
+///
+/// The kernel space maps all memory identically, so the frames need no
+/// mapping of their own. Runs with the heap locked, so nothing here may
+/// allocate.
+fn grow_heap(heap: &mut Heap<32>, layout: &Layout) {
+    // Only a region of twice its size surely holds a buddy block of a size
+    let block = layout.size().max(layout.align()).next_power_of_two();
+    let size = (2 * block).max(HEAP_GROW_MIN).next_multiple_of(PAGE_SIZE);
+    if heap.stats_total_bytes() + size > KERNEL_HEAP_MAX_SIZE {
+        return;
+    }
+    if let Some(ppn) = frame_alloc_for_heap(size / PAGE_SIZE) {
+        let start: usize = PhysAddr::from(ppn).into();
+        unsafe { heap.add_to_heap(start, start + size) };
+    }
+}
+
+/// Physical address range of the static heap space
+pub fn heap_range() -> (usize, usize) {
+    let start = unsafe { HEAP_SPACE.as_ptr() as usize };
+    (start, start + KERNEL_HEAP_SIZE)
+}
+
+/// Snapshot of the kernel heap usage
+#[derive(Copy, Clone, Debug)]
+pub struct HeapStats {
+    /// Bytes handed out, including rounding up to buddy block sizes
+    pub allocated: usize,
+    /// Bytes not handed out
+    pub free: usize,
+    /// Size of the largest block a single allocation can still get
+    pub largest_free_block: usize,
+}
+
+/// Collect the current kernel heap usage
+pub fn heap_stats() -> HeapStats {
+    let mut heap = HEAP_ALLOCATOR.heap.lock();
+    let allocated = heap.stats_alloc_actual();
+    let free = heap.stats_total_bytes() - allocated;
+    // The free lists are private, so probe for the largest block from the
+    // top down. The first block that fits is not split, and freeing it
+    // again restores the previous state.
+    let largest_free_block = (0..usize::BITS as usize)
+        .rev()
+        .map(|order| 1usize << order)
+        .filter(|&size| size <= free)
+        .find(|&size| {
+            let layout = Layout::from_size_align(size, size).unwrap();
+            match heap.alloc(layout) {
+                Ok(ptr) => {
+                    heap.dealloc(ptr, layout);
+                    true
+                }
+                Err(_) => false,
+            }
+        })
+        .unwrap_or(0);
+    HeapStats {
+        allocated,
+        free,
+        largest_free_block,
+    }
+}
+
+/// Get the most bytes the kernel heap ever had allocated at once, as
+/// requested by the allocations, i.e. before rounding up to block sizes
+pub fn heap_peak_usage() -> usize {
+    HEAP_ALLOCATOR.peak.load(Ordering::Relaxed)
+}
+
+/// Report the failed allocation and the heap state, then power off.
+///
+/// Nothing here may allocate, since the heap is what just ran out.
 #[alloc_error_handler]
-pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
-    panic!("Heap allocation error, layout = {:?}", layout);
+pub fn handle_alloc_error(layout: Layout) -> ! {
+    error!(
+        "Kernel heap exhausted: failed to allocate {} bytes aligned to {}",
+        layout.size(),
+        layout.align()
+    );
+    let stats = heap_stats();
+    error!(
+        "Heap: {} bytes allocated, {} bytes free, largest free block {} bytes",
+        stats.allocated, stats.free, stats.largest_free_block
+    );
+    console_flush();
+    shutdown()
 }
 
 #[allow(unused)]
@@ -46,3 +173,50 @@ pub fn heap_test() {
     drop(v);
     println!("heap_test passed!");
 }
+
+/// Check that the heap grows past its static arena when it runs full
+pub fn heap_growth_test() {
+    use alloc::vec::Vec;
+    extern "C" {
+        fn ekernel();
+    }
+    let before = heap_stats();
+    // More than the whole static arena
+    let v: Vec<u8> = Vec::with_capacity(KERNEL_HEAP_SIZE);
+    assert!(v.as_ptr() as usize >= ekernel as usize);
+    let during = heap_stats();
+    assert!(during.allocated + during.free > before.allocated + before.free);
+    assert!(during.allocated + during.free <= KERNEL_HEAP_MAX_SIZE);
+    drop(v);
+    println!("heap_growth_test passed!");
+}
+
+/// Check that `heap_stats` accounts for allocations and frees
+pub fn heap_stats_test() {
+    use alloc::vec::Vec;
+    let before = heap_stats();
+    let vs: Vec<Vec<u8>> = (1..=4).map(|i| Vec::with_capacity(i * 1024)).collect();
+    let during = heap_stats();
+    assert!(during.allocated >= before.allocated + 10 * 1024);
+    assert_eq!(
+        during.allocated + during.free,
+        before.allocated + before.free
+    );
+    assert!(during.largest_free_block <= during.free);
+    drop(vs);
+    let after = heap_stats();
+    assert_eq!(after.allocated, before.allocated);
+    println!("heap_stats_test passed!");
+}
+
+/// Check that the peak heap usage follows allocations but not frees
+pub fn heap_peak_test() {
+    use alloc::vec::Vec;
+    let before = heap_peak_usage();
+    let v: Vec<u8> = Vec::with_capacity(64 * 1024);
+    let during = heap_peak_usage();
+    assert!(during >= before && during >= 64 * 1024);
+    drop(v);
+    assert_eq!(heap_peak_usage(), during);
+    println!("heap_peak_test passed!");
+}
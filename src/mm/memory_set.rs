@@ -0,0 +1,1014 @@
+//! Address space abstraction built on top of [`PageTable`]
+
+use super::{frame_alloc, frame_ref_count, frames_used, is_zero_frame, zero_frame, FrameTracker};
+use super::{shm_detached, ShmSegment};
+use super::{PTEFlags, PageTable, PageTableEntry};
+use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+use super::{StepByOne, VPNRange};
+use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use bitflags::*;
+use core::arch::asm;
+use lazy_static::*;
+use riscv::register::satp;
+
+extern "C" {
+    fn stext();
+    fn etext();
+    fn srodata();
+    fn erodata();
+    fn sdata();
+    fn edata();
+    fn sbss_with_stack();
+    fn ebss();
+    fn ekernel();
+    fn strampoline();
+}
+
+lazy_static! {
+    /// The kernel's address space
+    pub static ref KERNEL_SPACE: Arc<UPSafeCell<MemorySet>> =
+        Arc::new(unsafe { UPSafeCell::new(MemorySet::new_kernel()) });
+}
+
+/// Address space: a page table plus the areas mapped into it
+pub struct MemorySet {
+    page_table: PageTable,
+    areas: Vec<MapArea>,
+}
+
+impl MemorySet {
+    /// Create an empty address space
+    pub fn new_bare() -> Self {
+        Self {
+            page_table: PageTable::new(),
+            areas: Vec::new(),
+        }
+    }
+
+    /// Get satp token of the underlying page table
+    pub fn token(&self) -> usize {
+        self.page_table.token()
+    }
+
+    /// Insert a framed area, assuming it doesn't overlap existing areas
+    pub fn insert_framed_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+    ) {
+        self.push(
+            MapArea::new(start_va, end_va, MapType::Framed, permission),
+            None,
+        );
+    }
+
+    /// Insert an area whose frames are only allocated when first touched
+    pub fn insert_lazy_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+    ) {
+        self.push(
+            MapArea::new(start_va, end_va, MapType::Lazy, permission),
+            None,
+        );
+    }
+
+    /// Map the frames of the shared memory `segment` starting at `start_va`,
+    /// assuming the range doesn't overlap existing areas
+    pub fn insert_shared_area(
+        &mut self,
+        start_va: VirtAddr,
+        segment: Arc<ShmSegment>,
+        permission: MapPermission,
+    ) {
+        let end_va = VirtAddr::from(usize::from(start_va) + segment.size());
+        let mut map_area = MapArea::new(start_va, end_va, MapType::Shared, permission);
+        map_area.shm = Some(segment);
+        self.push(map_area, None);
+    }
+
+    /// Unmap the shared memory area starting at `start_va`, detaching its
+    /// segment; fails if there is no such area
+    pub fn remove_shared_area(&mut self, start_va: VirtAddr) -> bool {
+        let Some(idx) = self.areas.iter().position(|area| {
+            area.map_type == MapType::Shared && area.vpn_range.get_start() == start_va.floor()
+        }) else {
+            return false;
+        };
+        self.areas[idx].unmap(&mut self.page_table);
+        self.areas.remove(idx);
+        true
+    }
+
+    /// Whether any page in `[start_va, end_va)` belongs to an existing area
+    pub fn overlaps(&self, start_va: VirtAddr, end_va: VirtAddr) -> bool {
+        let (start_vpn, end_vpn) = (start_va.floor(), end_va.ceil());
+        self.areas.iter().any(|area| {
+            area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+        })
+    }
+
+    /// Unmap and drop the area starting at `start_vpn`, freeing its frames
+    pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
+        if let Some((idx, area)) = self
+            .areas
+            .iter_mut()
+            .enumerate()
+            .find(|(_, area)| area.vpn_range.get_start() == start_vpn)
+        {
+            area.unmap(&mut self.page_table);
+            self.areas.remove(idx);
+        }
+    }
+
+    /// Unmap and drop the area spanning exactly `[start_va, end_va)`, freeing
+    /// its frames; returns false if no area matches the range as a whole
+    pub fn remove_area(&mut self, start_va: VirtAddr, end_va: VirtAddr) -> bool {
+        let (start_vpn, end_vpn) = (start_va.floor(), end_va.ceil());
+        if let Some(idx) = self.areas.iter().position(|area| {
+            area.vpn_range.get_start() == start_vpn && area.vpn_range.get_end() == end_vpn
+        }) {
+            self.areas[idx].unmap(&mut self.page_table);
+            self.areas.remove(idx);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether every page in `[start_vpn, end_vpn)` lies in an area
+    fn covers(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        // Walk the overlapping areas by start, so that the cost does not
+        // depend on the length of the range
+        let mut ranges: Vec<_> = self
+            .areas
+            .iter()
+            .map(|area| (area.vpn_range.get_start(), area.vpn_range.get_end()))
+            .filter(|&(start, end)| start < end_vpn && start_vpn < end)
+            .collect();
+        ranges.sort_unstable_by_key(|&(start, _)| start);
+        let mut covered = start_vpn;
+        for (start, end) in ranges {
+            if start > covered {
+                break;
+            }
+            covered = covered.max(end);
+        }
+        covered >= end_vpn
+    }
+
+    /// Split the areas reaching out of `[start_vpn, end_vpn)` at its bounds,
+    /// so that each one lies either inside or outside of it. Shared areas
+    /// cannot be split and stay whole.
+    fn split_at_range(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        // Areas split off are pushed behind the ones iterated over, and lie
+        // inside or outside the range already
+        for idx in 0..self.areas.len() {
+            let area = &mut self.areas[idx];
+            let (start, end) = (area.vpn_range.get_start(), area.vpn_range.get_end());
+            if end <= start_vpn || end_vpn <= start || area.map_type == MapType::Shared {
+                continue;
+            }
+            if end > end_vpn {
+                let tail = area.split_off(end_vpn);
+                self.areas.push(tail);
+            }
+            if start < start_vpn {
+                let inside = self.areas[idx].split_off(start_vpn);
+                self.areas.push(inside);
+            }
+        }
+    }
+
+    /// Unmap the pages in `[start_vpn, end_vpn)`, freeing their frames. Areas
+    /// reaching out of the range are split, and only their pages inside it go.
+    ///
+    /// Returns false without unmapping anything if no area overlaps the
+    /// range or a shared one does, as shared segments are detached whole.
+    pub fn remove_range(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        let overlaps = |area: &MapArea| {
+            area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+        };
+        if !self.areas.iter().any(overlaps)
+            || self
+                .areas
+                .iter()
+                .any(|area| overlaps(area) && area.map_type == MapType::Shared)
+        {
+            return false;
+        }
+        self.split_at_range(start_vpn, end_vpn);
+        let page_table = &mut self.page_table;
+        self.areas.retain_mut(|area| {
+            if overlaps(area) {
+                area.unmap(page_table);
+            }
+            !overlaps(area)
+        });
+        true
+    }
+
+    /// Give the pages in `[start_vpn, end_vpn)` the access rights in
+    /// `permission`, splitting areas reaching out of the range. Frames stay
+    /// as they are, so pages shared copy-on-write stay read-only until the
+    /// first store copies them. Without any of R, W and X the pages stay
+    /// mapped to their frames, but with a leaf PTE the hardware refuses
+    /// every access through.
+    ///
+    /// Returns false without changing anything unless every page of the
+    /// range lies in an area and no shared area reaches out of it.
+    pub fn protect_range(
+        &mut self,
+        start_vpn: VirtPageNum,
+        end_vpn: VirtPageNum,
+        permission: MapPermission,
+    ) -> bool {
+        let overlaps = |area: &MapArea| {
+            area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+        };
+        if !self.covers(start_vpn, end_vpn)
+            || self.areas.iter().any(|area| {
+                overlaps(area)
+                    && area.map_type == MapType::Shared
+                    && (area.vpn_range.get_start() < start_vpn
+                        || area.vpn_range.get_end() > end_vpn)
+            })
+        {
+            return false;
+        }
+        self.split_at_range(start_vpn, end_vpn);
+        for area in self.areas.iter_mut().filter(|area| overlaps(area)) {
+            area.map_perm = permission;
+            // Only pages holding a frame are mapped, unless the area is shared
+            let vpns: Vec<VirtPageNum> = match area.map_type {
+                MapType::Framed | MapType::Lazy => area.data_frames.keys().copied().collect(),
+                MapType::Identical | MapType::Shared => area.vpn_range.into_iter().collect(),
+            };
+            for vpn in vpns {
+                let pte = self.page_table.translate(vpn).unwrap();
+                let mut flags = PTEFlags::from_bits(permission.bits() as u16).unwrap();
+                if pte.is_cow() {
+                    flags.remove(PTEFlags::W);
+                    flags.insert(PTEFlags::COW);
+                }
+                self.page_table.set_flags(vpn, flags);
+            }
+        }
+        true
+    }
+
+    /// Drop the pages in `[start_vpn, end_vpn)` and free their frames, while
+    /// their areas stay. The parts of the areas inside the range become lazy,
+    /// so the next access to a page finds it zeroed. Pages of shared segments
+    /// keep their contents anyway and stay mapped.
+    ///
+    /// Returns false without dropping anything unless every page of the
+    /// range lies in an area.
+    pub fn discard_range(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        if !self.covers(start_vpn, end_vpn) {
+            return false;
+        }
+        self.split_at_range(start_vpn, end_vpn);
+        for area in self.areas.iter_mut().filter(|area| {
+            area.vpn_range.get_start() < end_vpn
+                && start_vpn < area.vpn_range.get_end()
+                && matches!(area.map_type, MapType::Framed | MapType::Lazy)
+        }) {
+            area.unmap(&mut self.page_table);
+            area.map_type = MapType::Lazy;
+        }
+        true
+    }
+
+    /// Map an area into this address space, optionally copying `data` into it
+    fn push(&mut self, map_area: MapArea, data: Option<&[u8]>) {
+        self.push_with_offset(map_area, 0, data);
+    }
+
+    /// Like [`MemorySet::push`], but `data` starts `offset` bytes into the first page
+    fn push_with_offset(&mut self, mut map_area: MapArea, offset: usize, data: Option<&[u8]>) {
+        map_area.map(&mut self.page_table);
+        if let Some(data) = data {
+            map_area.copy_data(&mut self.page_table, offset, data);
+        }
+        self.areas.push(map_area);
+    }
+
+    /// Map the trampoline at the top of the address space. It is not
+    /// tracked by any area, since no address space owns its frame.
+    fn map_trampoline(&mut self) {
+        self.page_table.map(
+            VirtAddr::from(TRAMPOLINE).into(),
+            PhysAddr::from(strampoline as usize).into(),
+            PTEFlags::R | PTEFlags::X,
+        );
+    }
+
+    /// Create the kernel address space: the trampoline plus identical
+    /// mappings of the kernel image and of the remaining physical memory
+    pub fn new_kernel() -> Self {
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trampoline();
+        println!(
+            "[KERNEL] .text [{:#x}, {:#x})",
+            stext as usize, etext as usize
+        );
+        println!(
+            "[KERNEL] .rodata [{:#x}, {:#x})",
+            srodata as usize, erodata as usize
+        );
+        println!(
+            "[KERNEL] .data [{:#x}, {:#x})",
+            sdata as usize, edata as usize
+        );
+        println!(
+            "[KERNEL] .bss [{:#x}, {:#x})",
+            sbss_with_stack as usize, ebss as usize
+        );
+        memory_set.map_kernel();
+        memory_set
+    }
+
+    /// Identically map the kernel image, the remaining physical memory and
+    /// the MMIO regions of devices
+    fn map_kernel(&mut self) {
+        self.push(
+            MapArea::new(
+                (stext as usize).into(),
+                (etext as usize).into(),
+                MapType::Identical,
+                MapPermission::R | MapPermission::X,
+            ),
+            None,
+        );
+        self.push(
+            MapArea::new(
+                (srodata as usize).into(),
+                (erodata as usize).into(),
+                MapType::Identical,
+                MapPermission::R,
+            ),
+            None,
+        );
+        self.push(
+            MapArea::new(
+                (sdata as usize).into(),
+                (edata as usize).into(),
+                MapType::Identical,
+                MapPermission::R | MapPermission::W,
+            ),
+            None,
+        );
+        self.push(
+            MapArea::new(
+                (sbss_with_stack as usize).into(),
+                (ebss as usize).into(),
+                MapType::Identical,
+                MapPermission::R | MapPermission::W,
+            ),
+            None,
+        );
+        self.push(
+            MapArea::new(
+                (ekernel as usize).into(),
+                MEMORY_END.into(),
+                MapType::Identical,
+                MapPermission::R | MapPermission::W,
+            ),
+            None,
+        );
+        for &(base, size) in MMIO {
+            self.push(
+                MapArea::new(
+                    base.into(),
+                    (base + size).into(),
+                    MapType::Identical,
+                    MapPermission::R | MapPermission::W,
+                ),
+                None,
+            );
+        }
+    }
+
+    /// Build a user address space from an ELF64 image.
+    ///
+    /// Returns the address space, the initial user stack pointer and the entry point.
+    /// Besides the image, stack and heap, only the trampoline and the trap
+    /// context page are mapped; the kernel itself is not.
+    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trampoline();
+        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+        let elf_header = elf.header;
+        let magic = elf_header.pt1.magic;
+        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
+        let ph_count = elf_header.pt2.ph_count();
+        let mut max_end_vpn = VirtPageNum(0);
+        for i in 0..ph_count {
+            let ph = elf.program_header(i).unwrap();
+            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
+                let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
+                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
+                let mut map_perm = MapPermission::U;
+                let ph_flags = ph.flags();
+                if ph_flags.is_read() {
+                    map_perm |= MapPermission::R;
+                }
+                if ph_flags.is_write() {
+                    map_perm |= MapPermission::W;
+                }
+                if ph_flags.is_execute() {
+                    map_perm |= MapPermission::X;
+                }
+                let file_end_va: VirtAddr = ((ph.virtual_addr() + ph.file_size()) as usize).into();
+                max_end_vpn = end_va.ceil();
+                // Only the first file_size bytes come from the image. The rest
+                // of mem_size is BSS: the page it shares with the image data
+                // is loaded along with it and stays zero since frames are
+                // cleared, the pages after it are demand-zero
+                let data_end_vpn = file_end_va.ceil();
+                if data_end_vpn > start_va.floor() {
+                    let map_area =
+                        MapArea::new(start_va, data_end_vpn.into(), MapType::Framed, map_perm);
+                    let file_start = ph.offset() as usize;
+                    let file_end = file_start + ph.file_size() as usize;
+                    memory_set.push_with_offset(
+                        map_area,
+                        start_va.page_offset(),
+                        Some(&elf.input[file_start..file_end]),
+                    );
+                }
+                if max_end_vpn > data_end_vpn {
+                    memory_set.insert_lazy_area(data_end_vpn.into(), end_va, map_perm);
+                }
+            }
+        }
+        // Leave an unmapped guard page between the image and the user stack
+        let max_end_va: VirtAddr = max_end_vpn.into();
+        let mut user_stack_bottom: usize = max_end_va.into();
+        user_stack_bottom += PAGE_SIZE;
+        let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
+        memory_set.insert_lazy_area(
+            user_stack_bottom.into(),
+            user_stack_top.into(),
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        );
+        // The heap starts out empty right above the user stack and is grown
+        // through brk
+        memory_set.insert_lazy_area(
+            user_stack_top.into(),
+            user_stack_top.into(),
+            MapPermission::R | MapPermission::W | MapPermission::U,
+        );
+        // Kernel-only page right below the trampoline for the TrapContext
+        memory_set.insert_framed_area(
+            TRAP_CONTEXT.into(),
+            TRAMPOLINE.into(),
+            MapPermission::R | MapPermission::W,
+        );
+        (
+            memory_set,
+            user_stack_top,
+            elf.header.pt2.entry_point() as usize,
+        )
+    }
+
+    /// Shrink the heap ending at `end` so that it ends at `new_end`, freeing
+    /// the frames above it. munmap and mprotect may have split the heap into
+    /// several areas or left holes in it, so the pages in between go from
+    /// whatever areas are left there.
+    ///
+    /// Returns false without unmapping anything if a shared area lies there.
+    pub fn shrink_to(&mut self, end: VirtAddr, new_end: VirtAddr) -> bool {
+        let (start_vpn, end_vpn) = (new_end.ceil(), end.ceil());
+        if !self.areas.iter().any(|area| {
+            area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+        }) {
+            return true;
+        }
+        self.remove_range(start_vpn, end_vpn)
+    }
+
+    /// Extend the heap `[start, end)` so that it ends at `new_end`; the pages
+    /// in between must be free. The heap area holding the page below `end`
+    /// grows, unless that page was unmapped or protected otherwise: a new
+    /// heap area is added then.
+    pub fn append_to(&mut self, start: VirtAddr, end: VirtAddr, new_end: VirtAddr) {
+        let permission = MapPermission::R | MapPermission::W | MapPermission::U;
+        let (start_vpn, end_vpn, new_end_vpn) = (start.floor(), end.ceil(), new_end.ceil());
+        if new_end_vpn <= end_vpn {
+            return;
+        }
+        match self.areas.iter_mut().find(|area| {
+            area.vpn_range.get_start() >= start_vpn
+                && area.vpn_range.get_end() == end_vpn
+                && area.map_type == MapType::Lazy
+                && area.map_perm == permission
+        }) {
+            Some(area) => area.append_to(&mut self.page_table, new_end_vpn),
+            None => self.insert_lazy_area(end_vpn.into(), new_end_vpn.into(), permission),
+        }
+    }
+
+    /// Switch to this address space and flush stale TLB entries
+    pub fn activate(&self) {
+        let satp = self.page_table.token();
+        unsafe {
+            satp::write(satp);
+            asm!("sfence.vma");
+        }
+    }
+
+    /// Duplicate a user address space copy-on-write: writable pages are
+    /// shared read-only and marked COW in both spaces, to be copied on the
+    /// first store fault
+    pub fn from_existed_user_cow(user_space: &mut MemorySet) -> MemorySet {
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trampoline();
+        let MemorySet { page_table, areas } = user_space;
+        for area in areas
+            .iter()
+            .filter(|area| area.map_type != MapType::Identical)
+        {
+            let mut new_area = MapArea::from_another(area);
+            if area.map_type == MapType::Shared {
+                // The child attaches the same segment instead of copying it
+                memory_set.push(new_area, None);
+                continue;
+            }
+            if !area.map_perm.contains(MapPermission::U) {
+                // The kernel writes kernel-only pages such as the trap context
+                // through their physical address, bypassing COW, so they are
+                // copied right away
+                memory_set.push(new_area, None);
+                for vpn in area.vpn_range {
+                    let src_ppn = page_table.translate(vpn).unwrap().ppn();
+                    let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
+                    dst_ppn
+                        .get_bytes_array()
+                        .copy_from_slice(src_ppn.get_bytes_array());
+                }
+                continue;
+            }
+            // Lazy pages that were never touched stay unmapped in both spaces.
+            // Read-only pages are marked COW too, in case mprotect makes them
+            // writable later.
+            for (&vpn, frame) in area.data_frames.iter() {
+                let frame = frame.clone();
+                let mut flags = PTEFlags::from_bits(area.map_perm.bits() as u16).unwrap();
+                flags.remove(PTEFlags::W);
+                flags.insert(PTEFlags::COW);
+                page_table.set_flags(vpn, flags);
+                memory_set.page_table.map(vpn, frame.ppn, flags);
+                new_area.data_frames.insert(vpn, frame);
+            }
+            memory_set.areas.push(new_area);
+        }
+        memory_set
+    }
+
+    /// Resolve a store fault on a copy-on-write page.
+    ///
+    /// Fails as genuine if `va` is not a COW page of this address space or
+    /// its area is not writable, and if no frame is left for the copy.
+    pub fn handle_cow_fault(&mut self, va: VirtAddr) -> Result<(), FaultError> {
+        let vpn = va.floor();
+        match self.page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() && pte.is_cow() => {}
+            _ => return Err(FaultError::Genuine),
+        }
+        let area = match self.areas.iter_mut().find(|area| area.contains(vpn)) {
+            Some(area) if area.map_perm.contains(MapPermission::W) => area,
+            _ => return Err(FaultError::Genuine),
+        };
+        let flags = PTEFlags::from_bits(area.map_perm.bits() as u16).unwrap();
+        let frame = area.data_frames.get(&vpn).unwrap();
+        if frame_ref_count(frame.ppn) == 1 {
+            // Every other sharer already took its own copy
+            self.page_table.set_flags(vpn, flags);
+        } else {
+            let new_frame = frame_alloc().ok_or(FaultError::OutOfMemory)?;
+            // Fresh frames are cleared, so a copy of the zero frame is free
+            if !is_zero_frame(frame.ppn) {
+                new_frame
+                    .ppn
+                    .get_bytes_array()
+                    .copy_from_slice(frame.ppn.get_bytes_array());
+            }
+            self.page_table.remap(vpn, new_frame.ppn, flags);
+            // Dropping our tracker of the shared frame decrements its count
+            area.data_frames.insert(vpn, new_frame);
+        }
+        Ok(())
+    }
+
+    /// Resolve a page fault on a not yet populated page of a lazy area,
+    /// caused by a store if `write`.
+    ///
+    /// Fails as genuine if `va` lies outside every lazy area, is already
+    /// mapped or may not be accessed at all, and if no frame is left.
+    pub fn handle_lazy_fault(&mut self, va: VirtAddr, write: bool) -> Result<(), FaultError> {
+        let vpn = va.floor();
+        let access = MapPermission::R | MapPermission::W | MapPermission::X;
+        match self
+            .areas
+            .iter_mut()
+            .find(|area| area.map_type == MapType::Lazy && area.contains(vpn))
+        {
+            Some(area)
+                if !area.data_frames.contains_key(&vpn) && area.map_perm.intersects(access) =>
+            {
+                area.map_one_lazy(&mut self.page_table, vpn, write)
+            }
+            _ => Err(FaultError::Genuine),
+        }
+    }
+
+    /// Free every area together with its frames, but keep the page table
+    /// itself, which may still be the active one
+    pub fn recycle_data_pages(&mut self) {
+        self.areas.clear();
+    }
+
+    /// Translate a vpn through this address space's page table
+    pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
+        self.page_table.translate(vpn)
+    }
+}
+
+/// Why a page fault on a user page could not be resolved
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FaultError {
+    /// The page is not lazy or copy-on-write, or the access is not allowed
+    Genuine,
+    /// No frame is left to back the page
+    OutOfMemory,
+}
+
+/// A contiguous range of virtual pages mapped the same way
+pub struct MapArea {
+    vpn_range: VPNRange,
+    /// Backing frames of a framed area; after a COW fork the trackers in
+    /// both address spaces share the frame through its reference count
+    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    map_type: MapType,
+    map_perm: MapPermission,
+    /// Segment whose frames a shared area maps
+    shm: Option<Arc<ShmSegment>>,
+}
+
+impl MapArea {
+    pub fn new(
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_type: MapType,
+        map_perm: MapPermission,
+    ) -> Self {
+        let start_vpn: VirtPageNum = start_va.floor();
+        let end_vpn: VirtPageNum = end_va.ceil();
+        Self {
+            vpn_range: VPNRange::new(start_vpn, end_vpn),
+            data_frames: BTreeMap::new(),
+            map_type,
+            map_perm,
+            shm: None,
+        }
+    }
+
+    /// Create an empty area with the same range and permissions as `another`
+    pub fn from_another(another: &MapArea) -> Self {
+        Self {
+            vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
+            data_frames: BTreeMap::new(),
+            map_type: another.map_type,
+            map_perm: another.map_perm,
+            shm: another.shm.clone(),
+        }
+    }
+
+    /// Split the area at `vpn`, which must lie inside it: `self` keeps the
+    /// pages below `vpn`, and the returned area takes the rest along with
+    /// their frames. Shared areas cannot be split.
+    pub fn split_off(&mut self, vpn: VirtPageNum) -> MapArea {
+        assert_ne!(self.map_type, MapType::Shared);
+        let (start, end) = (self.vpn_range.get_start(), self.vpn_range.get_end());
+        assert!(start < vpn && vpn < end);
+        self.vpn_range = VPNRange::new(start, vpn);
+        Self {
+            vpn_range: VPNRange::new(vpn, end),
+            data_frames: self.data_frames.split_off(&vpn),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            shm: None,
+        }
+    }
+
+    /// Whether `vpn` lies within this area
+    pub fn contains(&self, vpn: VirtPageNum) -> bool {
+        self.vpn_range.get_start() <= vpn && vpn < self.vpn_range.get_end()
+    }
+
+    fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        let ppn: PhysPageNum = match self.map_type {
+            MapType::Identical => PhysPageNum(vpn.0),
+            MapType::Framed | MapType::Lazy => {
+                let frame = frame_alloc().unwrap();
+                let ppn = frame.ppn;
+                self.data_frames.insert(vpn, frame);
+                ppn
+            }
+            MapType::Shared => {
+                let segment = self.shm.as_ref().unwrap();
+                segment.frames[vpn.0 - self.vpn_range.get_start().0].ppn
+            }
+        };
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits() as u16).unwrap();
+        page_table.map(vpn, ppn, pte_flags);
+    }
+
+    /// Populate one page of a lazy area on its first access.
+    ///
+    /// A store gets a frame of its own right away. A load maps the shared
+    /// zero frame read-only and COW instead, so that a later store takes a
+    /// private copy, even if mprotect makes the area writable only then.
+    /// Fails if no frame is left for the page or a page table node.
+    pub fn map_one_lazy(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+        write: bool,
+    ) -> Result<(), FaultError> {
+        assert_eq!(self.map_type, MapType::Lazy);
+        let mut flags = PTEFlags::from_bits(self.map_perm.bits() as u16).unwrap();
+        let frame = if write {
+            frame_alloc().ok_or(FaultError::OutOfMemory)?
+        } else {
+            flags.remove(PTEFlags::W);
+            flags.insert(PTEFlags::COW);
+            zero_frame()
+        };
+        if !page_table.try_map(vpn, frame.ppn, flags) {
+            return Err(FaultError::OutOfMemory);
+        }
+        self.data_frames.insert(vpn, frame);
+        Ok(())
+    }
+
+    fn map(&mut self, page_table: &mut PageTable) {
+        if self.map_type == MapType::Lazy {
+            return;
+        }
+        for vpn in self.vpn_range {
+            self.map_one(page_table, vpn);
+        }
+    }
+
+    fn unmap(&mut self, page_table: &mut PageTable) {
+        match self.map_type {
+            MapType::Identical | MapType::Shared => {
+                for vpn in self.vpn_range {
+                    page_table.unmap(vpn);
+                }
+            }
+            // Only pages holding a frame are mapped; untouched lazy pages
+            // have nothing to unmap
+            MapType::Framed | MapType::Lazy => {
+                for &vpn in self.data_frames.keys() {
+                    page_table.unmap(vpn);
+                }
+                self.data_frames.clear();
+            }
+        }
+    }
+
+    /// Map the pages in `[end, new_end)`; lazy areas only grow their range
+    fn append_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        if self.map_type != MapType::Lazy {
+            for vpn in VPNRange::new(self.vpn_range.get_end(), new_end) {
+                self.map_one(page_table, vpn);
+            }
+        }
+        self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
+    }
+
+    /// Copy `data` into this area, starting `offset` bytes into its first page;
+    /// the area must be framed
+    fn copy_data(&mut self, page_table: &mut PageTable, offset: usize, data: &[u8]) {
+        assert_eq!(self.map_type, MapType::Framed);
+        assert!(offset < PAGE_SIZE);
+        let mut start: usize = 0;
+        let mut page_offset = offset;
+        let mut current_vpn = self.vpn_range.get_start();
+        let len = data.len();
+        while start < len {
+            let chunk = (PAGE_SIZE - page_offset).min(len - start);
+            let src = &data[start..start + chunk];
+            let dst = &mut page_table
+                .translate(current_vpn)
+                .unwrap()
+                .ppn()
+                .get_bytes_array()[page_offset..page_offset + chunk];
+            dst.copy_from_slice(src);
+            start += chunk;
+            page_offset = 0;
+            current_vpn.step();
+        }
+    }
+}
+
+impl Drop for MapArea {
+    fn drop(&mut self) {
+        if let Some(segment) = self.shm.take() {
+            shm_detached(segment);
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// How the pages of a [`MapArea`] are backed
+pub enum MapType {
+    /// Virtual page number equals physical page number
+    Identical,
+    /// Each page is backed by a freshly allocated frame
+    Framed,
+    /// Like `Framed`, but frames are allocated on the first page fault
+    Lazy,
+    /// Backed by the frames of a shared memory segment, which other address
+    /// spaces may map as well
+    Shared,
+}
+
+bitflags! {
+    /// Access permissions of a [`MapArea`], a subset of [`PTEFlags`]
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    pub struct MapPermission: u8 {
+        const R = 1 << 1;
+        const W = 1 << 2;
+        const X = 1 << 3;
+        const U = 1 << 4;
+    }
+}
+
+/// Check that the kernel mappings behave as expected after activation
+pub fn remap_test() {
+    let kernel_space = KERNEL_SPACE.exclusive_access();
+    let mid_text: VirtAddr = ((stext as usize + etext as usize) / 2).into();
+    let mid_rodata: VirtAddr = ((srodata as usize + erodata as usize) / 2).into();
+    let mid_data: VirtAddr = ((sdata as usize + edata as usize) / 2).into();
+    assert!(!kernel_space.translate(mid_text.floor()).unwrap().writable());
+    assert!(!kernel_space
+        .translate(mid_rodata.floor())
+        .unwrap()
+        .writable());
+    assert!(!kernel_space
+        .translate(mid_data.floor())
+        .unwrap()
+        .executable());
+    println!("remap_test passed!");
+}
+
+/// A range operation as done by munmap, mprotect and madvise
+#[derive(Copy, Clone)]
+enum RangeOp {
+    Remove,
+    Protect(MapPermission),
+    Discard,
+}
+
+/// A [`RangeOp`] on the pages from a first to an end page, and whether it
+/// succeeds
+type RangeStep = (RangeOp, usize, usize, bool);
+
+/// Run sequences of range operations on a framed area of six pages, each on
+/// a fresh address space, and check what became of every page: `w` mapped
+/// writable, `r` read-only, `-` without access, `l` left to a lazy fault and
+/// `.` outside every area. Mapped pages keep their frames, freed frames are
+/// given back, and lazy pages fault back in zeroed.
+pub fn range_ops_test() {
+    let read_write = MapPermission::R | MapPermission::W | MapPermission::U;
+    let read_only = MapPermission::R | MapPermission::U;
+    let page = |page: usize| VirtAddr::from(0x1000_0000 + page * PAGE_SIZE).floor();
+    use RangeOp::*;
+    #[rustfmt::skip]
+    let cases: [(&[RangeStep], &str); 7] = [
+        // Single pages at the start, the end and in the middle
+        (&[(Remove, 0, 1, true), (Remove, 5, 6, true), (Remove, 3, 4, true),
+           (Remove, 3, 4, false)], ".ww.w."),
+        // Across several areas and the holes between them
+        (&[(Remove, 1, 2, true), (Remove, 3, 4, true), (Remove, 0, 6, true)], "......"),
+        // A page outside every area fails the whole call
+        (&[(Protect(read_only), 1, 3, true), (Protect(read_write), 2, 7, false)], "wrrwww"),
+        (&[(Protect(read_only), 1, 3, true), (Protect(read_write), 0, 6, true)], "wwwwww"),
+        (&[(Protect(MapPermission::U), 2, 6, true)], "ww----"),
+        (&[(Discard, 1, 3, true), (Discard, 5, 7, false)], "wllwww"),
+        (&[(Remove, 2, 3, true), (Discard, 1, 4, false), (Discard, 3, 4, true)], "ww.lww"),
+    ];
+    for (ops, expected) in cases {
+        let mut memory_set = MemorySet::new_bare();
+        memory_set.insert_framed_area(page(0).into(), page(6).into(), read_write);
+        let ppns: Vec<_> = (0..6)
+            .map(|i| memory_set.translate(page(i)).unwrap().ppn())
+            .collect();
+        for ppn in ppns.iter() {
+            ppn.get_bytes_array().fill(0xAA);
+        }
+        let used = frames_used();
+        for &(op, start, end, ok) in ops {
+            let result = match op {
+                Remove => memory_set.remove_range(page(start), page(end)),
+                Protect(permission) => memory_set.protect_range(page(start), page(end), permission),
+                Discard => memory_set.discard_range(page(start), page(end)),
+            };
+            assert_eq!(result, ok);
+        }
+        let states: String = (0..6)
+            .map(
+                |i| match memory_set.translate(page(i)).filter(|pte| pte.is_valid()) {
+                    Some(pte) => {
+                        assert_eq!(pte.ppn(), ppns[i]);
+                        if pte.writable() {
+                            'w'
+                        } else if pte.readable() {
+                            'r'
+                        } else {
+                            '-'
+                        }
+                    }
+                    None if memory_set.areas.iter().any(|area| area.contains(page(i))) => 'l',
+                    None => '.',
+                },
+            )
+            .collect();
+        assert_eq!(states, expected);
+        let freed = expected
+            .chars()
+            .filter(|&state| state == 'l' || state == '.');
+        assert_eq!(frames_used(), used - freed.count());
+        for (i, _) in expected.char_indices().filter(|&(_, state)| state == 'l') {
+            assert!(memory_set.handle_lazy_fault(page(i).into(), true).is_ok());
+            let pte = memory_set.translate(page(i)).unwrap();
+            assert!(pte.writable());
+            assert!(pte.ppn().get_bytes_array().iter().all(|&byte| byte == 0));
+        }
+    }
+    // A heap with a hole still shrinks and grows without overlapping areas
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.insert_lazy_area(page(0).into(), page(0).into(), read_write);
+    memory_set.append_to(page(0).into(), page(0).into(), page(4).into());
+    assert!(memory_set.remove_range(page(1), page(2)));
+    assert!(memory_set.shrink_to(page(4).into(), page(2).into()));
+    memory_set.append_to(page(0).into(), page(2).into(), page(3).into());
+    let mut ranges: Vec<_> = memory_set
+        .areas
+        .iter()
+        .map(|area| (area.vpn_range.get_start(), area.vpn_range.get_end()))
+        .collect();
+    ranges.sort_by_key(|&(start, _)| start);
+    assert_eq!(ranges, [(page(0), page(1)), (page(2), page(3))]);
+    assert!(memory_set.shrink_to(page(3).into(), page(0).into()));
+    assert!(!memory_set.overlaps(page(0).into(), page(3).into()));
+    println!("range_ops_test passed!");
+}
+
+/// Check that reading a lazy page maps the zero frame and that the first
+/// store gives the page a zeroed frame of its own
+pub fn demand_zero_test() {
+    let permission = MapPermission::R | MapPermission::W | MapPermission::U;
+    let page_va = |page: usize| VirtAddr::from(0x1000_0000 + page * PAGE_SIZE);
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.insert_lazy_area(page_va(0), page_va(4), permission);
+    // The first fault also allocates the page table nodes
+    assert!(memory_set.handle_lazy_fault(page_va(0), false).is_ok());
+    let used = frames_used();
+    for page in 1..4 {
+        assert!(memory_set.handle_lazy_fault(page_va(page), false).is_ok());
+    }
+    assert_eq!(frames_used(), used);
+    for page in 0..4 {
+        let pte = memory_set.translate(page_va(page).floor()).unwrap();
+        assert!(is_zero_frame(pte.ppn()));
+        assert!(pte.is_cow() && !pte.writable());
+    }
+    assert_eq!(
+        memory_set.handle_lazy_fault(page_va(0), false),
+        Err(FaultError::Genuine)
+    );
+    assert!(memory_set.handle_cow_fault(page_va(0)).is_ok());
+    assert_eq!(frames_used(), used + 1);
+    let pte = memory_set.translate(page_va(0).floor()).unwrap();
+    assert!(!is_zero_frame(pte.ppn()) && pte.writable());
+    assert!(pte.ppn().get_bytes_array().iter().all(|&byte| byte == 0));
+    // A store to an untouched page skips the zero frame
+    memory_set.append_to(page_va(0), page_va(4), page_va(5));
+    assert!(memory_set.handle_lazy_fault(page_va(4), true).is_ok());
+    assert!(memory_set.translate(page_va(4).floor()).unwrap().writable());
+    println!("demand_zero_test passed!");
+}
@@ -2,27 +2,81 @@
 //! Handles physical frame allocation, virtual memory, and heap allocation
 
 mod address;
+mod dma;
 mod frame_allocator;
 mod heap_allocator;
+mod memory_set;
 mod page_table;
+mod shm;
 
-pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
-pub use frame_allocator::{frame_alloc, frame_dealloc, FrameTracker};
+pub use address::{vpn_range_test, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+use address::{StepByOne, VPNRange};
+pub use dma::DmaBuffer;
+pub use frame_allocator::{
+    frame_add_ref, frame_alloc, frame_alloc_contiguous, frame_alloc_contiguous_test,
+    frame_alloc_for_heap, frame_dealloc, frame_ref_count, frames_total, frames_used, is_zero_frame,
+    zero_frame, FrameTracker,
+};
+pub use heap_allocator::{
+    heap_growth_test, heap_peak_test, heap_peak_usage, heap_stats, heap_stats_test,
+};
+pub use memory_set::{
+    demand_zero_test, range_ops_test, remap_test, FaultError, MapPermission, MemorySet,
+    KERNEL_SPACE,
+};
 pub use page_table::{
-    translated_byte_buffer, translated_ref, translated_refmut, translated_str, PTEFlags, PageTable,
-    PageTableEntry,
+    copy_from_user, copy_to_user, flush_tlb, try_translated_byte_buffer, try_translated_ref,
+    try_translated_refmut, try_translated_str, try_translated_str_max, PTEFlags, PageTable,
+    PageTableEntry, TranslateError, UserBuffer,
 };
+pub use shm::{shm_detached, shm_get, shm_remove, shm_segment, shm_test, ShmSegment};
+
+use crate::config::{MEMORY_END, MEMORY_START};
 
-use crate::config::MEMORY_END;
+const _: () = if MEMORY_START >= MEMORY_END {
+    panic!("MEMORY_END must lie above MEMORY_START");
+};
+
+/// Check that the memory layout from `config.rs` and the linker script is
+/// consistent, before anything is placed according to it
+fn validate_layout() {
+    extern "C" {
+        fn skernel();
+        fn edata();
+        fn ekernel();
+    }
+    assert!(
+        skernel as usize >= MEMORY_START,
+        "kernel image at {:#x} starts below MEMORY_START {:#x}",
+        skernel as usize,
+        MEMORY_START
+    );
+    let frame_start: usize = PhysAddr::from(ekernel as usize).ceil().into();
+    assert!(
+        ekernel as usize <= frame_start && frame_start < MEMORY_END,
+        "no frames left: kernel image ends at {:#x}, MEMORY_END is {:#x}",
+        ekernel as usize,
+        MEMORY_END
+    );
+    let (heap_start, heap_end) = heap_allocator::heap_range();
+    assert!(
+        heap_start >= edata as usize && heap_end <= ekernel as usize,
+        "kernel heap [{:#x}, {:#x}) overlaps the kernel code/data or the frame area",
+        heap_start,
+        heap_end
+    );
+}
 
 /// Initialize memory management system
 pub fn init() {
+    validate_layout();
     heap_allocator::init_heap();
     frame_allocator::init_frame_allocator();
+    KERNEL_SPACE.exclusive_access().activate();
     println!("[KERNEL] Memory management initialized");
 }
 
 /// Get physical memory size
 pub fn memory_size() -> usize {
-    MEMORY_END
+    MEMORY_END - MEMORY_START
 }
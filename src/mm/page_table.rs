@@ -1,15 +1,19 @@
 //! SV39 page table implementation for RISC-V
 
-use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+use super::{frame_alloc, FaultError, FrameTracker, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+use crate::config::PAGE_SIZE;
 use crate::mm::address::StepByOne;
+use crate::task::{handle_current_cow_fault, handle_current_lazy_fault};
+use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
+use core::arch::asm;
 
 bitflags! {
     /// Page table entry flags
-    #[derive(PartialEq)]
-    pub struct PTEFlags: u8 {
+    #[derive(Copy, Clone, PartialEq)]
+    pub struct PTEFlags: u16 {
         const V = 1 << 0;  // Valid
         const R = 1 << 1;  // Readable
         const W = 1 << 2;  // Writable
@@ -18,6 +22,7 @@ bitflags! {
         const G = 1 << 5;  // Global
         const A = 1 << 6;  // Accessed
         const D = 1 << 7;  // Dirty
+        const COW = 1 << 8;  // Copy-on-write (RSW bit, ignored by hardware)
     }
 }
 
@@ -44,7 +49,7 @@ impl PageTableEntry {
     }
 
     pub fn flags(&self) -> PTEFlags {
-        PTEFlags::from_bits(self.bits as u8).unwrap()
+        PTEFlags::from_bits((self.bits & 0x3ff) as u16).unwrap()
     }
 
     pub fn is_valid(&self) -> bool {
@@ -62,6 +67,10 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+
+    pub fn is_cow(&self) -> bool {
+        (self.flags() & PTEFlags::COW) != PTEFlags::empty()
+    }
 }
 
 /// Page table for address translation
@@ -70,6 +79,17 @@ pub struct PageTable {
     frames: Vec<FrameTracker>,
 }
 
+/// Drop stale translations of `vpn` from the TLB, in every address space.
+///
+/// Called whenever a leaf entry changes; switching address spaces flushes
+/// the whole TLB instead.
+pub fn flush_tlb(vpn: VirtPageNum) {
+    let va: usize = VirtAddr::from(vpn).into();
+    unsafe {
+        asm!("sfence.vma {}, zero", in(reg) va);
+    }
+}
+
 impl PageTable {
     /// Create a new page table
     pub fn new() -> Self {
@@ -93,7 +113,8 @@ impl PageTable {
         8usize << 60 | self.root_ppn.0
     }
 
-    /// Find page table entry for vpn, create if not exists
+    /// Find page table entry for vpn, create if not exists; `None` if no
+    /// frame is left for a missing page table node
     fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
@@ -105,7 +126,7 @@ impl PageTable {
                 break;
             }
             if !pte.is_valid() {
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc()?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame);
             }
@@ -135,16 +156,52 @@ impl PageTable {
 
     /// Map vpn to ppn with flags
     pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
-        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+        kassert!(
+            self.try_map(vpn, ppn, flags),
+            "no frame left to map vpn {:?}",
+            vpn
+        );
+    }
+
+    /// Like [`PageTable::map`], but fails if no frame is left for a page
+    /// table node, e.g. when resolving a user page fault
+    pub fn try_map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) -> bool {
+        let pte = match self.find_pte_create(vpn) {
+            Some(pte) => pte,
+            None => return false,
+        };
+        kassert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        flush_tlb(vpn);
+        true
+    }
+
+    /// Replace the flags of an already mapped vpn, keeping its ppn
+    pub fn set_flags(&mut self, vpn: VirtPageNum, flags: PTEFlags) {
+        let pte = self.find_pte(vpn).unwrap();
+        kassert!(
+            pte.is_valid(),
+            "vpn {:?} is invalid before setting flags",
+            vpn
+        );
+        *pte = PageTableEntry::new(pte.ppn(), flags | PTEFlags::V);
+        flush_tlb(vpn);
+    }
+
+    /// Point an already mapped vpn at another ppn with new flags
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let pte = self.find_pte(vpn).unwrap();
+        kassert!(pte.is_valid(), "vpn {:?} is invalid before remapping", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        flush_tlb(vpn);
     }
 
     /// Unmap a vpn
     pub fn unmap(&mut self, vpn: VirtPageNum) {
         let pte = self.find_pte(vpn).unwrap();
-        assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
+        kassert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
+        flush_tlb(vpn);
     }
 
     /// Translate vpn to pte
@@ -163,74 +220,292 @@ impl PageTable {
     }
 }
 
-/// Translate a pointer to a mutable reference
-pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
-    let page_table = PageTable::from_token(token);
-    let va = ptr as usize;
-    // SAFETY: The virtual address has been translated through the page table,
-    // ensuring it maps to valid physical memory. The resulting pointer is
-    // guaranteed to be valid for the lifetime 'static as the physical memory
-    // remains allocated throughout the kernel's lifetime.
-    unsafe {
-        (page_table.translate_va(VirtAddr::from(va)).unwrap().0 as *const T)
-            .as_ref()
-            .unwrap()
+/// Why the kernel may not access a user pointer
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TranslateError {
+    /// No page is mapped at the address
+    Unmapped,
+    /// The page is mapped, but not accessible from user mode
+    NotUser,
+    /// The page lacks the R or W permission the access needs
+    PermissionDenied,
+    /// The object does not fit into a single page
+    CrossesPage,
+    /// A string is not valid UTF-8
+    InvalidUtf8,
+    /// A string runs on beyond the length the caller accepts
+    TooLong,
+    /// A lazy or copy-on-write page needs a frame, but none is left
+    OutOfMemory,
+}
+
+/// Look up the physical page behind the user address `va` for a read or,
+/// if `write`, a write.
+///
+/// The kernel accesses user memory on behalf of the current task, so lazy
+/// and copy-on-write pages of its address space are resolved first, exactly
+/// as if the task had touched them itself.
+fn translated_user_page(
+    page_table: &PageTable,
+    va: VirtAddr,
+    write: bool,
+) -> Result<PhysPageNum, TranslateError> {
+    let vpn = va.floor();
+    let present = |page_table: &PageTable| page_table.translate(vpn).filter(|pte| pte.is_valid());
+    let mut pte = match present(page_table) {
+        Some(pte) => pte,
+        None => match handle_current_lazy_fault(va.into(), write) {
+            Ok(()) => present(page_table).unwrap(),
+            Err(FaultError::OutOfMemory) => return Err(TranslateError::OutOfMemory),
+            Err(FaultError::Genuine) => return Err(TranslateError::Unmapped),
+        },
+    };
+    if write && pte.is_cow() {
+        match handle_current_cow_fault(va.into()) {
+            Ok(()) => pte = present(page_table).unwrap(),
+            Err(FaultError::OutOfMemory) => return Err(TranslateError::OutOfMemory),
+            // Caught by the permission check below
+            Err(FaultError::Genuine) => {}
+        }
+    }
+    if !pte.flags().contains(PTEFlags::U) {
+        return Err(TranslateError::NotUser);
+    }
+    if !pte.readable() || (write && !pte.writable()) {
+        return Err(TranslateError::PermissionDenied);
     }
+    Ok(pte.ppn())
 }
 
-/// Translate a pointer to a mutable reference
-pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
+/// Translate a user pointer for a read or, if `write`, a write access,
+/// checking that the whole object lies in a single user page
+fn try_translated_ptr<T>(
+    token: usize,
+    ptr: *const T,
+    write: bool,
+) -> Result<*mut T, TranslateError> {
     let page_table = PageTable::from_token(token);
-    let va = ptr as usize;
-    // SAFETY: The virtual address has been validated through page table translation,
-    // ensuring it points to valid, mapped physical memory. The mutable reference
-    // is safe as we have exclusive access through the page table token.
-    unsafe {
-        (page_table.translate_va(VirtAddr::from(va)).unwrap().0 as *mut T)
-            .as_mut()
-            .unwrap()
+    let va = VirtAddr::from(ptr as usize);
+    if va.page_offset() + core::mem::size_of::<T>() > PAGE_SIZE {
+        return Err(TranslateError::CrossesPage);
     }
+    let ppn = translated_user_page(&page_table, va, write)?;
+    let pa: PhysAddr = ppn.into();
+    Ok((pa.0 + va.page_offset()) as *mut T)
 }
 
-/// Translate a buffer in user space
-pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
-    let page_table = PageTable::from_token(token);
-    let mut start = ptr as usize;
-    let end = start + len;
-    let mut v = Vec::new();
-    while start < end {
-        let start_va = VirtAddr::from(start);
+/// Translate a user pointer to a reference
+pub fn try_translated_ref<T>(token: usize, ptr: *const T) -> Result<&'static T, TranslateError> {
+    // SAFETY: the pointer was checked to lie within a mapped, readable user
+    // page, which the kernel reaches through the identity mapping
+    try_translated_ptr(token, ptr, false).map(|ptr| unsafe { &*ptr })
+}
+
+/// Translate a user pointer to a mutable reference
+pub fn try_translated_refmut<T>(
+    token: usize,
+    ptr: *mut T,
+) -> Result<&'static mut T, TranslateError> {
+    // SAFETY: as for `try_translated_ref`, and the page is writable
+    try_translated_ptr(token, ptr, true).map(|ptr| unsafe { &mut *ptr })
+}
+
+/// The pages of a user buffer as kernel-accessible slices, in order.
+///
+/// Every page is checked to be a user page that is readable or, for a
+/// `write` access, writable; the first one that is not ends the iteration
+/// with an error.
+struct UserPages {
+    page_table: PageTable,
+    start: usize,
+    end: usize,
+    write: bool,
+}
+
+impl UserPages {
+    fn new(token: usize, ptr: *const u8, len: usize, write: bool) -> Result<Self, TranslateError> {
+        let start = ptr as usize;
+        let end = start.checked_add(len).ok_or(TranslateError::Unmapped)?;
+        Ok(Self {
+            page_table: PageTable::from_token(token),
+            start,
+            end,
+            write,
+        })
+    }
+}
+
+impl Iterator for UserPages {
+    type Item = Result<&'static mut [u8], TranslateError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.start >= self.end {
+            return None;
+        }
+        let start_va = VirtAddr::from(self.start);
         let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
+        let ppn = match translated_user_page(&self.page_table, start_va, self.write) {
+            Ok(ppn) => ppn,
+            Err(error) => {
+                self.start = self.end;
+                return Some(Err(error));
+            }
+        };
         vpn.step();
-        let mut end_va: VirtAddr = vpn.into();
-        end_va = end_va.min(VirtAddr::from(end));
-        if end_va.page_offset() == 0 {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..]);
+        let end_va = VirtAddr::from(vpn).min(VirtAddr::from(self.end));
+        let bytes = if end_va.page_offset() == 0 {
+            &mut ppn.get_bytes_array()[start_va.page_offset()..]
         } else {
-            v.push(&mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]);
-        }
-        start = end_va.into();
+            &mut ppn.get_bytes_array()[start_va.page_offset()..end_va.page_offset()]
+        };
+        self.start = end_va.into();
+        Some(Ok(bytes))
+    }
+}
+
+/// Translate a user buffer into kernel-accessible slices split at page
+/// boundaries, checking that every page is a user page that is readable
+/// or, if the kernel is going to `write` the buffer, writable
+pub fn try_translated_byte_buffer(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+    write: bool,
+) -> Result<Vec<&'static mut [u8]>, TranslateError> {
+    UserPages::new(token, ptr, len, write)?.collect()
+}
+
+/// A copy between kernel and user memory that a fault cut short
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CopyFault {
+    /// Number of bytes transferred before the fault
+    pub copied: usize,
+    pub error: TranslateError,
+}
+
+/// Copy `data` to the writable user buffer at `ptr`, page by page,
+/// returning the number of bytes copied, i.e. `data.len()`.
+///
+/// A page that is not a writable user page stops the copy; the bytes in
+/// the pages before it stay copied.
+pub fn copy_to_user(token: usize, ptr: *mut u8, data: &[u8]) -> Result<usize, CopyFault> {
+    let mut copied = 0;
+    let pages = UserPages::new(token, ptr, data.len(), true)
+        .map_err(|error| CopyFault { copied, error })?;
+    for buffer in pages {
+        let buffer = buffer.map_err(|error| CopyFault { copied, error })?;
+        buffer.copy_from_slice(&data[copied..copied + buffer.len()]);
+        copied += buffer.len();
+    }
+    Ok(copied)
+}
+
+/// Copy `len` bytes from the readable user buffer at `ptr`, page by page.
+///
+/// A page that is not a readable user page stops the copy with the number
+/// of bytes read before it.
+pub fn copy_from_user(token: usize, ptr: *const u8, len: usize) -> Result<Vec<u8>, CopyFault> {
+    // Grow with the pages actually read, so that a huge `len` from user code
+    // cannot make the kernel allocate more than the user has mapped
+    let mut data = Vec::new();
+    let pages =
+        UserPages::new(token, ptr, len, false).map_err(|error| CopyFault { copied: 0, error })?;
+    for buffer in pages {
+        let buffer = buffer.map_err(|error| CopyFault {
+            copied: data.len(),
+            error,
+        })?;
+        data.extend_from_slice(buffer);
     }
-    v
+    Ok(data)
 }
 
-/// Translate a string from user space
-pub fn translated_str(token: usize, ptr: *const u8) -> alloc::string::String {
+/// Translate a NUL-terminated string from user space
+pub fn try_translated_str(token: usize, ptr: *const u8) -> Result<String, TranslateError> {
+    try_translated_str_max(token, ptr, usize::MAX)
+}
+
+/// Translate a NUL-terminated string of at most `max_len` bytes, not
+/// counting the NUL, from user space
+pub fn try_translated_str_max(
+    token: usize,
+    ptr: *const u8,
+    max_len: usize,
+) -> Result<String, TranslateError> {
     let page_table = PageTable::from_token(token);
     let mut string = Vec::new();
     let mut va = ptr as usize;
     loop {
-        // SAFETY: Each virtual address is translated through the page table before
-        // dereferencing, ensuring it points to valid mapped memory. The loop terminates
-        // on null byte, preventing out-of-bounds access.
-        let ch: u8 =
-            unsafe { *(page_table.translate_va(VirtAddr::from(va)).unwrap().0 as *const u8) };
+        // Every byte is looked up on its own, so a string running off the
+        // end of mapped memory fails on the first unmapped byte
+        let ppn = translated_user_page(&page_table, VirtAddr::from(va), false)?;
+        let ch = ppn.get_bytes_array()[VirtAddr::from(va).page_offset()];
         if ch == 0 {
             break;
         }
+        if string.len() == max_len {
+            return Err(TranslateError::TooLong);
+        }
         string.push(ch);
         va += 1;
     }
-    alloc::string::String::from_utf8(string).unwrap()
+    String::from_utf8(string).map_err(|_| TranslateError::InvalidUtf8)
+}
+
+/// A user buffer split at page boundaries into kernel-accessible slices
+pub struct UserBuffer {
+    pub buffers: Vec<&'static mut [u8]>,
+}
+
+impl UserBuffer {
+    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
+        Self { buffers }
+    }
+
+    /// Total length of the buffer in bytes
+    pub fn len(&self) -> usize {
+        self.buffers.iter().map(|buffer| buffer.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl IntoIterator for UserBuffer {
+    type Item = *mut u8;
+    type IntoIter = UserBufferIterator;
+
+    fn into_iter(self) -> Self::IntoIter {
+        UserBufferIterator {
+            buffers: self.buffers,
+            current_buffer: 0,
+            current_idx: 0,
+        }
+    }
+}
+
+/// Iterator over the bytes of a [`UserBuffer`]
+pub struct UserBufferIterator {
+    buffers: Vec<&'static mut [u8]>,
+    current_buffer: usize,
+    current_idx: usize,
+}
+
+impl Iterator for UserBufferIterator {
+    type Item = *mut u8;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.current_buffer < self.buffers.len() {
+            let buffer = &mut self.buffers[self.current_buffer];
+            if self.current_idx < buffer.len() {
+                let r = &mut buffer[self.current_idx] as *mut u8;
+                self.current_idx += 1;
+                return Some(r);
+            }
+            self.current_idx = 0;
+            self.current_buffer += 1;
+        }
+        None
+    }
 }
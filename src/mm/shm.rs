@@ -0,0 +1,145 @@
+//! System V style shared memory segments
+//!
+//! A segment is a set of frames that [`MemorySet::insert_shared_area`] maps
+//! into every address space attaching it, so all of them access the same
+//! physical pages. Segments are found by a key through a global table, which
+//! keeps each segment until its last attachment is detached, be it by
+//! `shmdt` or by the address space going away; a segment nobody attached yet
+//! stays as well. [`shm_remove`] takes a segment out of the table right away,
+//! and its frames go once nobody has it attached anymore.
+//!
+//! [`MemorySet::insert_shared_area`]: super::MemorySet::insert_shared_area
+
+use super::{frame_alloc, FrameTracker};
+use crate::config::PAGE_SIZE;
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// Key that always creates a new segment
+pub const IPC_PRIVATE: usize = 0;
+
+/// Frames of a shared memory segment, zeroed at creation
+pub struct ShmSegment {
+    pub frames: Vec<FrameTracker>,
+}
+
+impl ShmSegment {
+    /// Size of the segment in bytes
+    pub fn size(&self) -> usize {
+        self.frames.len() * PAGE_SIZE
+    }
+}
+
+struct ShmTable {
+    /// Segments by id; each attachment holds another reference
+    segments: BTreeMap<usize, (usize, Arc<ShmSegment>)>,
+    next_id: usize,
+}
+
+lazy_static! {
+    static ref SHM_TABLE: UPSafeCell<ShmTable> = unsafe {
+        UPSafeCell::new(ShmTable {
+            segments: BTreeMap::new(),
+            next_id: 0,
+        })
+    };
+}
+
+/// Get the id of the segment with `key`, creating one of `size` bytes,
+/// rounded up to whole pages, if there is none or `key` is [`IPC_PRIVATE`].
+///
+/// Fails if an existing segment is smaller than `size`, or for a new one if
+/// `size` is 0 or memory runs out.
+pub fn shm_get(key: usize, size: usize) -> Option<usize> {
+    let mut table = SHM_TABLE.exclusive_access();
+    if key != IPC_PRIVATE {
+        if let Some((&id, (_, segment))) = table
+            .segments
+            .iter()
+            .find(|(_, (segment_key, _))| *segment_key == key)
+        {
+            return (size <= segment.size()).then_some(id);
+        }
+    }
+    if size == 0 {
+        return None;
+    }
+    let frames = (0..size.div_ceil(PAGE_SIZE))
+        .map(|_| frame_alloc())
+        .collect::<Option<Vec<_>>>()?;
+    let id = table.next_id;
+    table.next_id += 1;
+    table
+        .segments
+        .insert(id, (key, Arc::new(ShmSegment { frames })));
+    Some(id)
+}
+
+/// Get the segment `id` to attach it
+pub fn shm_segment(id: usize) -> Option<Arc<ShmSegment>> {
+    SHM_TABLE
+        .exclusive_access()
+        .segments
+        .get(&id)
+        .map(|(_, segment)| segment.clone())
+}
+
+/// Drop `segment`, which was just detached, from the table once no address
+/// space has it attached anymore; its frames are freed with it
+pub fn shm_detached(segment: Arc<ShmSegment>) {
+    let mut table = SHM_TABLE.exclusive_access();
+    // The table holds one reference and `segment` another
+    if Arc::strong_count(&segment) == 2 {
+        table
+            .segments
+            .retain(|_, (_, other)| !Arc::ptr_eq(other, &segment));
+    }
+}
+
+/// Take the segment `id` out of the table, so that no one can attach it
+/// anymore; its frames are freed once it is detached everywhere. Fails if
+/// there is no such segment.
+pub fn shm_remove(id: usize) -> bool {
+    SHM_TABLE.exclusive_access().segments.remove(&id).is_some()
+}
+
+/// Check that two address spaces attaching a segment share its frames
+pub fn shm_test() {
+    use super::{MapPermission, MemorySet, VirtAddr};
+    let id = shm_get(IPC_PRIVATE, PAGE_SIZE + 1).unwrap();
+    let segment = shm_segment(id).unwrap();
+    assert_eq!(segment.size(), 2 * PAGE_SIZE);
+    let permission = MapPermission::R | MapPermission::W | MapPermission::U;
+    let mut a = MemorySet::new_bare();
+    let mut b = MemorySet::new_bare();
+    a.insert_shared_area(VirtAddr::from(0x1000_0000), segment.clone(), permission);
+    b.insert_shared_area(VirtAddr::from(0x2000_0000), segment.clone(), permission);
+    for page in 0..2 {
+        let va_a = VirtAddr::from(0x1000_0000 + page * PAGE_SIZE);
+        let va_b = VirtAddr::from(0x2000_0000 + page * PAGE_SIZE);
+        assert_eq!(
+            a.translate(va_a.floor()).unwrap().ppn(),
+            b.translate(va_b.floor()).unwrap().ppn()
+        );
+    }
+    drop(segment);
+    assert!(a.remove_shared_area(VirtAddr::from(0x1000_0000)));
+    assert!(shm_segment(id).is_some());
+    // Dropping the address space detaches the segment as well
+    drop(b);
+    assert!(shm_segment(id).is_none());
+    // A removed segment can no longer be attached, but stays mapped
+    let id = shm_get(IPC_PRIVATE, PAGE_SIZE).unwrap();
+    a.insert_shared_area(
+        VirtAddr::from(0x1000_0000),
+        shm_segment(id).unwrap(),
+        permission,
+    );
+    assert!(shm_remove(id));
+    assert!(shm_segment(id).is_none());
+    assert!(a.translate(VirtAddr::from(0x1000_0000).floor()).is_some());
+    println!("shm_test passed!");
+}
@@ -0,0 +1,47 @@
+//! Per-hart data
+//!
+//! Each hart keeps its id in `tp`: `_start` sets it from the id the SBI
+//! passes in a0, and traps from user mode reload it from the trap context,
+//! since user code may use `tp` for itself.
+
+use crate::config::MAX_HARTS;
+use core::arch::asm;
+
+/// Get the id of the hart this code runs on
+pub fn hart_id() -> usize {
+    let hart_id;
+    unsafe {
+        asm!("mv {}, tp", out(reg) hart_id);
+    }
+    hart_id
+}
+
+/// One `T` for each hart, up to `MAX_HARTS` of them
+pub struct PerCpu<T> {
+    slots: [T; MAX_HARTS],
+}
+
+impl<T> PerCpu<T> {
+    /// Create the slots, building the one of hart `i` with `init(i)`
+    pub fn new(init: impl FnMut(usize) -> T) -> Self {
+        Self {
+            slots: core::array::from_fn(init),
+        }
+    }
+
+    /// Get the slot of hart `hart_id`
+    pub fn get(&self, hart_id: usize) -> &T {
+        assert!(
+            hart_id < MAX_HARTS,
+            "hart {} is beyond MAX_HARTS {}",
+            hart_id,
+            MAX_HARTS
+        );
+        &self.slots[hart_id]
+    }
+
+    /// Get the slot of the current hart
+    pub fn current(&self) -> &T {
+        self.get(hart_id())
+    }
+}
@@ -0,0 +1,100 @@
+//! Platform-Level Interrupt Controller
+//!
+//! Devices raise external interrupts through the PLIC. The trap handler
+//! claims the pending IRQ, runs the handler registered for it with
+//! [`register_irq_handler`] and signals completion back to the PLIC.
+
+use crate::config::{PLIC_BASE, PLIC_MAX_IRQ};
+use crate::drivers::mmio::{read_volatile, write_volatile};
+use crate::sync::SpinLock;
+use riscv::register::sie;
+
+/// S-mode context of hart 0, the only hart the kernel runs on
+const PLIC_CONTEXT: usize = 1;
+
+/// Handler of each IRQ, indexed by IRQ number
+type IrqHandlers = [Option<fn()>; PLIC_MAX_IRQ + 1];
+
+static IRQ_HANDLERS: SpinLock<IrqHandlers> = SpinLock::new([None; PLIC_MAX_IRQ + 1]);
+
+fn priority_reg(irq: usize) -> usize {
+    PLIC_BASE + irq * 4
+}
+
+fn enable_reg(irq: usize) -> usize {
+    PLIC_BASE + 0x2000 + PLIC_CONTEXT * 0x80 + irq / 32 * 4
+}
+
+fn threshold_reg() -> usize {
+    PLIC_BASE + 0x20_0000 + PLIC_CONTEXT * 0x1000
+}
+
+fn claim_reg() -> usize {
+    PLIC_BASE + 0x20_0004 + PLIC_CONTEXT * 0x1000
+}
+
+/// Read the register `addr`, one of those above
+fn read_reg(addr: usize) -> u32 {
+    // The registers above are 32-bit and lie in the PLIC's MMIO region
+    unsafe { read_volatile(addr) }
+}
+
+/// Write `value` to the register `addr`, one of those above
+fn write_reg(addr: usize, value: u32) {
+    // The PLIC does no DMA
+    unsafe { write_volatile(addr, value) }
+}
+
+/// Initialize the PLIC with every IRQ masked and accept external interrupts
+pub fn init() {
+    for irq in 1..=PLIC_MAX_IRQ {
+        write_reg(priority_reg(irq), 0);
+    }
+    for word in 0..=PLIC_MAX_IRQ / 32 {
+        write_reg(enable_reg(word * 32), 0);
+    }
+    // Let through every IRQ with a non-zero priority
+    write_reg(threshold_reg(), 0);
+    unsafe {
+        sie::set_sext();
+    }
+    info!("PLIC initialized at {:#x}", PLIC_BASE);
+}
+
+/// Route `irq` to the S-mode context and run `handler` whenever it fires
+#[allow(unused)]
+pub fn register_irq_handler(irq: usize, handler: fn()) {
+    assert!(irq != 0 && irq <= PLIC_MAX_IRQ, "invalid PLIC IRQ {}", irq);
+    IRQ_HANDLERS.lock()[irq] = Some(handler);
+    write_reg(priority_reg(irq), 1);
+    let enable = enable_reg(irq);
+    write_reg(enable, read_reg(enable) | 1 << (irq % 32));
+}
+
+/// Claim the highest-priority pending IRQ, or 0 if none is pending
+pub fn plic_claim() -> usize {
+    read_reg(claim_reg()) as usize
+}
+
+/// Tell the PLIC that `irq`, obtained from [`plic_claim`], has been handled
+pub fn plic_complete(irq: usize) {
+    write_reg(claim_reg(), irq as u32);
+}
+
+/// Handle a supervisor external interrupt
+pub fn handle_external_interrupt() {
+    let irq = plic_claim();
+    if irq == 0 {
+        // Another context claimed it first
+        return;
+    }
+    // Copy the handler out, so that it may register handlers itself
+    let handler = IRQ_HANDLERS.lock()[irq];
+    match handler {
+        Some(handler) => handler(),
+        None => {
+            warn!("Unexpected external interrupt {}", irq);
+        }
+    }
+    plic_complete(irq);
+}
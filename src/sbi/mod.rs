@@ -9,7 +9,36 @@ const SBI_CONSOLE_PUTCHAR: usize = 1;
 const SBI_CONSOLE_GETCHAR: usize = 2;
 
 // SBI extension IDs (for new SBI v0.2+ interface)
+const SBI_EXT_TIME: usize = 0x54494D45; // Timer Extension
 const SBI_EXT_SRST: usize = 0x53525354; // System Reset Extension
+const SBI_EXT_DBCN: usize = 0x4442434E; // Debug Console Extension
+const SBI_EXT_HSM: usize = 0x48534D; // Hart State Management Extension
+const SBI_EXT_IPI: usize = 0x735049; // IPI Extension ("sPI")
+const SBI_EXT_RFENCE: usize = 0x52464E43; // Remote Fence Extension
+
+// TIME function IDs
+const SBI_TIME_SET_TIMER: usize = 0;
+
+// DBCN function IDs
+const SBI_DBCN_CONSOLE_WRITE: usize = 0;
+
+// HSM function IDs
+const SBI_HSM_HART_START: usize = 0;
+const SBI_HSM_HART_STOP: usize = 1;
+const SBI_HSM_HART_GET_STATUS: usize = 2;
+
+// IPI function IDs
+const SBI_IPI_SEND_IPI: usize = 0;
+
+// RFENCE function IDs
+const SBI_RFENCE_REMOTE_SFENCE_VMA: usize = 1;
+
+// HSM hart states, as returned by hart_get_status
+pub const HART_STATE_STARTED: usize = 0;
+pub const HART_STATE_STOPPED: usize = 1;
+pub const HART_STATE_START_PENDING: usize = 2;
+pub const HART_STATE_STOP_PENDING: usize = 3;
+pub const HART_STATE_SUSPENDED: usize = 4;
 
 // SRST reset types
 const SBI_SRST_RESET_TYPE_SHUTDOWN: usize = 0;
@@ -20,6 +49,48 @@ const SBI_SRST_RESET_TYPE_WARM_REBOOT: usize = 2;
 const SBI_SRST_RESET_REASON_NONE: usize = 0;
 const SBI_SRST_RESET_REASON_SYSTEM_FAILURE: usize = 1;
 
+/// Standard SBI error codes, as returned in `a0` by v0.2+ calls
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SbiError {
+    /// SBI_ERR_FAILED (-1)
+    Failed,
+    /// SBI_ERR_NOT_SUPPORTED (-2)
+    NotSupported,
+    /// SBI_ERR_INVALID_PARAM (-3)
+    InvalidParam,
+    /// SBI_ERR_DENIED (-4)
+    Denied,
+    /// SBI_ERR_INVALID_ADDRESS (-5)
+    InvalidAddress,
+    /// SBI_ERR_ALREADY_AVAILABLE (-6)
+    AlreadyAvailable,
+    /// SBI_ERR_ALREADY_STARTED (-7)
+    AlreadyStarted,
+    /// SBI_ERR_ALREADY_STOPPED (-8)
+    AlreadyStopped,
+    /// SBI_ERR_NO_SHMEM (-9)
+    NoShmem,
+    /// A code this kernel does not know about
+    Unknown(isize),
+}
+
+impl From<isize> for SbiError {
+    fn from(code: isize) -> Self {
+        match code {
+            -1 => SbiError::Failed,
+            -2 => SbiError::NotSupported,
+            -3 => SbiError::InvalidParam,
+            -4 => SbiError::Denied,
+            -5 => SbiError::InvalidAddress,
+            -6 => SbiError::AlreadyAvailable,
+            -7 => SbiError::AlreadyStarted,
+            -8 => SbiError::AlreadyStopped,
+            -9 => SbiError::NoShmem,
+            code => SbiError::Unknown(code),
+        }
+    }
+}
+
 /// Legacy SBI call (for extensions 0-8)
 #[inline(always)]
 fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
@@ -38,7 +109,14 @@ fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
 
 /// New SBI v0.2+ call with extension ID and function ID
 #[inline(always)]
-fn sbi_call_ext(ext: usize, fid: usize, arg0: usize, arg1: usize) -> (usize, usize) {
+fn sbi_call_ext(
+    ext: usize,
+    fid: usize,
+    arg0: usize,
+    arg1: usize,
+    arg2: usize,
+    arg3: usize,
+) -> (usize, usize) {
     let error;
     let value;
     unsafe {
@@ -46,6 +124,8 @@ fn sbi_call_ext(ext: usize, fid: usize, arg0: usize, arg1: usize) -> (usize, usi
             "ecall",
             inlateout("x10") arg0 => error,
             inlateout("x11") arg1 => value,
+            in("x12") arg2,
+            in("x13") arg3,
             in("x16") fid,
             in("x17") ext,
         );
@@ -53,30 +133,171 @@ fn sbi_call_ext(ext: usize, fid: usize, arg0: usize, arg1: usize) -> (usize, usi
     (error, value)
 }
 
+/// Turn the `(error, value)` pair of an SBI call into a `Result`;
+/// SBI_SUCCESS (0) yields the value
+fn sbi_result((error, value): (usize, usize)) -> Result<usize, SbiError> {
+    if error == 0 {
+        Ok(value)
+    } else {
+        Err(SbiError::from(error as isize))
+    }
+}
+
 /// Print a character to console
 pub fn console_putchar(c: usize) {
     sbi_call(SBI_CONSOLE_PUTCHAR, c, 0, 0);
 }
 
+/// Write `bytes` to the console in as few SBI calls as possible.
+///
+/// Uses the DBCN extension when the firmware provides it and falls back to
+/// one legacy putchar per byte otherwise. `bytes` must be identity mapped,
+/// since DBCN takes a physical address.
+pub fn console_write(bytes: &[u8]) {
+    let mut written = 0;
+    while written < bytes.len() {
+        let rest = &bytes[written..];
+        match sbi_result(sbi_call_ext(
+            SBI_EXT_DBCN,
+            SBI_DBCN_CONSOLE_WRITE,
+            rest.len(),
+            rest.as_ptr() as usize,
+            0,
+            0,
+        )) {
+            Ok(count) => written += count,
+            Err(_) => {
+                for &b in rest {
+                    console_putchar(b as usize);
+                }
+                return;
+            }
+        }
+    }
+}
+
 /// Get a character from console
 pub fn console_getchar() -> usize {
     sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
 }
 
-/// Set timer for next interrupt
-pub fn set_timer(timer: usize) {
-    sbi_call(SBI_SET_TIMER, timer, 0, 0);
+/// Set timer for next interrupt, through the legacy call if the firmware
+/// lacks the TIME extension
+pub fn set_timer(timer: usize) -> Result<(), SbiError> {
+    match sbi_result(sbi_call_ext(
+        SBI_EXT_TIME,
+        SBI_TIME_SET_TIMER,
+        timer,
+        0,
+        0,
+        0,
+    )) {
+        Ok(_) => Ok(()),
+        Err(SbiError::NotSupported) => {
+            sbi_call(SBI_SET_TIMER, timer, 0, 0);
+            Ok(())
+        }
+        Err(error) => Err(error),
+    }
 }
 
-/// Shutdown the system using SRST extension (SBI v0.2+)
-pub fn shutdown() -> ! {
-    // Use SRST extension for system reset/shutdown
-    sbi_call_ext(
+/// Start `hartid` in S-mode at physical address `start_addr`, with `a0 = hartid`
+/// and `a1 = opaque`
+pub fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> Result<(), SbiError> {
+    sbi_result(sbi_call_ext(
+        SBI_EXT_HSM,
+        SBI_HSM_HART_START,
+        hartid,
+        start_addr,
+        opaque,
+        0,
+    ))
+    .map(|_| ())
+}
+
+/// Stop the calling hart; only returns on failure
+pub fn hart_stop() -> SbiError {
+    match sbi_result(sbi_call_ext(SBI_EXT_HSM, SBI_HSM_HART_STOP, 0, 0, 0, 0)) {
+        Ok(_) => SbiError::Failed,
+        Err(error) => error,
+    }
+}
+
+/// Get the `HART_STATE_*` of `hartid`
+pub fn hart_get_status(hartid: usize) -> Result<usize, SbiError> {
+    sbi_result(sbi_call_ext(
+        SBI_EXT_HSM,
+        SBI_HSM_HART_GET_STATUS,
+        hartid,
+        0,
+        0,
+        0,
+    ))
+}
+
+/// Raise a supervisor software interrupt on every hart selected by
+/// `hart_mask`, whose bit 0 stands for hart `hart_mask_base`
+pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> Result<usize, SbiError> {
+    sbi_result(sbi_call_ext(
+        SBI_EXT_IPI,
+        SBI_IPI_SEND_IPI,
+        hart_mask,
+        hart_mask_base,
+        0,
+        0,
+    ))
+}
+
+/// Execute `sfence.vma` for `[start_addr, start_addr + size)` on the harts
+/// selected by `hart_mask` and `hart_mask_base`
+pub fn remote_sfence_vma(
+    hart_mask: usize,
+    hart_mask_base: usize,
+    start_addr: usize,
+    size: usize,
+) -> Result<usize, SbiError> {
+    sbi_result(sbi_call_ext(
+        SBI_EXT_RFENCE,
+        SBI_RFENCE_REMOTE_SFENCE_VMA,
+        hart_mask,
+        hart_mask_base,
+        start_addr,
+        size,
+    ))
+}
+
+/// Send an IPI to this hart and check that it shows up as a pending
+/// supervisor software interrupt. `sie.SSIE` stays clear, so no trap is taken.
+pub fn ipi_test() {
+    // A mask of just bit 0, based at this hart's id, targets this hart
+    match send_ipi(1, crate::percpu::hart_id()) {
+        Ok(_) => {
+            assert!(riscv::register::sip::read().ssoft());
+            // Clear the pending SSIP again
+            unsafe {
+                core::arch::asm!("csrc sip, {}", in(reg) 1 << 1);
+            }
+            println!("ipi_test passed!");
+        }
+        Err(SbiError::NotSupported) => {
+            println!("ipi_test skipped: no IPI extension");
+        }
+        Err(error) => panic!("send_ipi failed: {:?}", error),
+    }
+}
+
+/// Reset the system using SRST extension (SBI v0.2+)
+fn system_reset(reset_type: usize, reason: usize) -> ! {
+    if let Err(error) = sbi_result(sbi_call_ext(
         SBI_EXT_SRST,
         0, // function ID 0 = sbi_system_reset
-        SBI_SRST_RESET_TYPE_SHUTDOWN,
-        SBI_SRST_RESET_REASON_NONE,
-    );
+        reset_type,
+        reason,
+        0,
+        0,
+    )) {
+        error!("SRST reset type {} failed: {:?}", reset_type, error);
+    }
     // If SRST fails, loop forever
     loop {
         unsafe {
@@ -84,3 +305,18 @@ pub fn shutdown() -> ! {
         }
     }
 }
+
+/// Shutdown the system using SRST extension (SBI v0.2+)
+pub fn shutdown() -> ! {
+    system_reset(SBI_SRST_RESET_TYPE_SHUTDOWN, SBI_SRST_RESET_REASON_NONE)
+}
+
+/// Power-cycle the system using SRST extension
+pub fn reboot_cold() -> ! {
+    system_reset(SBI_SRST_RESET_TYPE_COLD_REBOOT, SBI_SRST_RESET_REASON_NONE)
+}
+
+/// Restart the harts using SRST extension, without power-cycling the system
+pub fn reboot_warm() -> ! {
+    system_reset(SBI_SRST_RESET_TYPE_WARM_REBOOT, SBI_SRST_RESET_REASON_NONE)
+}
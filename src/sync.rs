@@ -1,24 +0,0 @@
-//! Synchronization primitives
-
-use core::cell::{RefCell, RefMut};
-
-/// Wrapper around RefCell for uniprocessor systems
-pub struct UPSafeCell<T> {
-    inner: RefCell<T>,
-}
-
-unsafe impl<T> Sync for UPSafeCell<T> {}
-
-impl<T> UPSafeCell<T> {
-    /// Create a new UPSafeCell
-    pub unsafe fn new(value: T) -> Self {
-        Self {
-            inner: RefCell::new(value),
-        }
-    }
-
-    /// Get exclusive access to inner value
-    pub fn exclusive_access(&self) -> RefMut<'_, T> {
-        self.inner.borrow_mut()
-    }
-}
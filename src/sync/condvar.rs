@@ -0,0 +1,48 @@
+//! Condition variables for user threads
+
+use super::UPSafeCell;
+use crate::task::{block_current_and_run_next, wakeup_task, with_current_task};
+use alloc::collections::VecDeque;
+
+/// Condition variable user threads wait on through `sys_condvar_*`
+pub struct Condvar {
+    inner: UPSafeCell<CondvarInner>,
+}
+
+struct CondvarInner {
+    /// Tids of the waiting threads, oldest first
+    wait_queue: VecDeque<usize>,
+}
+
+impl Condvar {
+    pub fn new() -> Self {
+        Self {
+            inner: unsafe {
+                UPSafeCell::new(CondvarInner {
+                    wait_queue: VecDeque::new(),
+                })
+            },
+        }
+    }
+
+    /// Wake the oldest waiting thread, if any
+    pub fn signal(&self) {
+        if let Some(tid) = self.inner.exclusive_access().wait_queue.pop_front() {
+            wakeup_task(tid);
+        }
+    }
+
+    /// Block until signalled. The caller releases the mutex it waits with
+    /// first and takes it again afterwards.
+    ///
+    /// The kernel is not preempted between unlocking and blocking, so a
+    /// signal sent after the mutex is released always finds the thread in
+    /// the wait queue.
+    pub fn wait(&self) {
+        self.inner
+            .exclusive_access()
+            .wait_queue
+            .push_back(with_current_task(|task| task.tid.0));
+        block_current_and_run_next();
+    }
+}
@@ -0,0 +1,144 @@
+//! Deadlock detection for the mutexes and semaphores of a process
+//!
+//! Threads are identified by their slot in the process. Before a thread
+//! waits for a resource, the request is checked with the safety test of the
+//! banker's algorithm: it may only wait if all threads could still finish in
+//! some order, each one getting what it waits for from the units the threads
+//! before it gave back.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+
+/// A lock of a process, by id
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Resource {
+    Mutex(usize),
+    Semaphore(usize),
+}
+
+/// Available, Allocation and Need of the banker's algorithm, kept whether
+/// or not detection is enabled
+#[derive(Default)]
+pub struct DeadlockDetector {
+    /// Whether requests that could deadlock are refused
+    pub enabled: bool,
+    /// Units of each resource no thread holds
+    available: BTreeMap<Resource, usize>,
+    /// Units held, by (thread slot, resource)
+    allocation: BTreeMap<(usize, Resource), usize>,
+    /// Units waited for, by (thread slot, resource)
+    need: BTreeMap<(usize, Resource), usize>,
+}
+
+impl DeadlockDetector {
+    /// Start tracking a new resource with `units` free units
+    pub fn add_resource(&mut self, resource: Resource, units: usize) {
+        self.available.insert(resource, units);
+    }
+
+    /// Record that the thread in `slot` waits for a unit of `resource`.
+    ///
+    /// With detection enabled, a request that leaves no safe order is not
+    /// recorded and `false` is returned instead.
+    pub fn request(&mut self, slot: usize, resource: Resource) -> bool {
+        *self.need.entry((slot, resource)).or_insert(0) += 1;
+        if self.enabled && !self.is_safe() {
+            self.take_need(slot, resource);
+            return false;
+        }
+        true
+    }
+
+    /// Record that the thread in `slot` got the unit of `resource` it
+    /// requested. A unit handed over by a release only counts as held once
+    /// the woken thread runs and calls this.
+    pub fn acquire(&mut self, slot: usize, resource: Resource) {
+        self.take_need(slot, resource);
+        let available = self.available.entry(resource).or_insert(0);
+        *available = available.saturating_sub(1);
+        *self.allocation.entry((slot, resource)).or_insert(0) += 1;
+    }
+
+    /// Record that the thread in `slot` gave back a unit of `resource`.
+    /// Semaphores may be released by threads that never took them.
+    pub fn release(&mut self, slot: usize, resource: Resource) {
+        if let Some(held) = self.allocation.get_mut(&(slot, resource)) {
+            *held -= 1;
+            if *held == 0 {
+                self.allocation.remove(&(slot, resource));
+            }
+        }
+        *self.available.entry(resource).or_insert(0) += 1;
+    }
+
+    /// Forget the thread in `slot` after it exited. What it held stays
+    /// taken, just as the locks themselves stay locked.
+    pub fn remove_thread(&mut self, slot: usize) {
+        self.allocation.retain(|&(s, _), _| s != slot);
+        self.need.retain(|&(s, _), _| s != slot);
+    }
+
+    fn take_need(&mut self, slot: usize, resource: Resource) {
+        if let Some(need) = self.need.get_mut(&(slot, resource)) {
+            *need -= 1;
+            if *need == 0 {
+                self.need.remove(&(slot, resource));
+            }
+        }
+    }
+
+    /// Whether all threads can finish in some order
+    fn is_safe(&self) -> bool {
+        let mut work = self.available.clone();
+        let mut unfinished: BTreeSet<usize> = self
+            .allocation
+            .keys()
+            .chain(self.need.keys())
+            .map(|&(slot, _)| slot)
+            .collect();
+        loop {
+            let runnable = unfinished.iter().copied().find(|&slot| {
+                self.need
+                    .range((slot, Resource::Mutex(0))..)
+                    .take_while(|(&(s, _), _)| s == slot)
+                    .all(|(&(_, resource), &need)| {
+                        work.get(&resource).copied().unwrap_or(0) >= need
+                    })
+            });
+            let slot = match runnable {
+                Some(slot) => slot,
+                None => return unfinished.is_empty(),
+            };
+            unfinished.remove(&slot);
+            for (&(_, resource), &held) in self
+                .allocation
+                .range((slot, Resource::Mutex(0))..)
+                .take_while(|(&(s, _), _)| s == slot)
+            {
+                *work.entry(resource).or_insert(0) += held;
+            }
+        }
+    }
+}
+
+/// Refuse the request that closes a cycle of two threads and two mutexes
+pub fn deadlock_detector_test() {
+    let mut detector = DeadlockDetector {
+        enabled: true,
+        ..Default::default()
+    };
+    let (a, b) = (Resource::Mutex(0), Resource::Mutex(1));
+    detector.add_resource(a, 1);
+    detector.add_resource(b, 1);
+    assert!(detector.request(0, a));
+    detector.acquire(0, a);
+    assert!(detector.request(1, b));
+    detector.acquire(1, b);
+    // Thread 0 may wait for b, but then thread 1 must not wait for a
+    assert!(detector.request(0, b));
+    assert!(!detector.request(1, a));
+    // Once thread 1 gives b back, thread 0 gets it
+    detector.release(1, b);
+    detector.acquire(0, b);
+    assert!(detector.is_safe());
+    println!("deadlock_detector_test passed!");
+}
@@ -0,0 +1,90 @@
+//! Futex wait queues
+//!
+//! User threads wait on a `u32` in user memory until another thread wakes
+//! the waiters of that word. Futexes are identified by the physical address
+//! of the word, so threads of different processes sharing its page meet on
+//! the same futex, while copy-on-write copies of it are told apart.
+
+use super::UPSafeCell;
+use crate::mm::{try_translated_ref, try_translated_refmut, TranslateError};
+use crate::task::{block_current_and_run_next, wakeup_task, with_current_task};
+use alloc::collections::{BTreeMap, VecDeque};
+use lazy_static::*;
+
+/// Physical address of a futex word
+pub type FutexKey = usize;
+
+lazy_static! {
+    /// Tids of the threads waiting on each futex, oldest first
+    static ref FUTEX_QUEUES: UPSafeCell<BTreeMap<FutexKey, VecDeque<usize>>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// Get the key of the futex at `uaddr` in the address space of `token`,
+/// along with the word itself.
+///
+/// Where the page is writable, the word is translated for writing, so that
+/// a copy-on-write page gets a frame of its own first and its address is
+/// stable. A read-only page is never copied on write, so it keeps its frame
+/// as it is.
+pub fn futex_key(token: usize, uaddr: usize) -> Result<(FutexKey, &'static u32), TranslateError> {
+    let word: &'static u32 = match try_translated_refmut(token, uaddr as *mut u32) {
+        Ok(word) => word,
+        Err(TranslateError::PermissionDenied) => try_translated_ref(token, uaddr as *const u32)?,
+        Err(error) => return Err(error),
+    };
+    Ok((word as *const u32 as usize, word))
+}
+
+/// Block the current thread until [`futex_wake`] wakes it up on `key`
+pub fn futex_wait(key: FutexKey) {
+    let tid = with_current_task(|task| task.tid.0);
+    FUTEX_QUEUES
+        .exclusive_access()
+        .entry(key)
+        .or_default()
+        .push_back(tid);
+    block_current_and_run_next();
+}
+
+/// Wake up to `count` threads waiting on `key`, oldest first, returning how
+/// many were woken
+pub fn futex_wake(key: FutexKey, count: usize) -> usize {
+    let mut queues = FUTEX_QUEUES.exclusive_access();
+    let Some(queue) = queues.get_mut(&key) else {
+        return 0;
+    };
+    let woken = count.min(queue.len());
+    for tid in queue.drain(..woken) {
+        wakeup_task(tid);
+    }
+    if queue.is_empty() {
+        queues.remove(&key);
+    }
+    woken
+}
+
+/// Stop thread `tid` from waiting on `key`, e.g. once its wait timed out.
+/// Returns whether it was still waiting, i.e. nobody woke it up.
+pub fn futex_cancel(key: FutexKey, tid: usize) -> bool {
+    let mut queues = FUTEX_QUEUES.exclusive_access();
+    let Some(queue) = queues.get_mut(&key) else {
+        return false;
+    };
+    let Some(pos) = queue.iter().position(|&waiter| waiter == tid) else {
+        return false;
+    };
+    queue.remove(pos);
+    if queue.is_empty() {
+        queues.remove(&key);
+    }
+    true
+}
+
+/// Stop thread `tid` from waiting on any futex, e.g. because it exited
+pub fn futex_cancel_all(tid: usize) {
+    FUTEX_QUEUES.exclusive_access().retain(|_, queue| {
+        queue.retain(|&waiter| waiter != tid);
+        !queue.is_empty()
+    });
+}
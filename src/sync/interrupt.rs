@@ -0,0 +1,95 @@
+//! Nestable disabling of supervisor interrupts
+//!
+//! [`push_off`] and [`pop_off`] come in pairs, which may nest. The first
+//! `push_off` of a hart records whether `sstatus.SIE` was set, and only the
+//! matching outermost `pop_off` restores it, so leaving an inner critical
+//! section never re-enables interrupts inside an outer one.
+
+use crate::percpu::PerCpu;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lazy_static::*;
+use riscv::register::{sie, sstatus};
+
+/// Interrupt state of one hart
+struct InterruptState {
+    /// Number of `push_off` calls not yet matched by `pop_off`
+    depth: AtomicUsize,
+    /// Whether interrupts were enabled before the outermost `push_off`
+    sie_before: AtomicBool,
+}
+
+lazy_static! {
+    static ref INTERRUPT_STATE: PerCpu<InterruptState> = PerCpu::new(|_| InterruptState {
+        depth: AtomicUsize::new(0),
+        sie_before: AtomicBool::new(false),
+    });
+}
+
+/// Disable interrupts on this hart until the matching [`pop_off`]
+pub fn push_off() {
+    let sie = sstatus::read().sie();
+    unsafe {
+        sstatus::clear_sie();
+    }
+    let state = INTERRUPT_STATE.current();
+    if state.depth.load(Ordering::Relaxed) == 0 {
+        state.sie_before.store(sie, Ordering::Relaxed);
+    }
+    state.depth.fetch_add(1, Ordering::Relaxed);
+}
+
+/// End the critical section of the matching [`push_off`], enabling
+/// interrupts again if it was the outermost one and they were enabled before
+pub fn pop_off() {
+    assert!(!sstatus::read().sie(), "pop_off with interrupts enabled");
+    let state = INTERRUPT_STATE.current();
+    let depth = state.depth.load(Ordering::Relaxed);
+    assert!(depth > 0, "pop_off without push_off");
+    state.depth.store(depth - 1, Ordering::Relaxed);
+    if depth == 1 && state.sie_before.load(Ordering::Relaxed) {
+        unsafe {
+            sstatus::set_sie();
+        }
+    }
+}
+
+/// Check that only the outermost [`pop_off`] enables interrupts again
+pub fn push_off_test() {
+    let enabled = sstatus::read().sie();
+    // Mask all sources, so that setting SIE cannot trap into the kernel
+    let sources = sie::read();
+    unsafe {
+        sie::clear_sext();
+        sie::clear_stimer();
+        sie::clear_ssoft();
+        sstatus::set_sie();
+    }
+    push_off();
+    push_off();
+    pop_off();
+    assert!(!sstatus::read().sie());
+    pop_off();
+    assert!(sstatus::read().sie());
+    // Starting with interrupts disabled, they stay disabled
+    unsafe {
+        sstatus::clear_sie();
+    }
+    push_off();
+    pop_off();
+    assert!(!sstatus::read().sie());
+    unsafe {
+        if sources.sext() {
+            sie::set_sext();
+        }
+        if sources.stimer() {
+            sie::set_stimer();
+        }
+        if sources.ssoft() {
+            sie::set_ssoft();
+        }
+        if enabled {
+            sstatus::set_sie();
+        }
+    }
+    println!("push_off_test passed!");
+}
@@ -0,0 +1,126 @@
+//! Synchronization primitives
+
+mod condvar;
+mod deadlock;
+mod futex;
+mod interrupt;
+mod mutex;
+mod semaphore;
+mod wait_queue;
+
+use core::cell::{RefCell, RefMut, UnsafeCell};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub use condvar::Condvar;
+pub use deadlock::{deadlock_detector_test, DeadlockDetector, Resource};
+pub use futex::{futex_cancel, futex_cancel_all, futex_key, futex_wait, futex_wake};
+pub use interrupt::{pop_off, push_off, push_off_test};
+pub use mutex::{Mutex, MutexBlocking, MutexSpin};
+pub use semaphore::Semaphore;
+pub use wait_queue::WaitQueue;
+
+/// Wrapper around RefCell for uniprocessor systems
+pub struct UPSafeCell<T> {
+    inner: RefCell<T>,
+}
+
+unsafe impl<T> Sync for UPSafeCell<T> {}
+
+impl<T> UPSafeCell<T> {
+    /// Create a new UPSafeCell
+    pub unsafe fn new(value: T) -> Self {
+        Self {
+            inner: RefCell::new(value),
+        }
+    }
+
+    /// Get exclusive access to inner value
+    pub fn exclusive_access(&self) -> RefMut<'_, T> {
+        self.inner.borrow_mut()
+    }
+
+    /// Get exclusive access to inner value, or `None` if it is already borrowed
+    pub fn try_exclusive_access(&self) -> Option<RefMut<'_, T>> {
+        self.inner.try_borrow_mut().ok()
+    }
+}
+
+/// Spin lock that keeps supervisor interrupts disabled while held
+///
+/// Ordering: `lock()` first disables interrupts with [`push_off`], then spins
+/// for the lock, so an interrupt handler can never preempt the holder and spin
+/// on the same lock. Dropping the guard releases the lock first and only then
+/// calls [`pop_off`], so interrupts come back once the outermost lock is gone.
+pub struct SpinLock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send> Send for SpinLock<T> {}
+
+impl<T> SpinLock<T> {
+    /// Create a new unlocked SpinLock
+    pub const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Disable interrupts and spin until the lock is acquired
+    pub fn lock(&self) -> SpinLockGuard<'_, T> {
+        push_off();
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        SpinLockGuard { lock: self }
+    }
+
+    /// Same as [`SpinLock::lock`], named after [`UPSafeCell::exclusive_access`]
+    pub fn exclusive_access(&self) -> SpinLockGuard<'_, T> {
+        self.lock()
+    }
+
+    /// Release the lock without a guard, e.g. one held by code that panicked
+    ///
+    /// # Safety
+    ///
+    /// The holder must never touch the data again, as the next `lock()` hands
+    /// it out while the old guard still exists. The interrupt state of the
+    /// holder's [`push_off`] is not restored.
+    pub unsafe fn force_unlock(&self) {
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+/// RAII guard of a held [`SpinLock`]
+pub struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        pop_off();
+    }
+}
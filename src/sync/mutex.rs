@@ -0,0 +1,115 @@
+//! Mutexes for user threads
+//!
+//! Both kinds are only taken from syscalls, where the kernel cannot be
+//! preempted, so checking and setting the owner needs no atomics.
+
+use super::UPSafeCell;
+use crate::task::{
+    block_current_and_run_next, suspend_current_and_run_next, wakeup_task, with_current_task,
+};
+use alloc::collections::VecDeque;
+
+/// A mutex user threads synchronize on through `sys_mutex_*`
+pub trait Mutex: Sync + Send {
+    /// Acquire the mutex, waiting while another thread holds it
+    fn lock(&self);
+    /// Release the mutex; fails unless the current thread holds it
+    fn unlock(&self) -> bool;
+}
+
+/// Tid of the current thread, which mutexes record as their owner
+fn current_tid() -> usize {
+    with_current_task(|task| task.tid.0)
+}
+
+/// Mutex that yields the CPU while it is held by another thread
+pub struct MutexSpin {
+    /// Tid of the holder, if any
+    owner: UPSafeCell<Option<usize>>,
+}
+
+impl MutexSpin {
+    pub fn new() -> Self {
+        Self {
+            owner: unsafe { UPSafeCell::new(None) },
+        }
+    }
+}
+
+impl Mutex for MutexSpin {
+    fn lock(&self) {
+        let tid = current_tid();
+        loop {
+            let mut owner = self.owner.exclusive_access();
+            if owner.is_none() {
+                *owner = Some(tid);
+                return;
+            }
+            drop(owner);
+            suspend_current_and_run_next();
+        }
+    }
+
+    fn unlock(&self) -> bool {
+        let mut owner = self.owner.exclusive_access();
+        if *owner != Some(current_tid()) {
+            return false;
+        }
+        *owner = None;
+        true
+    }
+}
+
+/// Mutex that blocks contending threads until it is handed to them
+pub struct MutexBlocking {
+    inner: UPSafeCell<MutexBlockingInner>,
+}
+
+struct MutexBlockingInner {
+    /// Tid of the holder, if any
+    owner: Option<usize>,
+    /// Tids of the blocked threads, in the order they will get the mutex
+    wait_queue: VecDeque<usize>,
+}
+
+impl MutexBlocking {
+    pub fn new() -> Self {
+        Self {
+            inner: unsafe {
+                UPSafeCell::new(MutexBlockingInner {
+                    owner: None,
+                    wait_queue: VecDeque::new(),
+                })
+            },
+        }
+    }
+}
+
+impl Mutex for MutexBlocking {
+    fn lock(&self) {
+        let tid = current_tid();
+        let mut inner = self.inner.exclusive_access();
+        if inner.owner.is_none() {
+            inner.owner = Some(tid);
+            return;
+        }
+        inner.wait_queue.push_back(tid);
+        drop(inner);
+        // Once woken up, the mutex has been handed over by `unlock`
+        block_current_and_run_next();
+    }
+
+    /// Hand the mutex straight to the longest waiting thread, if any, so
+    /// that no other thread can take it before that one runs again
+    fn unlock(&self) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        if inner.owner != Some(current_tid()) {
+            return false;
+        }
+        inner.owner = inner.wait_queue.pop_front();
+        if let Some(tid) = inner.owner {
+            wakeup_task(tid);
+        }
+        true
+    }
+}
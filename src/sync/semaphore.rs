@@ -0,0 +1,54 @@
+//! Counting semaphores for user threads
+
+use super::UPSafeCell;
+use crate::task::{block_current_and_run_next, wakeup_task, with_current_task};
+use alloc::collections::VecDeque;
+
+/// Semaphore user threads synchronize on through `sys_semaphore_*`
+pub struct Semaphore {
+    inner: UPSafeCell<SemaphoreInner>,
+}
+
+struct SemaphoreInner {
+    /// Resources left if non-negative, otherwise minus the number of waiters
+    count: isize,
+    /// Tids of the blocked threads, oldest first
+    wait_queue: VecDeque<usize>,
+}
+
+impl Semaphore {
+    pub fn new(count: usize) -> Self {
+        Self {
+            inner: unsafe {
+                UPSafeCell::new(SemaphoreInner {
+                    count: count as isize,
+                    wait_queue: VecDeque::new(),
+                })
+            },
+        }
+    }
+
+    /// Release a resource, handing it to the oldest waiting thread if any
+    pub fn up(&self) {
+        let mut inner = self.inner.exclusive_access();
+        inner.count += 1;
+        if inner.count <= 0 {
+            if let Some(tid) = inner.wait_queue.pop_front() {
+                wakeup_task(tid);
+            }
+        }
+    }
+
+    /// Take a resource, blocking until one is handed over if none is left
+    pub fn down(&self) {
+        let mut inner = self.inner.exclusive_access();
+        inner.count -= 1;
+        if inner.count < 0 {
+            inner
+                .wait_queue
+                .push_back(with_current_task(|task| task.tid.0));
+            drop(inner);
+            block_current_and_run_next();
+        }
+    }
+}
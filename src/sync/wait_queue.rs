@@ -0,0 +1,53 @@
+//! Wait queues of threads blocked until an event, e.g. input arriving
+//!
+//! A queue holds [`Waiter`]s rather than tids, so an entry left behind by a
+//! thread that has stopped waiting, or has exited, wakes nothing.
+
+use super::SpinLock;
+use crate::task::{block_current_and_run_next, prepare_wait, wakeup_waiter, Waiter};
+use alloc::collections::VecDeque;
+
+/// Threads waiting for the same event
+pub struct WaitQueue {
+    waiters: SpinLock<VecDeque<Waiter>>,
+}
+
+impl WaitQueue {
+    /// Create an empty wait queue
+    pub const fn new() -> Self {
+        Self {
+            waiters: SpinLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Block the current thread until the next [`WaitQueue::wake_all`]
+    pub fn wait(&self) {
+        self.add(prepare_wait());
+        block_current_and_run_next();
+    }
+
+    /// Have the next [`WaitQueue::wake_all`] wake `waiter`, e.g. one that
+    /// waits on several queues at once
+    pub fn add(&self, waiter: Waiter) {
+        self.waiters.lock().push_back(waiter);
+    }
+
+    /// Forget `waiter` again once it no longer waits here
+    pub fn remove(&self, waiter: Waiter) {
+        self.waiters.lock().retain(|&queued| queued != waiter);
+    }
+
+    /// Wake up every waiting thread
+    pub fn wake_all(&self) {
+        // Waking borrows the task manager, so the lock is not held meanwhile
+        let waiters = core::mem::take(&mut *self.waiters.lock());
+        for waiter in waiters {
+            wakeup_waiter(waiter);
+        }
+    }
+
+    /// Whether no thread waits here
+    pub fn is_empty(&self) -> bool {
+        self.waiters.lock().is_empty()
+    }
+}
@@ -1,33 +1,622 @@
 //! File system related syscalls
 
+use super::process::TimeSpec;
+use super::{EAGAIN, EBADF, EFAULT, EINVAL, EMFILE, EPIPE, ESPIPE};
+use crate::config::{MAX_FD, PAGE_SIZE};
+use crate::fs::{
+    absolute_path, link, lookup, make_pipe, open_file, open_proc, unlink, File, FileDescriptor,
+    OpenFlags, PollEvents, Stat,
+};
+use crate::mm::{
+    copy_from_user, copy_to_user, frame_alloc, try_translated_byte_buffer, try_translated_ref,
+    try_translated_refmut, try_translated_str, UserBuffer,
+};
+use crate::task::{
+    block_current_and_run_next, current_user_token, prepare_wait, with_current_process,
+    with_current_task, SignalFlags,
+};
+use crate::timer::{add_timer, get_time_ms, remove_timer};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// Look up the file behind `fd` in the current process's fd table
+fn get_file(fd: usize) -> Option<Arc<dyn File>> {
+    with_current_process(|process| {
+        let fd = process.fd_table.get(fd)?.as_ref()?;
+        Some(fd.file.clone())
+    })
+}
+
+/// Whether a read or, if `write`, a write of `file` has to fail with
+/// -EAGAIN because the file is nonblocking and the access would block. An
+/// error or a hung up peer makes it return right away instead.
+fn would_block(file: &Arc<dyn File>, write: bool) -> bool {
+    let ready = if write {
+        PollEvents::POLLOUT
+    } else {
+        PollEvents::POLLIN
+    };
+    file.nonblocking()
+        && !file
+            .poll()
+            .intersects(ready | PollEvents::POLLERR | PollEvents::POLLHUP)
+}
+
+/// Whether nothing could be written to `file` because it is a pipe whose
+/// read ends are all closed; the current process gets SIGPIPE then, and the
+/// write fails with -EPIPE.
+fn broken_pipe(file: &Arc<dyn File>) -> bool {
+    if !file.poll().contains(PollEvents::POLLERR) {
+        return false;
+    }
+    with_current_process(|process| process.signals |= SignalFlags::SIGPIPE);
+    true
+}
+
 /// Read from file descriptor
-pub fn sys_read(_fd: usize, _buf: *const u8, _len: usize) -> isize {
-    // TODO: Implement file reading
-    0
+pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
+    let token = current_user_token();
+    match get_file(fd) {
+        Some(file) if file.readable() && would_block(&file, false) => -EAGAIN,
+        Some(file) if file.readable() => {
+            // The kernel stores into the buffer, so it must be writable
+            match try_translated_byte_buffer(token, buf, len, true) {
+                // The task manager must not be borrowed here: reading may block
+                Ok(buffers) => file.read(UserBuffer::new(buffers)) as isize,
+                Err(_) => -EFAULT,
+            }
+        }
+        _ => -1,
+    }
 }
 
-/// Write to file descriptor
+/// Write to file descriptor. Writing to a pipe without readers fails with
+/// -EPIPE and raises SIGPIPE, unless some bytes got through before.
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
-    match fd {
-        1 | 2 => {
-            // stdout/stderr
-            // TODO: Add proper validation that buf is in user address space
-            // and the memory region [buf, buf+len) is valid and readable
-            // For now, this is only called from kernel space for testing
-            if buf.is_null() || len == 0 {
-                return 0;
-            }
-            // SAFETY: This is currently only safe when called from kernel space
-            // with valid kernel buffers. Future implementation should use
-            // page table translation to validate user space buffers.
-            let slice = unsafe { core::slice::from_raw_parts(buf, len) };
-            let str = core::str::from_utf8(slice).unwrap_or("[Invalid UTF-8]");
-            print!("{}", str);
-            len as isize
-        }
-        _ => {
-            println!("[KERNEL] Unsupported fd in sys_write!");
-            -1
+    let token = current_user_token();
+    match get_file(fd) {
+        Some(file) if file.writable() && would_block(&file, true) => -EAGAIN,
+        Some(file) if file.writable() => match try_translated_byte_buffer(token, buf, len, false) {
+            Ok(buffers) => match file.write(UserBuffer::new(buffers)) {
+                0 if len > 0 && broken_pipe(&file) => -EPIPE,
+                written => written as isize,
+            },
+            Err(_) => -EFAULT,
+        },
+        _ => -1,
+    }
+}
+
+/// One buffer of a scatter-gather I/O request
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct IoVec {
+    pub base: usize,
+    pub len: usize,
+}
+
+/// Most buffers a scatter-gather I/O request may have
+const IOV_MAX: usize = 1024;
+
+/// Read the array of `iovcnt` [`IoVec`]s at `iov` from user space
+fn read_iovecs(token: usize, iov: *const IoVec, iovcnt: usize) -> Option<Vec<IoVec>> {
+    let size = iovcnt.checked_mul(size_of::<IoVec>())?;
+    let bytes = copy_from_user(token, iov as *const u8, size).ok()?;
+    Some(
+        bytes
+            .chunks_exact(size_of::<IoVec>())
+            // SAFETY: every bit pattern is a valid `IoVec`
+            .map(|chunk| unsafe { core::ptr::read_unaligned(chunk.as_ptr() as *const IoVec) })
+            .collect(),
+    )
+}
+
+/// Transfer the buffers of `iov` in order with `transfer`, which gets the
+/// translated buffer and returns how many bytes it moved. Stops at the first
+/// short transfer; a buffer the kernel may not access fails with -EFAULT
+/// unless some bytes were transferred before it, which are returned then.
+/// More than `IOV_MAX` buffers fail with -EINVAL.
+fn transfer_iovecs(
+    iov: *const IoVec,
+    iovcnt: usize,
+    write: bool,
+    mut transfer: impl FnMut(UserBuffer) -> usize,
+) -> isize {
+    if iovcnt > IOV_MAX {
+        return -EINVAL;
+    }
+    let token = current_user_token();
+    let iovecs = match read_iovecs(token, iov, iovcnt) {
+        Some(iovecs) => iovecs,
+        None => return -EFAULT,
+    };
+    let mut total = 0;
+    for iovec in iovecs.iter().filter(|iovec| iovec.len > 0) {
+        let buffers =
+            match try_translated_byte_buffer(token, iovec.base as *const u8, iovec.len, write) {
+                Ok(buffers) => buffers,
+                Err(_) if total > 0 => break,
+                Err(_) => return -EFAULT,
+            };
+        let transferred = transfer(UserBuffer::new(buffers));
+        total += transferred;
+        if transferred < iovec.len {
+            break;
+        }
+    }
+    total as isize
+}
+
+/// Read from file descriptor into the `iovcnt` buffers described at `iov`,
+/// filling each one before moving on to the next
+pub fn sys_readv(fd: usize, iov: *const IoVec, iovcnt: usize) -> isize {
+    match get_file(fd) {
+        Some(file) if file.readable() && would_block(&file, false) => -EAGAIN,
+        // The task manager must not be borrowed here: reading may block
+        Some(file) if file.readable() => {
+            transfer_iovecs(iov, iovcnt, true, |buffer| file.read(buffer))
+        }
+        _ => -1,
+    }
+}
+
+/// Write the `iovcnt` buffers described at `iov` to file descriptor, in
+/// order; a pipe without readers is handled as in [`sys_write`]
+pub fn sys_writev(fd: usize, iov: *const IoVec, iovcnt: usize) -> isize {
+    match get_file(fd) {
+        Some(file) if file.writable() && would_block(&file, true) => -EAGAIN,
+        Some(file) if file.writable() => {
+            match transfer_iovecs(iov, iovcnt, false, |buffer| file.write(buffer)) {
+                0 if broken_pipe(&file) => -EPIPE,
+                written => written,
+            }
+        }
+        _ => -1,
+    }
+}
+
+/// Store the metadata of the file behind `fd` into `st`
+pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
+    let file = match get_file(fd) {
+        Some(file) => file,
+        None => return -1,
+    };
+    match try_translated_refmut(current_user_token(), st) {
+        Ok(st) => {
+            *st = file.stat();
+            0
+        }
+        Err(_) => -EFAULT,
+    }
+}
+
+/// Fill `buf` with the entries of the directory `fd` after the ones read so
+/// far, in the layout described in [`crate::fs`], returning the number of
+/// bytes filled; 0 at the end of the directory. Fails with -1 if `fd` is no
+/// directory or the next entry does not fit into `len` bytes.
+pub fn sys_getdents64(fd: usize, buf: *mut u8, len: usize) -> isize {
+    let token = current_user_token();
+    match get_file(fd) {
+        Some(file) => match try_translated_byte_buffer(token, buf, len, true) {
+            Ok(buffers) => match file.getdents(UserBuffer::new(buffers)) {
+                Some(filled) => filled as isize,
+                None => -1,
+            },
+            Err(_) => -EFAULT,
+        },
+        None => -1,
+    }
+}
+
+/// Open the file at `path`, relative to the working directory, returning
+/// its new file descriptor; fails with -EMFILE if the fd table is full
+pub fn sys_open(path: *const u8, flags: u32) -> isize {
+    let path = match try_translated_str(current_user_token(), path) {
+        Ok(path) => path,
+        Err(_) => return -EFAULT,
+    };
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return -1,
+    };
+    let path = with_current_process(|process| absolute_path(&process.cwd, &path));
+    let file: Option<Arc<dyn File>> = match open_proc(path.as_str(), flags) {
+        Some(file) => Some(file),
+        None => open_file(path.as_str(), flags).map(|file| file as Arc<dyn File>),
+    };
+    match file {
+        Some(file) => with_current_process(|process| match process.alloc_fd() {
+            Some(fd) => {
+                process.fd_table[fd] = Some(FileDescriptor::new(
+                    file,
+                    flags.contains(OpenFlags::CLOEXEC),
+                ));
+                fd as isize
+            }
+            None => -EMFILE,
+        }),
+        None => -1,
+    }
+}
+
+/// Close a file descriptor, freeing its slot for reuse
+pub fn sys_close(fd: usize) -> isize {
+    with_current_process(|process| match process.fd_table.get_mut(fd) {
+        Some(slot @ Some(_)) => {
+            // Dropping the Arc closes the file once no other descriptor refers to it
+            slot.take();
+            0
+        }
+        _ => -1,
+    })
+}
+
+/// Duplicate `fd` into the lowest free descriptor, which stays open
+/// across exec; fails with -EMFILE if there is none below `MAX_FD`
+pub fn sys_dup(fd: usize) -> isize {
+    with_current_process(
+        |process| match process.fd_table.get(fd).cloned().flatten() {
+            Some(fd) => match process.alloc_fd() {
+                Some(new_fd) => {
+                    process.fd_table[new_fd] = Some(FileDescriptor::new(fd.file, false));
+                    new_fd as isize
+                }
+                None => -EMFILE,
+            },
+            None => -1,
+        },
+    )
+}
+
+/// Duplicate `old_fd` into `new_fd`, closing whatever `new_fd` referred to;
+/// `new_fd` stays open across exec. Fails with -EBADF if `new_fd` is not
+/// below `MAX_FD`.
+pub fn sys_dup2(old_fd: usize, new_fd: usize) -> isize {
+    if new_fd >= MAX_FD {
+        return -EBADF;
+    }
+    with_current_process(
+        |process| match process.fd_table.get(old_fd).cloned().flatten() {
+            Some(_) if old_fd == new_fd => new_fd as isize,
+            Some(fd) => {
+                if new_fd >= process.fd_table.len() {
+                    process.fd_table.resize(new_fd + 1, None);
+                }
+                process.fd_table[new_fd] = Some(FileDescriptor::new(fd.file, false));
+                new_fd as isize
+            }
+            None => -1,
+        },
+    )
+}
+
+/// Copy up to `count` bytes from `in_fd` to `out_fd` through a kernel
+/// buffer, returning the number of bytes written to `out_fd`.
+///
+/// Reading starts at the offset of `in_fd` and advances it, unless `offset`
+/// is not null: then it starts at `*offset`, which is advanced instead, and
+/// the offset of `in_fd` stays. Fails with -ESPIPE if `offset` is given but
+/// `in_fd` has no offset, and with -EPIPE as [`sys_write`] does.
+pub fn sys_sendfile(out_fd: usize, in_fd: usize, offset: *mut usize, count: usize) -> isize {
+    let (out_file, in_file) = match (get_file(out_fd), get_file(in_fd)) {
+        (Some(out_file), Some(in_file)) if out_file.writable() && in_file.readable() => {
+            (out_file, in_file)
+        }
+        _ => return -1,
+    };
+    let token = current_user_token();
+    let offset = if offset.is_null() {
+        None
+    } else {
+        match try_translated_refmut(token, offset) {
+            Ok(offset) => Some(offset),
+            Err(_) => return -EFAULT,
+        }
+    };
+    let saved_offset = match offset.as_ref() {
+        Some(&&mut offset) => match in_file.seek(offset) {
+            Some(saved_offset) => Some(saved_offset),
+            None => return -ESPIPE,
+        },
+        None => None,
+    };
+    // One frame serves as the buffer; the kernel reaches it identity-mapped
+    let frame = match frame_alloc() {
+        Some(frame) => frame,
+        None => return -1,
+    };
+    let mut sent = 0;
+    while sent < count {
+        let chunk = (count - sent).min(PAGE_SIZE);
+        let read = in_file.read(UserBuffer::new(vec![
+            &mut frame.ppn.get_bytes_array()[..chunk],
+        ]));
+        if read == 0 {
+            break;
+        }
+        let written = out_file.write(UserBuffer::new(vec![
+            &mut frame.ppn.get_bytes_array()[..read],
+        ]));
+        sent += written;
+        if written < read {
+            break;
         }
     }
+    if let (Some(offset), Some(saved_offset)) = (offset, saved_offset) {
+        *offset = in_file.seek(saved_offset).unwrap();
+    }
+    if sent == 0 && count > 0 && broken_pipe(&out_file) {
+        return -EPIPE;
+    }
+    sent as isize
+}
+
+/// An fd to watch with `sys_ppoll`
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PollFd {
+    /// Ignored if negative
+    pub fd: i32,
+    /// [`PollEvents`] to wait for
+    pub events: i16,
+    /// [`PollEvents`] that occurred, filled in by the kernel
+    pub revents: i16,
+}
+
+/// Wait until one of the `nfds` fds in `fds` is ready for the events asked
+/// for, filling in the events that occurred, and return how many fds are
+/// ready; 0 once `timeout` passed, unless it is null.
+///
+/// A not open fd is reported with `POLLNVAL`. Until some fd is ready the
+/// task sleeps on the wait queues of the files and, with a timeout, on a
+/// timer. Fails with -EINVAL for more than `MAX_FD` fds or an invalid or
+/// negative timeout.
+pub fn sys_ppoll(fds: *mut PollFd, nfds: usize, timeout: *const TimeSpec) -> isize {
+    if nfds > MAX_FD {
+        return -EINVAL;
+    }
+    let token = current_user_token();
+    let deadline_ms = if timeout.is_null() {
+        None
+    } else {
+        match try_translated_ref(token, timeout) {
+            Ok(timeout) if (timeout.sec as isize) < 0 || timeout.nsec >= 1_000_000_000 => {
+                return -EINVAL
+            }
+            Ok(timeout) => Some(
+                get_time_ms().saturating_add(
+                    timeout
+                        .sec
+                        .saturating_mul(1000)
+                        .saturating_add(timeout.nsec.div_ceil(1_000_000)),
+                ),
+            ),
+            Err(_) => return -EFAULT,
+        }
+    };
+    let size = nfds * size_of::<PollFd>();
+    let mut poll_fds: Vec<PollFd> = match copy_from_user(token, fds as *const u8, size) {
+        Ok(bytes) => bytes
+            .chunks_exact(size_of::<PollFd>())
+            // SAFETY: every bit pattern is a valid `PollFd`
+            .map(|chunk| unsafe { core::ptr::read_unaligned(chunk.as_ptr() as *const PollFd) })
+            .collect(),
+        Err(_) => return -EFAULT,
+    };
+    let always = PollEvents::POLLERR | PollEvents::POLLHUP | PollEvents::POLLNVAL;
+    let ready = loop {
+        let mut ready = 0;
+        let mut files = Vec::new();
+        for poll_fd in poll_fds.iter_mut() {
+            let events = if poll_fd.fd < 0 {
+                PollEvents::empty()
+            } else {
+                match get_file(poll_fd.fd as usize) {
+                    Some(file) => {
+                        let events = file.poll();
+                        files.push(file);
+                        events
+                    }
+                    None => PollEvents::POLLNVAL,
+                }
+            };
+            let wanted = PollEvents::from_bits_truncate(poll_fd.events as u16) | always;
+            poll_fd.revents = (events & wanted).bits() as i16;
+            if poll_fd.revents != 0 {
+                ready += 1;
+            }
+        }
+        if ready > 0 || deadline_ms.is_some_and(|deadline_ms| get_time_ms() >= deadline_ms) {
+            break ready;
+        }
+        // Nothing runs in between on a single hart with interrupts off in the
+        // kernel, so no event can slip in between the checks and blocking
+        let waiter = prepare_wait();
+        let queues: Vec<_> = files.iter().filter_map(|file| file.wait_queue()).collect();
+        for queue in queues.iter() {
+            queue.add(waiter);
+        }
+        let tid = with_current_task(|task| task.tid.0);
+        if let Some(deadline_ms) = deadline_ms {
+            add_timer(deadline_ms, tid);
+        }
+        block_current_and_run_next();
+        for queue in queues.iter() {
+            queue.remove(waiter);
+        }
+        if deadline_ms.is_some() {
+            remove_timer(tid);
+        }
+    };
+    let bytes = unsafe { core::slice::from_raw_parts(poll_fds.as_ptr() as *const u8, size) };
+    match copy_to_user(token, fds as *mut u8, bytes) {
+        Ok(_) => ready,
+        Err(_) => -EFAULT,
+    }
+}
+
+const F_DUPFD: usize = 0;
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
+const F_DUPFD_CLOEXEC: usize = 1030;
+/// The only descriptor flag: close on exec
+const FD_CLOEXEC: usize = 1;
+
+/// Control the descriptor `fd` as `cmd` says:
+///
+/// - `F_DUPFD` duplicates it into the lowest free descriptor not below
+///   `arg`, which stays open across exec; `F_DUPFD_CLOEXEC` into one that
+///   is closed on exec. Both return the new descriptor and fail with
+///   -EINVAL if `arg` is not below `MAX_FD`, or with -EMFILE if no
+///   descriptor from `arg` on is free.
+/// - `F_GETFD` returns its flags, `F_SETFD` sets them to `arg`.
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    with_current_process(|process| {
+        let entry = match process.fd_table.get_mut(fd) {
+            Some(Some(entry)) => entry,
+            _ => return -1,
+        };
+        match cmd {
+            F_DUPFD | F_DUPFD_CLOEXEC if arg >= MAX_FD => -EINVAL,
+            F_DUPFD | F_DUPFD_CLOEXEC => {
+                let file = entry.file.clone();
+                match process.alloc_fd_from(arg) {
+                    Some(new_fd) => {
+                        process.fd_table[new_fd] =
+                            Some(FileDescriptor::new(file, cmd == F_DUPFD_CLOEXEC));
+                        new_fd as isize
+                    }
+                    None => -EMFILE,
+                }
+            }
+            F_GETFD => {
+                if entry.cloexec {
+                    FD_CLOEXEC as isize
+                } else {
+                    0
+                }
+            }
+            F_SETFD => {
+                entry.cloexec = arg & FD_CLOEXEC != 0;
+                0
+            }
+            _ => -EINVAL,
+        }
+    })
+}
+
+/// Create a pipe and store its (read fd, write fd) into `pipe[0..2]`.
+/// With `O_NONBLOCK` in `flags` neither end ever blocks, and with
+/// `O_CLOEXEC` both descriptors are closed on exec. Fails with -EMFILE
+/// unless two descriptors are free.
+pub fn sys_pipe2(pipe: *mut usize, flags: u32) -> isize {
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) if (OpenFlags::NONBLOCK | OpenFlags::CLOEXEC).contains(flags) => flags,
+        _ => return -EINVAL,
+    };
+    let cloexec = flags.contains(OpenFlags::CLOEXEC);
+    let token = current_user_token();
+    let (read_fd_ref, write_fd_ref) = match (
+        try_translated_refmut(token, pipe),
+        try_translated_refmut(token, pipe.wrapping_add(1)),
+    ) {
+        (Ok(read_fd_ref), Ok(write_fd_ref)) => (read_fd_ref, write_fd_ref),
+        _ => return -EFAULT,
+    };
+    let (read_end, write_end) = make_pipe(flags.contains(OpenFlags::NONBLOCK));
+    let fds = with_current_process(|process| {
+        let read_fd = process.alloc_fd()?;
+        process.fd_table[read_fd] = Some(FileDescriptor::new(read_end, cloexec));
+        let Some(write_fd) = process.alloc_fd() else {
+            process.fd_table[read_fd] = None;
+            return None;
+        };
+        process.fd_table[write_fd] = Some(FileDescriptor::new(write_end, cloexec));
+        Some((read_fd, write_fd))
+    });
+    match fds {
+        Some((read_fd, write_fd)) => {
+            *read_fd_ref = read_fd;
+            *write_fd_ref = write_fd;
+            0
+        }
+        None => -EMFILE,
+    }
+}
+
+/// Copy the absolute path of the working directory, NUL-terminated, into
+/// `buf`, returning `buf`. Fails with -1 and leaves `buf` alone if the path
+/// and its NUL do not fit into `size` bytes.
+pub fn sys_getcwd(buf: *mut u8, size: usize) -> isize {
+    let cwd = with_current_process(|process| process.cwd.clone());
+    if cwd.len() + 1 > size {
+        return -1;
+    }
+    let token = current_user_token();
+    match copy_to_user(token, buf, cwd.as_bytes())
+        .and_then(|_| copy_to_user(token, buf.wrapping_add(cwd.len()), &[0]))
+    {
+        Ok(_) => buf as isize,
+        Err(_) => -EFAULT,
+    }
+}
+
+/// Change the working directory to `path`, relative to the current one.
+/// Fails with -1 if `path` does not name a directory.
+pub fn sys_chdir(path: *const u8) -> isize {
+    let path = match try_translated_str(current_user_token(), path) {
+        Ok(path) => path,
+        Err(_) => return -EFAULT,
+    };
+    let path = with_current_process(|process| absolute_path(&process.cwd, &path));
+    match lookup(&path) {
+        Some(inode) if inode.is_dir() => {
+            with_current_process(|process| process.cwd = path);
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Link `new_path` to the file at `old_path`, both relative to the working
+/// directory. Fails with -1 for directories and if `new_path` exists.
+pub fn sys_linkat(old_path: *const u8, new_path: *const u8) -> isize {
+    let token = current_user_token();
+    let (old_path, new_path) = match (
+        try_translated_str(token, old_path),
+        try_translated_str(token, new_path),
+    ) {
+        (Ok(old_path), Ok(new_path)) => (old_path, new_path),
+        _ => return -EFAULT,
+    };
+    let (old_path, new_path) = with_current_process(|process| {
+        (
+            absolute_path(&process.cwd, &old_path),
+            absolute_path(&process.cwd, &new_path),
+        )
+    });
+    if link(&old_path, &new_path) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Remove the directory entry at `path`, relative to the working directory.
+/// With `AT_REMOVEDIR` in `flags` it must be an empty directory, otherwise
+/// no directory. The file stays readable through descriptors open on it.
+pub fn sys_unlinkat(path: *const u8, flags: usize) -> isize {
+    const AT_REMOVEDIR: usize = 0x200;
+    let path = match try_translated_str(current_user_token(), path) {
+        Ok(path) => path,
+        Err(_) => return -EFAULT,
+    };
+    let path = with_current_process(|process| absolute_path(&process.cwd, &path));
+    if unlink(&path, flags & AT_REMOVEDIR != 0) {
+        0
+    } else {
+        -1
+    }
 }
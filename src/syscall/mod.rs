@@ -2,13 +2,41 @@
 
 mod fs;
 mod process;
+mod sync;
 
+use crate::config::MAX_SYSCALL_NUM;
+use crate::fs::Stat;
+use crate::task::{with_current_task, SignalAction};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use fs::*;
 use process::*;
+use sync::*;
+
+/// Operation not permitted
+const EPERM: isize = 1;
+/// Argument list too long
+const E2BIG: isize = 7;
+/// Bad file descriptor
+const EBADF: isize = 9;
+/// Try again: e.g. a futex no longer holds the expected value
+const EAGAIN: isize = 11;
+/// Bad address: a user pointer the kernel may not access
+const EFAULT: isize = 14;
+/// Invalid argument
+const EINVAL: isize = 22;
+/// Too many open files: the fd table is full
+const EMFILE: isize = 24;
+/// Illegal seek: the file has no offset
+const ESPIPE: isize = 29;
+/// Broken pipe: every read end of a pipe written to is closed
+const EPIPE: isize = 32;
+/// Function not implemented: the syscall number is unknown
+const ENOSYS: isize = 38;
 
 /// System call IDs following Linux RISC-V ABI
 const SYSCALL_GETCWD: usize = 17;
 const SYSCALL_DUP: usize = 23;
+const SYSCALL_DUP3: usize = 24;
 const SYSCALL_FCNTL: usize = 25;
 const SYSCALL_IOCTL: usize = 29;
 const SYSCALL_MKDIRAT: usize = 34;
@@ -25,15 +53,18 @@ const SYSCALL_GETDENTS64: usize = 61;
 const SYSCALL_LSEEK: usize = 62;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_READV: usize = 65;
 const SYSCALL_WRITEV: usize = 66;
 const SYSCALL_SENDFILE: usize = 71;
 const SYSCALL_PSELECT6: usize = 72;
+const SYSCALL_PPOLL: usize = 73;
 const SYSCALL_READLINKAT: usize = 78;
 const SYSCALL_FSTATAT: usize = 79;
 const SYSCALL_FSTAT: usize = 80;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_EXIT_GROUP: usize = 94;
 const SYSCALL_SET_TID_ADDRESS: usize = 96;
+const SYSCALL_FUTEX: usize = 98;
 const SYSCALL_NANOSLEEP: usize = 101;
 const SYSCALL_GETITIMER: usize = 102;
 const SYSCALL_SETITIMER: usize = 103;
@@ -44,6 +75,7 @@ const SYSCALL_TKILL: usize = 130;
 const SYSCALL_RT_SIGACTION: usize = 134;
 const SYSCALL_RT_SIGPROCMASK: usize = 135;
 const SYSCALL_RT_SIGRETURN: usize = 139;
+const SYSCALL_SET_PRIORITY: usize = 140; // setpriority
 const SYSCALL_TIMES: usize = 153;
 const SYSCALL_UNAME: usize = 160;
 const SYSCALL_GETRUSAGE: usize = 165;
@@ -56,6 +88,10 @@ const SYSCALL_GETGID: usize = 176;
 const SYSCALL_GETEGID: usize = 177;
 const SYSCALL_GETTID: usize = 178;
 const SYSCALL_SYSINFO: usize = 179;
+const SYSCALL_SHMGET: usize = 194;
+const SYSCALL_SHMCTL: usize = 195;
+const SYSCALL_SHMAT: usize = 196;
+const SYSCALL_SHMDT: usize = 197;
 const SYSCALL_SOCKET: usize = 198;
 const SYSCALL_BIND: usize = 200;
 const SYSCALL_LISTEN: usize = 201;
@@ -67,29 +103,117 @@ const SYSCALL_RECVFROM: usize = 207;
 const SYSCALL_SETSOCKOPT: usize = 208;
 const SYSCALL_BRK: usize = 214;
 const SYSCALL_MUNMAP: usize = 215;
-const SYSCALL_FORK: usize = 220;
-const SYSCALL_CLONE: usize = 220;
+const SYSCALL_FORK: usize = 220; // clone
 const SYSCALL_EXECVE: usize = 221;
 const SYSCALL_MMAP: usize = 222;
 const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MADVISE: usize = 233;
 const SYSCALL_WAIT4: usize = 260;
 const SYSCALL_PRLIMIT64: usize = 261;
 const SYSCALL_RENAMEAT2: usize = 276;
+const SYSCALL_SPAWN: usize = 400; // rCore extension
+const SYSCALL_TASK_INFO: usize = 410; // rCore extension
+const SYSCALL_ENABLE_DEADLOCK_DETECT: usize = 469; // rCore extension
+const SYSCALL_THREAD_CREATE: usize = 1000; // rCore extension
+const SYSCALL_MUTEX_CREATE: usize = 1010; // rCore extension
+const SYSCALL_MUTEX_LOCK: usize = 1011; // rCore extension
+const SYSCALL_MUTEX_UNLOCK: usize = 1012; // rCore extension
+const SYSCALL_SEMAPHORE_CREATE: usize = 1020; // rCore extension
+const SYSCALL_SEMAPHORE_UP: usize = 1021; // rCore extension
+const SYSCALL_SEMAPHORE_DOWN: usize = 1022; // rCore extension
+const SYSCALL_CONDVAR_CREATE: usize = 1030; // rCore extension
+const SYSCALL_CONDVAR_SIGNAL: usize = 1031; // rCore extension
+const SYSCALL_CONDVAR_WAIT: usize = 1032; // rCore extension
+const SYSCALL_SHUTDOWN: usize = 1100; // RPOS extension
+const SYSCALL_REBOOT: usize = 1101; // RPOS extension
+
+/// Number of syscalls made since boot
+static SYSCALL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Get the number of syscalls made since boot, by all tasks together
+pub fn syscall_count() -> usize {
+    SYSCALL_COUNT.load(Ordering::Relaxed)
+}
 
 /// System call dispatcher
-pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
+pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
+    SYSCALL_COUNT.fetch_add(1, Ordering::Relaxed);
+    if syscall_id < MAX_SYSCALL_NUM {
+        with_current_task(|task| *task.syscall_times.entry(syscall_id).or_insert(0) += 1);
+    }
     match syscall_id {
+        SYSCALL_GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
+        SYSCALL_DUP => sys_dup(args[0]),
+        SYSCALL_FCNTL => sys_fcntl(args[0], args[1], args[2]),
+        // dup3 without flags behaves as dup2
+        SYSCALL_DUP3 => sys_dup2(args[0], args[1]),
+        // Relative paths always start from the working directory, as with
+        // `dirfd == AT_FDCWD`
+        SYSCALL_OPENAT => sys_open(args[1] as *const u8, args[2] as u32),
+        // Like openat, both take paths relative to the working directory
+        SYSCALL_UNLINKAT => sys_unlinkat(args[1] as *const u8, args[2]),
+        SYSCALL_LINKAT => sys_linkat(args[1] as *const u8, args[3] as *const u8),
+        SYSCALL_CHDIR => sys_chdir(args[0] as *const u8),
+        SYSCALL_CLOSE => sys_close(args[0]),
+        SYSCALL_GETDENTS64 => sys_getdents64(args[0], args[1] as *mut u8, args[2]),
+        SYSCALL_FSTAT => sys_fstat(args[0], args[1] as *mut Stat),
+        SYSCALL_PIPE => sys_pipe2(args[0] as *mut usize, args[1] as u32),
         SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
+        SYSCALL_READV => sys_readv(args[0], args[1] as *const IoVec, args[2]),
+        SYSCALL_WRITEV => sys_writev(args[0], args[1] as *const IoVec, args[2]),
+        // The signal mask is ignored, as if it were null
+        SYSCALL_PPOLL => sys_ppoll(args[0] as *mut PollFd, args[1], args[2] as *const TimeSpec),
+        SYSCALL_SENDFILE => sys_sendfile(args[0], args[1], args[2] as *mut usize, args[3]),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
+        SYSCALL_SET_TID_ADDRESS => sys_set_tid_address(args[0]),
+        SYSCALL_FUTEX => sys_futex(args[0], args[1], args[2] as u32, args[3] as *const TimeSpec),
+        SYSCALL_NANOSLEEP => sys_nanosleep(args[0] as *const TimeVal),
         SYSCALL_YIELD => sys_yield(),
+        SYSCALL_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
+        SYSCALL_GET_TIME_OF_DAY => sys_get_time(args[0] as *mut TimeVal, args[1]),
+        SYSCALL_KILL => sys_kill(args[0], args[1]),
+        SYSCALL_RT_SIGACTION => sys_sigaction(
+            args[0],
+            args[1] as *const SignalAction,
+            args[2] as *mut SignalAction,
+        ),
+        SYSCALL_RT_SIGRETURN => sys_sigreturn(),
+        SYSCALL_TIMES => sys_times(args[0] as *mut Tms),
+        SYSCALL_UNAME => sys_uname(args[0] as *mut UtsName),
+        SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
+        SYSCALL_SHMGET => sys_shmget(args[0], args[1]),
+        SYSCALL_SHMCTL => sys_shmctl(args[0], args[1], args[2]),
+        SYSCALL_SHMAT => sys_shmat(args[0], args[1], args[2]),
+        SYSCALL_SHMDT => sys_shmdt(args[0]),
         SYSCALL_GETPID => sys_getpid(),
-        SYSCALL_FORK | SYSCALL_CLONE => sys_fork(),
-        SYSCALL_EXECVE => sys_exec(args[0] as *const u8),
+        SYSCALL_GETTID => sys_gettid(),
+        SYSCALL_BRK => sys_brk(args[0]),
+        SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
+        SYSCALL_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
+        SYSCALL_MADVISE => sys_madvise(args[0], args[1], args[2]),
+        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
+        SYSCALL_FORK => sys_fork(),
+        SYSCALL_EXECVE => sys_exec(args[0] as *const u8, args[1] as *const *const u8),
+        SYSCALL_SPAWN => sys_spawn(args[0] as *const u8),
+        SYSCALL_TASK_INFO => sys_task_info(args[0] as *mut TaskInfo),
+        SYSCALL_THREAD_CREATE => sys_thread_create(args[0], args[1]),
+        SYSCALL_MUTEX_CREATE => sys_mutex_create(args[0] != 0),
+        SYSCALL_MUTEX_LOCK => sys_mutex_lock(args[0]),
+        SYSCALL_MUTEX_UNLOCK => sys_mutex_unlock(args[0]),
+        SYSCALL_SEMAPHORE_CREATE => sys_semaphore_create(args[0]),
+        SYSCALL_SEMAPHORE_UP => sys_semaphore_up(args[0]),
+        SYSCALL_SEMAPHORE_DOWN => sys_semaphore_down(args[0]),
+        SYSCALL_CONDVAR_CREATE => sys_condvar_create(),
+        SYSCALL_CONDVAR_SIGNAL => sys_condvar_signal(args[0]),
+        SYSCALL_CONDVAR_WAIT => sys_condvar_wait(args[0], args[1]),
+        SYSCALL_ENABLE_DEADLOCK_DETECT => sys_enable_deadlock_detect(args[0]),
+        SYSCALL_SHUTDOWN => sys_shutdown(),
+        SYSCALL_REBOOT => sys_reboot(args[0] != 0),
         SYSCALL_WAIT4 => sys_waitpid(args[0] as isize, args[1] as *mut i32),
         _ => {
-            println!("[KERNEL] Unsupported syscall_id: {}", syscall_id);
-            -1
+            warn!("Unsupported syscall_id: {}", syscall_id);
+            -ENOSYS
         }
     }
 }
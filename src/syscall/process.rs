@@ -1,39 +1,700 @@
 //! Process related syscalls
 
-use crate::sbi::shutdown;
+use super::{E2BIG, EFAULT, EINVAL, EPERM};
+use crate::config::{
+    BOOT_WALL_CLOCK_SEC, CLOCK_FREQ, INIT_PID, KERNEL_NAME, KERNEL_VERSION, MAX_SYSCALL_NUM,
+    PAGE_SIZE, TICKS_PER_SEC, USER_SPACE_END, USER_STACK_SIZE,
+};
+use crate::console::console_flush;
+use crate::loader::get_app_data_by_name;
+use crate::mm::{
+    copy_to_user, shm_get, shm_remove, shm_segment, try_translated_ref, try_translated_refmut,
+    try_translated_str, try_translated_str_max, MapPermission, TranslateError, VirtAddr,
+};
+use crate::sbi::{reboot_cold, reboot_warm, shutdown};
+use crate::task::{
+    block_current_and_run_next, create_thread_current, current_user_token, exec_current,
+    exit_current_and_run_next, exit_process_and_run_next, fork_current, leave_signal_handler,
+    reap_zombie_child, send_signal, spawn_current, suspend_current_and_run_next,
+    with_current_process, with_current_task, SignalAction, SignalFlags, SIGKILL, SIGSEGV, SIGSTOP,
+};
+use crate::timer::{add_timer, get_time, get_time_ms, get_time_ns, get_time_us};
+use alloc::alloc::{alloc_zeroed, handle_alloc_error, Layout};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::mem::size_of;
 
-/// Exit current process
+/// Time value as seen by user programs
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct TimeVal {
+    pub sec: usize,
+    pub usec: usize,
+}
+
+/// Time value with nanosecond resolution, e.g. of a clock or a timeout
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct TimeSpec {
+    pub sec: usize,
+    pub nsec: usize,
+}
+
+/// Wall-clock time, starting at [`BOOT_WALL_CLOCK_SEC`]
+const CLOCK_REALTIME: usize = 0;
+/// Time since boot
+const CLOCK_MONOTONIC: usize = 1;
+
+/// CPU times of a process in clock ticks of `1 / TICKS_PER_SEC` seconds
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Tms {
+    /// Time its threads spent in user mode
+    pub utime: usize,
+    /// Time its threads spent in the kernel
+    pub stime: usize,
+    /// User time of its reaped children and their descendants
+    pub cutime: usize,
+    /// Kernel time of its reaped children and their descendants
+    pub cstime: usize,
+}
+
+/// Length of each `UtsName` field, including the NUL
+const UTSNAME_LEN: usize = 65;
+
+/// Identification of the kernel, in the layout of Linux's `struct utsname`;
+/// each field is a NUL-terminated string
+#[repr(C)]
+pub struct UtsName {
+    pub sysname: [u8; UTSNAME_LEN],
+    pub nodename: [u8; UTSNAME_LEN],
+    pub release: [u8; UTSNAME_LEN],
+    pub version: [u8; UTSNAME_LEN],
+    pub machine: [u8; UTSNAME_LEN],
+    pub domainname: [u8; UTSNAME_LEN],
+}
+
+/// Scheduling statistics of a thread as seen by user programs
+#[repr(C)]
+pub struct TaskInfo {
+    /// Number of times the thread was scheduled
+    pub run_count: usize,
+    /// Time the thread spent running, in ms
+    pub run_time: usize,
+    /// Time since the thread was first scheduled, in ms
+    pub time: usize,
+    /// Number of invocations of each syscall, this one included
+    pub syscall_times: [u32; MAX_SYSCALL_NUM],
+}
+
+impl TaskInfo {
+    /// Allocate an all-zero `TaskInfo` on the heap, as it is large for a
+    /// kernel stack
+    fn new_zeroed() -> Box<Self> {
+        let layout = Layout::new::<Self>();
+        // All-zero bytes are a valid TaskInfo
+        let ptr = unsafe { alloc_zeroed(layout) } as *mut Self;
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        unsafe { Box::from_raw(ptr) }
+    }
+}
+
+/// Exit the current thread; the main thread takes the whole process along
 pub fn sys_exit(exit_code: i32) -> ! {
     println!("[KERNEL] Application exited with code {}", exit_code);
+    exit_current_and_run_next(exit_code)
+}
+
+/// Power off the machine; only the init process may do so
+pub fn sys_shutdown() -> isize {
+    if with_current_task(|task| task.process.pid) != INIT_PID {
+        return -EPERM;
+    }
+    println!("[KERNEL] Shutdown requested by init");
+    console_flush();
     shutdown()
 }
 
+/// Reboot the machine, warm or cold; only the init process may do so
+pub fn sys_reboot(warm: bool) -> isize {
+    if with_current_task(|task| task.process.pid) != INIT_PID {
+        return -EPERM;
+    }
+    println!("[KERNEL] Reboot requested by init");
+    console_flush();
+    if warm {
+        reboot_warm()
+    } else {
+        reboot_cold()
+    }
+}
+
+/// Read the clock `clockid`, `CLOCK_REALTIME` or `CLOCK_MONOTONIC`, into `tp`
+pub fn sys_clock_gettime(clockid: usize, tp: *mut TimeSpec) -> isize {
+    let ns = get_time_ns();
+    let sec = match clockid {
+        CLOCK_REALTIME => BOOT_WALL_CLOCK_SEC + ns / 1_000_000_000,
+        CLOCK_MONOTONIC => ns / 1_000_000_000,
+        _ => return -EINVAL,
+    };
+    match try_translated_refmut(current_user_token(), tp) {
+        Ok(tp) => {
+            *tp = TimeSpec {
+                sec,
+                nsec: ns % 1_000_000_000,
+            };
+            0
+        }
+        Err(_) => -EFAULT,
+    }
+}
+
 /// Yield current process
 pub fn sys_yield() -> isize {
-    // TODO: Implement task scheduling
+    suspend_current_and_run_next();
+    0
+}
+
+/// Get the time elapsed since boot
+pub fn sys_get_time(ts: *mut TimeVal, _tz: usize) -> isize {
+    let us = get_time_us();
+    match try_translated_refmut(current_user_token(), ts) {
+        Ok(ts) => {
+            *ts = TimeVal {
+                sec: us / 1_000_000,
+                usec: us % 1_000_000,
+            };
+            0
+        }
+        Err(_) => -EFAULT,
+    }
+}
+
+/// Store the scheduling statistics of the current thread into `ti`
+pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
+    let now = get_time_ms();
+    let mut info = TaskInfo::new_zeroed();
+    with_current_task(|task| {
+        info.run_count = task.run_count;
+        info.run_time = task.run_time + (now - task.last_run_time);
+        info.time = now - task.first_run_time.unwrap_or(now);
+        for (&syscall_id, &count) in task.syscall_times.iter() {
+            info.syscall_times[syscall_id] = count;
+        }
+    });
+    let info_bytes = unsafe {
+        core::slice::from_raw_parts(&*info as *const _ as *const u8, size_of::<TaskInfo>())
+    };
+    match copy_to_user(current_user_token(), ti as *mut u8, info_bytes) {
+        Ok(_) => 0,
+        Err(_) => -EFAULT,
+    }
+}
+
+/// Store the CPU times of the current process into `buf` and return the
+/// clock ticks since boot
+pub fn sys_times(buf: *mut Tms) -> isize {
+    const TIME_PER_TICK: usize = CLOCK_FREQ / TICKS_PER_SEC;
+    // Bring the count of the running thread up to date
+    with_current_task(|task| task.charge_time(false));
+    let tms = with_current_process(|process| Tms {
+        utime: process.utime / TIME_PER_TICK,
+        stime: process.stime / TIME_PER_TICK,
+        cutime: process.cutime / TIME_PER_TICK,
+        cstime: process.cstime / TIME_PER_TICK,
+    });
+    match try_translated_refmut(current_user_token(), buf) {
+        Ok(buf) => {
+            *buf = tms;
+            (get_time() / TIME_PER_TICK) as isize
+        }
+        Err(_) => -EFAULT,
+    }
+}
+
+/// Store the identification of the kernel into `buf`
+pub fn sys_uname(buf: *mut UtsName) -> isize {
+    fn field(value: &str) -> [u8; UTSNAME_LEN] {
+        let mut field = [0; UTSNAME_LEN];
+        field[..value.len()].copy_from_slice(value.as_bytes());
+        field
+    }
+    let uts_name = UtsName {
+        sysname: field(KERNEL_NAME),
+        nodename: field("rpos"),
+        release: field(KERNEL_VERSION),
+        version: field(KERNEL_VERSION),
+        machine: field("riscv64"),
+        domainname: field(""),
+    };
+    // Too large to rule out that it crosses a page, so copy it byte-wise
+    let uts_name_bytes = unsafe {
+        core::slice::from_raw_parts(&uts_name as *const _ as *const u8, size_of::<UtsName>())
+    };
+    match copy_to_user(current_user_token(), buf as *mut u8, uts_name_bytes) {
+        Ok(_) => 0,
+        Err(_) => -EFAULT,
+    }
+}
+
+/// Sleep for the duration in `req`, with microsecond fields rounded up to
+/// whole milliseconds; a zero duration just yields
+pub fn sys_nanosleep(req: *const TimeVal) -> isize {
+    let duration_ms = match try_translated_ref(current_user_token(), req) {
+        Ok(req) if req.usec >= 1_000_000 => return -1,
+        Ok(req) => req
+            .sec
+            .saturating_mul(1000)
+            .saturating_add((req.usec + 999) / 1000),
+        Err(_) => return -EFAULT,
+    };
+    if duration_ms == 0 {
+        suspend_current_and_run_next();
+        return 0;
+    }
+    let tid = with_current_task(|task| task.tid.0);
+    add_timer(get_time_ms().saturating_add(duration_ms), tid);
+    block_current_and_run_next();
     0
 }
 
+/// Move the program break by `increment` bytes, returning the old break
+pub fn sys_sbrk(increment: isize) -> isize {
+    match with_current_process(|process| process.change_program_brk(increment)) {
+        Some(old_brk) => old_brk as isize,
+        None => -1,
+    }
+}
+
+/// Set the program break to `addr`, returning the new break;
+/// `addr == 0` only queries the current break
+pub fn sys_brk(addr: usize) -> isize {
+    let brk = with_current_process(|process| process.program_brk);
+    if addr == 0 {
+        return brk as isize;
+    }
+    match sys_sbrk(addr as isize - brk as isize) {
+        -1 => -1,
+        _ => addr as isize,
+    }
+}
+
+/// End of the user range `[start, start + len)`, if all of it lies below
+/// `USER_SPACE_END`, so that its addresses are canonical and clear of the
+/// thread stacks, trap contexts and the trampoline
+fn user_range_end(start: usize, len: usize) -> Option<usize> {
+    start.checked_add(len).filter(|&end| end <= USER_SPACE_END)
+}
+
+/// Map `len` bytes of fresh anonymous memory at `start` with the access
+/// rights in `prot` (bit 0 = R, bit 1 = W, bit 2 = X; W implies R),
+/// returning `start`. Pages are zero-filled on first access; with no rights
+/// at all (`PROT_NONE`) every access faults, as for guard pages.
+///
+/// Fails with -1 if the range is not page-aligned, overlaps an existing
+/// mapping or `prot` is invalid. File mappings are not supported yet, so
+/// `flags`, `fd` and `offset` are ignored.
+pub fn sys_mmap(
+    start: usize,
+    len: usize,
+    prot: usize,
+    _flags: usize,
+    _fd: usize,
+    _offset: usize,
+) -> isize {
+    if start % PAGE_SIZE != 0 || len == 0 || len % PAGE_SIZE != 0 {
+        return -1;
+    }
+    if prot & !0x7 != 0 {
+        return -1;
+    }
+    let end = match user_range_end(start, len) {
+        Some(end) => end,
+        None => return -1,
+    };
+    let mut permission = MapPermission::from_bits_truncate((prot << 1) as u8) | MapPermission::U;
+    // SV39 reserves writable pages that are not readable
+    if permission.contains(MapPermission::W) {
+        permission |= MapPermission::R;
+    }
+    let (start_va, end_va) = (VirtAddr::from(start), VirtAddr::from(end));
+    with_current_process(|process| match process.memory_set.as_mut() {
+        Some(memory_set) if !memory_set.overlaps(start_va, end_va) => {
+            // Frames come on first touch, so a huge `len` costs nothing up front
+            memory_set.insert_lazy_area(start_va, end_va, permission);
+            start as isize
+        }
+        _ => -1,
+    })
+}
+
+/// Unmap the pages in `[start, start + len)`, freeing their frames; mappings
+/// reaching out of the range keep their other pages. Returns -1 if nothing
+/// in the range is mapped or it touches a shared memory segment.
+pub fn sys_munmap(start: usize, len: usize) -> isize {
+    if start % PAGE_SIZE != 0 || len == 0 || len % PAGE_SIZE != 0 {
+        return -1;
+    }
+    // Never let user programs tear down thread stacks or trap contexts
+    let end = match user_range_end(start, len) {
+        Some(end) => end,
+        None => return -1,
+    };
+    let removed = with_current_process(|process| match process.memory_set.as_mut() {
+        Some(memory_set) => {
+            memory_set.remove_range(VirtAddr::from(start).floor(), VirtAddr::from(end).floor())
+        }
+        None => false,
+    });
+    if removed {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Change the access rights of the pages in `[start, start + len)` to `prot`
+/// (bit 0 = R, bit 1 = W, bit 2 = X, as for `sys_mmap`), keeping their
+/// contents. Write access implies read access, and `PROT_NONE` makes every
+/// access fault until the rights are given back.
+///
+/// Fails with -1 if `start` is not page-aligned, `prot` is invalid or a page
+/// of the range is unmapped.
+pub fn sys_mprotect(start: usize, len: usize, prot: usize) -> isize {
+    if start % PAGE_SIZE != 0 || len == 0 {
+        return -1;
+    }
+    if prot & !0x7 != 0 {
+        return -1;
+    }
+    let end = match user_range_end(start, len) {
+        Some(end) => end,
+        None => return -1,
+    };
+    let mut permission = MapPermission::from_bits_truncate((prot << 1) as u8) | MapPermission::U;
+    if permission.contains(MapPermission::W) {
+        permission |= MapPermission::R;
+    }
+    let (start_vpn, end_vpn) = (VirtAddr::from(start).floor(), VirtAddr::from(end).ceil());
+    let protected = with_current_process(|process| match process.memory_set.as_mut() {
+        Some(memory_set) => memory_set.protect_range(start_vpn, end_vpn, permission),
+        None => false,
+    });
+    if protected {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Advise that the pages in `[start, start + len)` are not needed for now
+const MADV_DONTNEED: usize = 4;
+
+/// Act on `advice` about how the pages in `[start, start + len)` will be
+/// used. `MADV_DONTNEED` frees their frames, and their next access finds
+/// them zeroed; other advice is accepted and ignored.
+///
+/// Fails with -1 if `start` is not page-aligned or, for `MADV_DONTNEED`, a
+/// page of the range is unmapped.
+pub fn sys_madvise(start: usize, len: usize, advice: usize) -> isize {
+    if start % PAGE_SIZE != 0 {
+        return -1;
+    }
+    let end = match user_range_end(start, len) {
+        Some(end) => end,
+        None => return -1,
+    };
+    if advice != MADV_DONTNEED {
+        return 0;
+    }
+    let (start_vpn, end_vpn) = (VirtAddr::from(start).floor(), VirtAddr::from(end).ceil());
+    let discarded = with_current_process(|process| match process.memory_set.as_mut() {
+        Some(memory_set) => memory_set.discard_range(start_vpn, end_vpn),
+        None => false,
+    });
+    if discarded {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Get the id of the shared memory segment with `key`, creating one of at
+/// least `size` bytes if there is none yet or `key` is `IPC_PRIVATE`.
+/// Fails with -1 if the segment is smaller or cannot be created.
+pub fn sys_shmget(key: usize, size: usize) -> isize {
+    match shm_get(key, size) {
+        Some(id) => id as isize,
+        None => -1,
+    }
+}
+
+/// Map the shared memory segment `shmid` at `addr`, read-only with
+/// `SHM_RDONLY` in `flags`, returning `addr`.
+///
+/// Fails with -1 if there is no such segment, or `addr` is not page-aligned
+/// or the segment would overlap an existing mapping there.
+pub fn sys_shmat(shmid: usize, addr: usize, flags: usize) -> isize {
+    const SHM_RDONLY: usize = 0o10000;
+    let segment = match shm_segment(shmid) {
+        Some(segment) => segment,
+        None => return -1,
+    };
+    if addr == 0 || addr % PAGE_SIZE != 0 {
+        return -1;
+    }
+    if user_range_end(addr, segment.size()).is_none() {
+        return -1;
+    }
+    let mut permission = MapPermission::R | MapPermission::U;
+    if flags & SHM_RDONLY == 0 {
+        permission |= MapPermission::W;
+    }
+    let (start_va, end_va) = (VirtAddr::from(addr), VirtAddr::from(addr + segment.size()));
+    with_current_process(|process| match process.memory_set.as_mut() {
+        Some(memory_set) if !memory_set.overlaps(start_va, end_va) => {
+            memory_set.insert_shared_area(start_va, segment, permission);
+            addr as isize
+        }
+        _ => -1,
+    })
+}
+
+/// Unmap the shared memory segment attached at `addr`; the segment goes
+/// away once it is detached everywhere. Fails with -1 if none is attached
+/// there.
+pub fn sys_shmdt(addr: usize) -> isize {
+    let detached = with_current_process(|process| {
+        process
+            .memory_set
+            .as_mut()
+            .is_some_and(|memory_set| memory_set.remove_shared_area(VirtAddr::from(addr)))
+    });
+    if detached {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Control the shared memory segment `shmid`. Only `IPC_RMID` is supported:
+/// it removes the segment so that no one can attach it anymore, while those
+/// who have it attached keep it until they detach. Other commands fail with
+/// -EINVAL, and a missing segment with -1.
+pub fn sys_shmctl(shmid: usize, cmd: usize, _buf: usize) -> isize {
+    const IPC_RMID: usize = 0;
+    if cmd != IPC_RMID {
+        return -EINVAL;
+    }
+    if shm_remove(shmid) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Set the scheduling priority of the current task, returning it;
+/// priorities below 2 are rejected with -1
+pub fn sys_set_priority(priority: isize) -> isize {
+    if priority < 2 {
+        return -1;
+    }
+    with_current_task(|task| task.set_priority(priority as usize));
+    priority
+}
+
 /// Get process ID
 pub fn sys_getpid() -> isize {
-    // TODO: Return actual PID
-    1
+    with_current_task(|task| task.process.pid) as isize
+}
+
+/// Get the ID of the current thread
+pub fn sys_gettid() -> isize {
+    with_current_task(|task| task.tid.0) as isize
+}
+
+/// Clear the `u32` at `tidptr` and wake a futex waiter on it once the
+/// current thread exits, returning its tid
+pub fn sys_set_tid_address(tidptr: usize) -> isize {
+    with_current_task(|task| {
+        task.clear_child_tid = tidptr;
+        task.tid.0 as isize
+    })
+}
+
+/// Start a thread of the current process at `entry` with `arg` as its only
+/// argument, returning its tid. The thread shares the address space and
+/// files and gets a user stack of its own; returning from `entry` is not
+/// supported, threads end through `sys_exit`.
+pub fn sys_thread_create(entry: usize, arg: usize) -> isize {
+    match create_thread_current(entry, arg) {
+        Some(tid) => tid as isize,
+        None => -1,
+    }
 }
 
 /// Fork current process
 pub fn sys_fork() -> isize {
-    // TODO: Implement fork
-    -1
+    match fork_current() {
+        Some(child) => child as isize,
+        None => -1,
+    }
 }
 
-/// Execute program
-pub fn sys_exec(_path: *const u8) -> isize {
-    // TODO: Implement exec
-    -1
+/// Replace the current program with the one at `path`, passing it the
+/// arguments in the null-terminated array `args`, which may itself be null.
+/// Only the main thread may do so; the other threads end.
+///
+/// Fails with -E2BIG if the arguments, their NULs and the argv array would
+/// not fit on the new user stack.
+pub fn sys_exec(path: *const u8, mut args: *const *const u8) -> isize {
+    let token = current_user_token();
+    let path = match try_translated_str(token, path) {
+        Ok(path) => path,
+        Err(_) => return -EFAULT,
+    };
+    // Read the arguments now: the old address space goes away with exec.
+    // Stop as soon as they outgrow the stack, before copying any more; the
+    // budget leaves room for the null ending argv and for alignment.
+    let mut budget = USER_STACK_SIZE - 2 * size_of::<usize>();
+    let mut args_vec: Vec<String> = Vec::new();
+    while !args.is_null() {
+        let arg_ptr = match try_translated_ref(token, args) {
+            Ok(&arg_ptr) if arg_ptr.is_null() => break,
+            Ok(&arg_ptr) => arg_ptr,
+            Err(_) => return -EFAULT,
+        };
+        // Each argument takes an argv slot and a NUL besides its bytes
+        let Some(max_len) = budget.checked_sub(size_of::<usize>() + 1) else {
+            return -E2BIG;
+        };
+        match try_translated_str_max(token, arg_ptr, max_len) {
+            Ok(arg) => {
+                budget = max_len - arg.len();
+                args_vec.push(arg);
+            }
+            Err(TranslateError::TooLong) => return -E2BIG,
+            Err(_) => return -EFAULT,
+        }
+        args = args.wrapping_add(1);
+    }
+    let data = match get_app_data_by_name(path.as_str()) {
+        Some(data) => data,
+        None => return -1,
+    };
+    // The result lands in a0, so hand back argc rather than 0
+    match exec_current(data, &args_vec) {
+        Some(argc) => argc as isize,
+        None => -1,
+    }
+}
+
+/// Start the program at `path` in a new child process, returning its pid
+pub fn sys_spawn(path: *const u8) -> isize {
+    let path = match try_translated_str(current_user_token(), path) {
+        Ok(path) => path,
+        Err(_) => return -EFAULT,
+    };
+    match get_app_data_by_name(path.as_str()) {
+        Some(data) => spawn_current(data) as isize,
+        None => -1,
+    }
+}
+
+/// Reap the exited child `pid`, or any exited child if `pid == -1`, storing
+/// its exit code into `exit_code_ptr` unless that is null.
+///
+/// Returns the child's pid, -1 if there is no such child, or -2 if it is
+/// still running and the caller should yield and retry.
+pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
+    // Check the pointer before reaping, so that a bad one loses no exit code
+    let exit_code_ref = if exit_code_ptr.is_null() {
+        None
+    } else {
+        match try_translated_refmut(current_user_token(), exit_code_ptr) {
+            Ok(exit_code_ref) => Some(exit_code_ref),
+            Err(_) => return -EFAULT,
+        }
+    };
+    match reap_zombie_child(pid) {
+        Ok((child, exit_code)) => {
+            if let Some(exit_code_ref) = exit_code_ref {
+                *exit_code_ref = exit_code;
+            }
+            child as isize
+        }
+        Err(error) => error,
+    }
+}
+
+/// Send signal `signum` to the task `pid`
+pub fn sys_kill(pid: usize, signum: usize) -> isize {
+    match SignalFlags::from_signum(signum) {
+        Some(signal) if send_signal(pid, signal) => 0,
+        _ => -1,
+    }
+}
+
+/// Register `action` for signal `signum` unless it is null, storing the
+/// previous action into `old_action` unless that is null.
+///
+/// SIGKILL and SIGSTOP cannot be caught, blocked or ignored.
+pub fn sys_sigaction(
+    signum: usize,
+    action: *const SignalAction,
+    old_action: *mut SignalAction,
+) -> isize {
+    if SignalFlags::from_signum(signum).is_none() || signum == SIGKILL || signum == SIGSTOP {
+        return -1;
+    }
+    let token = current_user_token();
+    let action = if action.is_null() {
+        None
+    } else {
+        match try_translated_ref(token, action) {
+            Ok(action) => Some(SignalAction {
+                handler: action.handler,
+                mask: SignalFlags::from_bits_truncate(action.mask.bits())
+                    - (SignalFlags::SIGKILL | SignalFlags::SIGSTOP),
+            }),
+            Err(_) => return -EFAULT,
+        }
+    };
+    let old_action_ref = if old_action.is_null() {
+        None
+    } else {
+        match try_translated_refmut(token, old_action) {
+            Ok(old_action_ref) => Some(old_action_ref),
+            Err(_) => return -EFAULT,
+        }
+    };
+    let old = with_current_process(|process| {
+        let old = process.signal_actions[signum];
+        if let Some(action) = action {
+            process.signal_actions[signum] = action;
+        }
+        old
+    });
+    if let Some(old_action_ref) = old_action_ref {
+        *old_action_ref = old;
+    }
+    0
 }
 
-/// Wait for process
-pub fn sys_waitpid(_pid: isize, _exit_code: *mut i32) -> isize {
-    // TODO: Implement waitpid
-    -1
+/// Return from a signal handler to the code it interrupted. The result is
+/// the interrupted code's a0, which the syscall path writes back unchanged.
+///
+/// Without a handler frame to return to the task is beyond repair, so it
+/// gets terminated.
+pub fn sys_sigreturn() -> isize {
+    match leave_signal_handler() {
+        Some(a0) => a0 as isize,
+        None => {
+            println!("[KERNEL] sigreturn without a signal frame, terminating");
+            exit_process_and_run_next(-(SIGSEGV as i32))
+        }
+    }
 }
@@ -0,0 +1,267 @@
+//! Synchronization related syscalls
+
+use super::process::TimeSpec;
+use super::{EAGAIN, EFAULT, EINVAL};
+use crate::mm::try_translated_ref;
+use crate::sync::{
+    futex_cancel, futex_key, futex_wait, futex_wake, Condvar, Mutex, MutexBlocking, MutexSpin,
+    Resource, Semaphore,
+};
+use crate::task::{current_user_token, with_current_process, with_current_task};
+use crate::timer::{add_timer, get_time_ms, remove_timer};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// Returned instead of waiting for a lock when that could deadlock
+const EDEADLOCK: isize = 0xDEAD;
+
+/// Store `item` in the lowest free slot of `list`, returning its index
+fn alloc_id<T>(list: &mut Vec<Option<T>>, item: T) -> usize {
+    match list.iter().position(|slot| slot.is_none()) {
+        Some(id) => {
+            list[id] = Some(item);
+            id
+        }
+        None => {
+            list.push(Some(item));
+            list.len() - 1
+        }
+    }
+}
+
+/// Ask the deadlock detector whether the current thread may wait for
+/// `resource`, recording the request if so; returns the thread's slot
+fn request(resource: Resource) -> Option<usize> {
+    let slot = with_current_task(|task| task.slot);
+    with_current_process(|process| process.deadlock_detector.request(slot, resource))
+        .then_some(slot)
+}
+
+/// Record that the thread in `slot` got the `resource` it requested
+fn acquire(slot: usize, resource: Resource) {
+    with_current_process(|process| process.deadlock_detector.acquire(slot, resource));
+}
+
+/// Record that the current thread gave back a unit of `resource`
+fn release(resource: Resource) {
+    let slot = with_current_task(|task| task.slot);
+    with_current_process(|process| process.deadlock_detector.release(slot, resource));
+}
+
+/// Look up the mutex `id` of the current process
+fn get_mutex(id: usize) -> Option<Arc<dyn Mutex>> {
+    with_current_process(|process| process.mutex_list.get(id).cloned().flatten())
+}
+
+/// Create a mutex, returning its id. A blocking mutex puts contending
+/// threads to sleep, otherwise they keep yielding until it is free.
+pub fn sys_mutex_create(blocking: bool) -> isize {
+    let mutex: Arc<dyn Mutex> = if blocking {
+        Arc::new(MutexBlocking::new())
+    } else {
+        Arc::new(MutexSpin::new())
+    };
+    with_current_process(|process| {
+        let id = alloc_id(&mut process.mutex_list, mutex);
+        process
+            .deadlock_detector
+            .add_resource(Resource::Mutex(id), 1);
+        id as isize
+    })
+}
+
+/// Lock the mutex `id`, waiting as long as another thread holds it.
+/// Fails with -0xDEAD if deadlock detection is on and waiting could deadlock.
+pub fn sys_mutex_lock(id: usize) -> isize {
+    let mutex = match get_mutex(id) {
+        Some(mutex) => mutex,
+        None => return -1,
+    };
+    let slot = match request(Resource::Mutex(id)) {
+        Some(slot) => slot,
+        None => return -EDEADLOCK,
+    };
+    // The process must not be borrowed here: locking may block
+    mutex.lock();
+    acquire(slot, Resource::Mutex(id));
+    0
+}
+
+/// Unlock the mutex `id`, letting one waiting thread take it. Fails with -1
+/// unless the current thread holds it.
+pub fn sys_mutex_unlock(id: usize) -> isize {
+    match get_mutex(id) {
+        Some(mutex) if mutex.unlock() => {
+            release(Resource::Mutex(id));
+            0
+        }
+        _ => -1,
+    }
+}
+
+/// Look up the semaphore `id` of the current process
+fn get_semaphore(id: usize) -> Option<Arc<Semaphore>> {
+    with_current_process(|process| process.semaphore_list.get(id).cloned().flatten())
+}
+
+/// Create a semaphore holding `count` resources, returning its id
+pub fn sys_semaphore_create(count: usize) -> isize {
+    if count > isize::MAX as usize {
+        return -1;
+    }
+    let semaphore = Arc::new(Semaphore::new(count));
+    with_current_process(|process| {
+        let id = alloc_id(&mut process.semaphore_list, semaphore);
+        process
+            .deadlock_detector
+            .add_resource(Resource::Semaphore(id), count);
+        id as isize
+    })
+}
+
+/// Release a resource of the semaphore `id`, waking its oldest waiter
+pub fn sys_semaphore_up(id: usize) -> isize {
+    match get_semaphore(id) {
+        Some(semaphore) => {
+            semaphore.up();
+            release(Resource::Semaphore(id));
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Take a resource of the semaphore `id`, waiting until one is free.
+/// Fails with -0xDEAD if deadlock detection is on and waiting could deadlock.
+pub fn sys_semaphore_down(id: usize) -> isize {
+    let semaphore = match get_semaphore(id) {
+        Some(semaphore) => semaphore,
+        None => return -1,
+    };
+    let slot = match request(Resource::Semaphore(id)) {
+        Some(slot) => slot,
+        None => return -EDEADLOCK,
+    };
+    // The process must not be borrowed here: taking may block
+    semaphore.down();
+    acquire(slot, Resource::Semaphore(id));
+    0
+}
+
+/// Look up the condition variable `id` of the current process
+fn get_condvar(id: usize) -> Option<Arc<Condvar>> {
+    with_current_process(|process| process.condvar_list.get(id).cloned().flatten())
+}
+
+/// Create a condition variable, returning its id
+pub fn sys_condvar_create() -> isize {
+    let condvar = Arc::new(Condvar::new());
+    with_current_process(|process| alloc_id(&mut process.condvar_list, condvar)) as isize
+}
+
+/// Wake one thread waiting on the condition variable `id`
+pub fn sys_condvar_signal(id: usize) -> isize {
+    match get_condvar(id) {
+        Some(condvar) => {
+            condvar.signal();
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Release the mutex `mutex_id` and wait on the condition variable
+/// `condvar_id`, holding the mutex again on return. Fails with -1 if either
+/// id is invalid or the current thread does not hold the mutex, and with
+/// -0xDEAD, without the mutex, if taking it back could deadlock.
+pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
+    let condvar = match (get_condvar(condvar_id), get_mutex(mutex_id)) {
+        (Some(condvar), Some(_)) => condvar,
+        _ => return -1,
+    };
+    // Go through the mutex syscalls, so that the deadlock detector sees the
+    // mutex given back and taken again
+    if sys_mutex_unlock(mutex_id) != 0 {
+        return -1;
+    }
+    // The process must not be borrowed here: waiting blocks
+    condvar.wait();
+    sys_mutex_lock(mutex_id)
+}
+
+/// Turn the deadlock check of `sys_mutex_lock` and `sys_semaphore_down` on
+/// (`enabled == 1`) or off (`enabled == 0`) for the current process
+pub fn sys_enable_deadlock_detect(enabled: usize) -> isize {
+    if enabled > 1 {
+        return -1;
+    }
+    with_current_process(|process| process.deadlock_detector.enabled = enabled == 1);
+    0
+}
+
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+/// Marks a futex as used by one process only, which changes nothing here
+const FUTEX_PRIVATE_FLAG: usize = 128;
+
+/// Connection timed out
+const ETIMEDOUT: isize = 110;
+
+/// Wait on or wake the futex at `uaddr` as `op` says. A `uaddr` that is not
+/// 4-byte aligned fails with -EINVAL.
+///
+/// `FUTEX_WAIT` blocks until a `FUTEX_WAKE` on the same word, but fails with
+/// -EAGAIN right away unless the `u32` at `uaddr` equals `val`. Unless
+/// `timeout` is null, it fails with -ETIMEDOUT once that much time passed.
+/// `FUTEX_WAKE` wakes up to `val` waiters and returns their number.
+pub fn sys_futex(uaddr: usize, op: usize, val: u32, timeout: *const TimeSpec) -> isize {
+    if uaddr % size_of::<u32>() != 0 {
+        return -EINVAL;
+    }
+    let token = current_user_token();
+    let (key, word) = match futex_key(token, uaddr) {
+        Ok(futex) => futex,
+        Err(_) => return -EFAULT,
+    };
+    match op & !FUTEX_PRIVATE_FLAG {
+        FUTEX_WAIT => {
+            let timeout_ms = if timeout.is_null() {
+                None
+            } else {
+                match try_translated_ref(token, timeout) {
+                    Ok(timeout) if timeout.nsec >= 1_000_000_000 => return -EINVAL,
+                    Ok(timeout) => Some(
+                        timeout
+                            .sec
+                            .saturating_mul(1000)
+                            .saturating_add(timeout.nsec.div_ceil(1_000_000)),
+                    ),
+                    Err(_) => return -EFAULT,
+                }
+            };
+            // Nothing runs in between on a single hart with interrupts off in
+            // the kernel, so no wake-up can slip in after the comparison
+            if *word != val {
+                return -EAGAIN;
+            }
+            let tid = with_current_task(|task| task.tid.0);
+            if let Some(timeout_ms) = timeout_ms {
+                add_timer(get_time_ms().saturating_add(timeout_ms), tid);
+            }
+            futex_wait(key);
+            if timeout_ms.is_none() {
+                return 0;
+            }
+            // Still queued means the timer, not a FUTEX_WAKE, woke us up
+            if futex_cancel(key, tid) {
+                -ETIMEDOUT
+            } else {
+                remove_timer(tid);
+                0
+            }
+        }
+        FUTEX_WAKE => futex_wake(key, val as usize) as isize,
+        _ => -EINVAL,
+    }
+}
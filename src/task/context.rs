@@ -0,0 +1,44 @@
+//! Task context saved across `__switch`
+
+use crate::trap::trap_return;
+
+/// Callee-saved registers of a task suspended in kernel mode
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct TaskContext {
+    /// Return address: where `__switch` returns to when this task resumes
+    ra: usize,
+    /// Kernel stack pointer
+    sp: usize,
+    /// Callee-saved registers s0-s11
+    s: [usize; 12],
+}
+
+impl TaskContext {
+    /// Create an empty task context
+    pub const fn zero_init() -> Self {
+        Self {
+            ra: 0,
+            sp: 0,
+            s: [0; 12],
+        }
+    }
+
+    /// Create a context that returns to user mode through `trap_return`
+    pub fn goto_trap_return(kstack_ptr: usize) -> Self {
+        Self {
+            ra: trap_return as usize,
+            sp: kstack_ptr,
+            s: [0; 12],
+        }
+    }
+
+    /// Create a context that starts executing `entry` on the given kernel stack
+    pub fn goto_entry(entry: usize, kstack_ptr: usize) -> Self {
+        Self {
+            ra: entry,
+            sp: kstack_ptr,
+            s: [0; 12],
+        }
+    }
+}
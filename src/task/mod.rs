@@ -0,0 +1,847 @@
+//! Task management
+//!
+//! The running task gives up the CPU through [`suspend_current_and_run_next`]
+//! or [`exit_current_and_run_next`], and the next `Ready` task is switched in
+//! via `__switch`. Which one comes next depends on [`SCHED_POLICY`]: the one
+//! after it in the task list, or the one with the smallest stride pass.
+//! An exited task lingers as a `Zombie` until its exit code is collected.
+//! While no task is `Ready`, the CPU switches to an idle loop on the boot
+//! stack that waits for interrupts with `wfi`; the kernel shuts down once no
+//! task is `Ready`, no timer is pending and nobody waits for input, as then
+//! none can ever run again. Signals are acted on by [`handle_signals`]
+//! whenever a task is about to return to user mode.
+//!
+//! Tasks are threads: each one belongs to a process, which holds what its
+//! threads share. When the main thread exits, the whole process does.
+
+mod context;
+mod pid;
+mod process;
+mod signal;
+mod switch;
+#[allow(clippy::module_inception)]
+mod task;
+
+use crate::config::{trap_cx_position, USER_STACK_SIZE};
+use crate::console::console_flush;
+use crate::drivers::uart;
+use crate::loader::get_app_data_by_name;
+use crate::mm::{copy_from_user, copy_to_user, try_translated_refmut, FaultError};
+use crate::percpu::PerCpu;
+use crate::plic::handle_external_interrupt;
+use crate::sbi::shutdown;
+use crate::sync::{futex_cancel_all, futex_key, futex_wake, UPSafeCell};
+use crate::timer::{
+    check_timer, get_time_ms, has_pending_timers, remove_timer, set_next_trigger, watchdog_pet,
+};
+use crate::trap::TrapContext;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use process::{ProcessControlBlock, ProcessControlBlockInner};
+use riscv::register::sip;
+use signal::{SignalDisposition, SignalFrame};
+use switch::switch;
+use task::TaskStatus;
+
+pub use context::TaskContext;
+pub use pid::{kernel_stack_overflow, kernel_stack_overflow_test};
+pub use signal::{SignalAction, SignalFlags, SIGKILL, SIGSEGV, SIGSTOP};
+pub use switch::switch_test;
+pub use task::TaskControlBlock;
+
+/// How the scheduler picks the next task to run
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum SchedPolicy {
+    /// Take turns in task list order
+    RoundRobin,
+    /// Run the task with the smallest pass, which advances by the task's
+    /// stride whenever it is scheduled, so the CPU share follows priority
+    Stride,
+}
+
+/// Stride scheduling, unless the `round-robin` feature is enabled
+const SCHED_POLICY: SchedPolicy = if cfg!(feature = "round-robin") {
+    SchedPolicy::RoundRobin
+} else {
+    SchedPolicy::Stride
+};
+
+/// Number of tasks in each state, as reported by `/proc/stat`
+#[derive(Copy, Clone, Default, Debug)]
+pub struct TaskCounts {
+    pub ready: usize,
+    pub running: usize,
+    pub blocked: usize,
+    pub zombie: usize,
+}
+
+/// Task manager
+pub struct TaskManager {
+    /// The tasks of each hart and which of them runs there
+    inner: PerCpu<UPSafeCell<TaskManagerInner>>,
+}
+
+struct TaskManagerInner {
+    tasks: Vec<TaskControlBlock>,
+    current_task: usize,
+    /// Context of the idle loop, which runs on the boot stack
+    idle_task_cx: TaskContext,
+}
+
+impl TaskManagerInner {
+    /// In debug builds, check that the current task is the only `Running` one
+    fn check_invariants(&self) {
+        if cfg!(debug_assertions) {
+            for (id, task) in self.tasks.iter().enumerate() {
+                assert_eq!(
+                    task.status() == TaskStatus::Running,
+                    id == self.current_task,
+                    "task {} is {:?} while task {} is current",
+                    task.tid.0,
+                    task.status(),
+                    self.tasks[self.current_task].tid.0
+                );
+            }
+        }
+    }
+}
+
+lazy_static! {
+    /// Global task manager
+    pub static ref TASK_MANAGER: TaskManager = TaskManager {
+        inner: PerCpu::new(|_| unsafe {
+            UPSafeCell::new(TaskManagerInner {
+                tasks: Vec::new(),
+                current_task: 0,
+                idle_task_cx: TaskContext::zero_init(),
+            })
+        }),
+    };
+}
+
+impl TaskManager {
+    /// Add a kernel task that starts executing at `entry`
+    fn add_task(&self, entry: fn() -> !) {
+        let task = TaskControlBlock::new_kernel(entry as usize);
+        self.inner.current().exclusive_access().tasks.push(task);
+    }
+
+    /// Add a user task running the program in `elf_data`
+    fn add_user_task(&self, elf_data: &[u8]) {
+        let task = TaskControlBlock::new_user(elf_data);
+        self.inner.current().exclusive_access().tasks.push(task);
+    }
+
+    /// Switch from the boot stack to the first task; the boot stack then
+    /// serves the idle loop
+    fn run_first_task(&self) -> ! {
+        let mut inner = self.inner.current().exclusive_access();
+        assert!(!inner.tasks.is_empty(), "No task to run!");
+        let idle_task_cx_ptr = &mut inner.idle_task_cx as *mut TaskContext;
+        drop(inner);
+        self.switch_to(idle_task_cx_ptr, 0);
+        self.idle_loop()
+    }
+
+    /// Wait for interrupts until a task becomes `Ready` and switch to it,
+    /// resuming here whenever no task is left to run. Shuts down once no
+    /// timer is pending and nobody waits for input either, as then no task
+    /// can ever wake up again.
+    ///
+    /// Interrupts stay disabled in the kernel, but a pending one still ends
+    /// `wfi`. It is handled here, so that it does not end the next one.
+    fn idle_loop(&self) -> ! {
+        loop {
+            if sip::read().stimer() {
+                set_next_trigger();
+            }
+            if sip::read().sext() {
+                handle_external_interrupt();
+            }
+            check_timer();
+            if let Some(next) = self.find_next_task() {
+                let mut inner = self.inner.current().exclusive_access();
+                let idle_task_cx_ptr = &mut inner.idle_task_cx as *mut TaskContext;
+                drop(inner);
+                self.switch_to(idle_task_cx_ptr, next);
+            } else if !has_pending_timers() && !uart::has_waiting_readers() {
+                if self.has_blocked_task() {
+                    println!("[KERNEL] Blocked tasks are left that nothing can wake up");
+                }
+                println!("[KERNEL] All tasks completed!");
+                console_flush();
+                shutdown();
+            } else {
+                unsafe { asm!("wfi") };
+            }
+        }
+    }
+
+    /// Make task `next` the current one and switch to it, saving the
+    /// context to resume into `current_task_cx_ptr`
+    fn switch_to(&self, current_task_cx_ptr: *mut TaskContext, next: usize) {
+        let mut inner = self.inner.current().exclusive_access();
+        inner.tasks[next].start_running(get_time_ms());
+        watchdog_pet();
+        inner.current_task = next;
+        inner.check_invariants();
+        let next_task_cx_ptr = &inner.tasks[next].task_cx as *const TaskContext;
+        drop(inner);
+        switch(current_task_cx_ptr, next_task_cx_ptr);
+    }
+
+    /// Change the status of the current `Running` task into `Ready`
+    fn mark_current_suspended(&self) {
+        let mut inner = self.inner.current().exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].set_status(TaskStatus::Ready);
+    }
+
+    /// Change the status of the current `Running` task into `Blocked`
+    fn mark_current_blocked(&self) {
+        let mut inner = self.inner.current().exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].set_status(TaskStatus::Blocked);
+    }
+
+    /// Make the `Blocked` task `tid` `Ready` again; other tasks are left alone
+    fn wakeup_task(&self, tid: usize) {
+        let mut inner = self.inner.current().exclusive_access();
+        if let Some(task) = inner
+            .tasks
+            .iter_mut()
+            .find(|task| task.tid.0 == tid && task.status() == TaskStatus::Blocked)
+        {
+            task.wait_token = 0;
+            task.set_status(TaskStatus::Ready);
+        }
+    }
+
+    /// Make the thread of `waiter` `Ready` again if it is still blocked in
+    /// that very wait
+    fn wakeup_waiter(&self, waiter: Waiter) {
+        let mut inner = self.inner.current().exclusive_access();
+        if let Some(task) = inner.tasks.iter_mut().find(|task| {
+            task.tid.0 == waiter.tid
+                && task.wait_token == waiter.token
+                && task.status() == TaskStatus::Blocked
+        }) {
+            task.wait_token = 0;
+            task.set_status(TaskStatus::Ready);
+        }
+    }
+
+    /// Count the tasks in each state
+    fn task_counts(&self) -> TaskCounts {
+        let inner = self.inner.current().exclusive_access();
+        let mut counts = TaskCounts::default();
+        for task in inner.tasks.iter() {
+            match task.status() {
+                TaskStatus::Ready => counts.ready += 1,
+                TaskStatus::Running => counts.running += 1,
+                TaskStatus::Blocked => counts.blocked += 1,
+                TaskStatus::Zombie => counts.zombie += 1,
+            }
+        }
+        counts
+    }
+
+    /// Turn the current `Running` task into a `Zombie`.
+    ///
+    /// If it is the main thread, or `whole_process` is set, the whole process
+    /// exits with `exit_code`: its other threads become zombies as well and
+    /// its user memory is released, while the main thread's TCB stays until
+    /// the parent reaps it. Otherwise only the current thread exits and gives
+    /// back its thread slot.
+    fn mark_current_exited(&self, exit_code: i32, whole_process: bool) {
+        let mut inner = self.inner.current().exclusive_access();
+        let current = inner.current_task;
+        inner.tasks[current].set_status(TaskStatus::Zombie);
+        cancel_waits(inner.tasks[current].tid.0);
+        if !inner.tasks[current].is_main_thread() {
+            inner.tasks[current].release_thread_slot();
+            if !whole_process {
+                return;
+            }
+        }
+        let process = inner.tasks[current].process.clone();
+        for task in inner.tasks.iter_mut() {
+            if Arc::ptr_eq(&task.process, &process) && task.status() != TaskStatus::Zombie {
+                task.set_status(TaskStatus::Zombie);
+                cancel_waits(task.tid.0);
+            }
+        }
+        let mut process = process.inner_exclusive_access();
+        process.exit_code = exit_code;
+        // The page table goes once the parent reaps the zombie
+        if let Some(memory_set) = process.memory_set.as_mut() {
+            memory_set.recycle_data_pages();
+        }
+        let fd_table = core::mem::take(&mut process.fd_table);
+        drop(process);
+        drop(inner);
+        // Close all files, e.g. so that readers of its pipes see EOF; closing
+        // a pipe wakes up its waiters, which needs the task manager
+        drop(fd_table);
+    }
+
+    /// Drop the TCBs of exited threads other than main threads, which wait
+    /// for their parent. The current task is still running on its kernel
+    /// stack, so it is kept until the next switch.
+    fn remove_exited_threads(&self) {
+        let mut inner = self.inner.current().exclusive_access();
+        let mut id = 0;
+        while id < inner.tasks.len() {
+            let task = &inner.tasks[id];
+            if id != inner.current_task
+                && task.status() == TaskStatus::Zombie
+                && !task.is_main_thread()
+            {
+                inner.tasks.remove(id);
+                if id < inner.current_task {
+                    inner.current_task -= 1;
+                }
+            } else {
+                id += 1;
+            }
+        }
+    }
+
+    /// Reap a zombie child of the current process whose pid is `pid`, or
+    /// any zombie child if `pid == -1`, returning its pid and exit code.
+    ///
+    /// Fails with -1 if no child matches and -2 if none of the matching
+    /// children has exited yet.
+    fn reap_zombie_child(&self, pid: isize) -> Result<(usize, i32), isize> {
+        let mut inner = self.inner.current().exclusive_access();
+        let current = inner.current_task;
+        let mut process = inner.tasks[current].process.inner_exclusive_access();
+        if !process
+            .children
+            .iter()
+            .any(|&child| pid == -1 || child as isize == pid)
+        {
+            return Err(-1);
+        }
+        let (idx, child) = process
+            .children
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, child)| pid == -1 || child as isize == pid)
+            .find(|&(_, child)| {
+                inner.tasks.iter().any(|task| {
+                    task.tid.0 == child
+                        && task.is_main_thread()
+                        && task.status() == TaskStatus::Zombie
+                })
+            })
+            .ok_or(-2_isize)?;
+        process.children.remove(idx);
+        drop(process);
+        // Dropping the last thread frees the page table; each thread gives
+        // back its kernel stack and tid
+        let mut exit_code = 0;
+        let (mut utime, mut stime) = (0, 0);
+        let mut id = 0;
+        while id < inner.tasks.len() {
+            if inner.tasks[id].process.pid == child {
+                let zombie = inner.tasks.remove(id);
+                if zombie.is_main_thread() {
+                    let zombie = zombie.process.inner_exclusive_access();
+                    exit_code = zombie.exit_code;
+                    utime = zombie.utime + zombie.cutime;
+                    stime = zombie.stime + zombie.cstime;
+                }
+                if id < inner.current_task {
+                    inner.current_task -= 1;
+                }
+            } else {
+                id += 1;
+            }
+        }
+        let current = inner.current_task;
+        let mut process = inner.tasks[current].process.inner_exclusive_access();
+        process.cutime += utime;
+        process.cstime += stime;
+        Ok((child, exit_code))
+    }
+
+    /// Make `signal` pending on the user process `pid`; fails if there is no
+    /// such process or it has already exited
+    fn send_signal(&self, pid: usize, signal: SignalFlags) -> bool {
+        let inner = self.inner.current().exclusive_access();
+        match inner
+            .tasks
+            .iter()
+            .find(|task| task.tid.0 == pid && task.is_main_thread())
+        {
+            Some(task) if task.status() != TaskStatus::Zombie => {
+                let mut process = task.process.inner_exclusive_access();
+                if process.memory_set.is_none() {
+                    return false;
+                }
+                process.signals |= signal;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Get the satp token of the current task's address space
+    fn get_current_token(&self) -> usize {
+        let inner = self.inner.current().exclusive_access();
+        inner.tasks[inner.current_task].get_user_token()
+    }
+
+    /// Replace the current process's image with the program in `elf_data`,
+    /// ending its other threads. Fails unless called from the main thread.
+    fn exec_current(&self, elf_data: &[u8]) -> bool {
+        let mut inner = self.inner.current().exclusive_access();
+        let current = inner.current_task;
+        if !inner.tasks[current].is_main_thread() {
+            return false;
+        }
+        let process = inner.tasks[current].process.clone();
+        for (id, task) in inner.tasks.iter_mut().enumerate() {
+            if id != current
+                && Arc::ptr_eq(&task.process, &process)
+                && task.status() != TaskStatus::Zombie
+            {
+                task.set_status(TaskStatus::Zombie);
+            }
+        }
+        inner.tasks[current].exec(elf_data);
+        true
+    }
+
+    /// Run `f` with exclusive access to the current task
+    fn with_current_task<T>(&self, f: impl FnOnce(&mut TaskControlBlock) -> T) -> T {
+        let mut inner = self.inner.current().exclusive_access();
+        let current = inner.current_task;
+        f(&mut inner.tasks[current])
+    }
+
+    /// Get the process of the current task
+    fn current_process(&self) -> Arc<ProcessControlBlock> {
+        let inner = self.inner.current().exclusive_access();
+        inner.tasks[inner.current_task].process.clone()
+    }
+
+    /// Fork the current process, returning the pid of the child
+    fn fork_current(&self) -> Option<usize> {
+        let mut inner = self.inner.current().exclusive_access();
+        let current = inner.current_task;
+        let child = inner.tasks[current].fork()?;
+        let pid = child.process.pid;
+        inner.tasks.push(child);
+        Some(pid)
+    }
+
+    /// Spawn a child of the current process running `elf_data`, returning
+    /// its pid
+    fn spawn_current(&self, elf_data: &[u8]) -> usize {
+        let mut inner = self.inner.current().exclusive_access();
+        let current = inner.current_task;
+        let child = inner.tasks[current].spawn(elf_data);
+        let pid = child.process.pid;
+        inner.tasks.push(child);
+        pid
+    }
+
+    /// Start a thread of the current process at `entry` with `arg` in a0,
+    /// returning its tid
+    fn create_thread_current(&self, entry: usize, arg: usize) -> Option<usize> {
+        let mut inner = self.inner.current().exclusive_access();
+        let current = inner.current_task;
+        let thread = inner.tasks[current].create_thread(entry, arg)?;
+        let tid = thread.tid.0;
+        inner.tasks.push(thread);
+        Some(tid)
+    }
+
+    /// Find the next `Ready` task according to [`SCHED_POLICY`]; ties go to
+    /// the first task after the current one, wrapping around
+    fn find_next_task(&self) -> Option<usize> {
+        let inner = self.inner.current().exclusive_access();
+        let current = inner.current_task;
+        let num_task = inner.tasks.len();
+        let mut ready = (current + 1..current + num_task + 1)
+            .map(|id| id % num_task)
+            .filter(|id| inner.tasks[*id].status() == TaskStatus::Ready);
+        match SCHED_POLICY {
+            SchedPolicy::RoundRobin => ready.next(),
+            // Passes may wrap around, so compare their difference
+            SchedPolicy::Stride => ready.min_by_key(|id| {
+                inner.tasks[*id]
+                    .pass
+                    .wrapping_sub(inner.tasks[current].pass) as isize
+            }),
+        }
+    }
+
+    /// Whether some task waits to be woken up
+    fn has_blocked_task(&self) -> bool {
+        let inner = self.inner.current().exclusive_access();
+        inner
+            .tasks
+            .iter()
+            .any(|task| task.status() == TaskStatus::Blocked)
+    }
+
+    /// Switch to the next `Ready` task, or to the idle loop if there is none
+    fn run_next_task(&self) {
+        self.with_current_task(|task| task.stop_running(get_time_ms()));
+        self.remove_exited_threads();
+        let mut inner = self.inner.current().exclusive_access();
+        let current = inner.current_task;
+        let current_task_cx_ptr = &mut inner.tasks[current].task_cx as *mut TaskContext;
+        drop(inner);
+        match self.find_next_task() {
+            Some(next) => self.switch_to(current_task_cx_ptr, next),
+            None => {
+                let inner = self.inner.current().exclusive_access();
+                let idle_task_cx_ptr = &inner.idle_task_cx as *const TaskContext;
+                drop(inner);
+                switch(current_task_cx_ptr, idle_task_cx_ptr);
+            }
+        }
+    }
+}
+
+/// Add a kernel task that starts executing at `entry`
+pub fn add_task(entry: fn() -> !) {
+    TASK_MANAGER.add_task(entry);
+}
+
+/// Add the `initproc` application as the first user process, which gets
+/// [`crate::config::INIT_PID`] as long as no task was added before. Fails if no
+/// application of that name is linked into the kernel.
+pub fn add_initproc() -> bool {
+    match get_app_data_by_name("initproc") {
+        Some(elf_data) => {
+            TASK_MANAGER.add_user_task(elf_data);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Get the satp token of the current task's address space
+pub fn current_user_token() -> usize {
+    TASK_MANAGER.get_current_token()
+}
+
+/// Replace the current process's image with the program in `elf_data` and
+/// pass it `args`, returning argc.
+///
+/// The strings go onto the new user stack, NUL-terminated, below the argv
+/// array of pointers to them, which ends with a null pointer. The program
+/// starts with argc in a0, argv in a1 and sp 8-byte aligned below the
+/// strings. Descriptors marked close-on-exec are closed. Fails and keeps the
+/// old image if the arguments do not fit on the user stack or the current
+/// task is not the main thread.
+pub fn exec_current(elf_data: &[u8], args: &[String]) -> Option<usize> {
+    let argv_size = (args.len() + 1) * size_of::<usize>();
+    let strings_size: usize = args.iter().map(|arg| arg.len() + 1).sum();
+    if argv_size + strings_size + size_of::<usize>() > USER_STACK_SIZE
+        || !TASK_MANAGER.exec_current(elf_data)
+    {
+        return None;
+    }
+    // Closing a pipe wakes up its waiters, so this waits for the task
+    // manager to be free again
+    with_current_process(|process| {
+        for slot in process.fd_table.iter_mut() {
+            if slot.as_ref().is_some_and(|fd| fd.cloexec) {
+                slot.take();
+            }
+        }
+    });
+    let token = current_user_token();
+    let trap_cx = current_trap_cx();
+    let argv_base = trap_cx.x[2] - argv_size;
+    let mut user_sp = argv_base;
+    let mut argv = vec![0usize; args.len() + 1];
+    for (arg, arg_ptr) in args.iter().zip(argv.iter_mut()) {
+        user_sp -= arg.len() + 1;
+        *arg_ptr = user_sp;
+        // Checked above to fit; lazy stack pages get populated on the way
+        copy_to_user(token, user_sp as *mut u8, arg.as_bytes())
+            .and_then(|_| copy_to_user(token, (user_sp + arg.len()) as *mut u8, &[0]))
+            .expect("arguments fit on the new user stack");
+    }
+    user_sp -= user_sp % size_of::<usize>();
+    let argv_bytes = unsafe {
+        core::slice::from_raw_parts(argv.as_ptr() as *const u8, argv.len() * size_of::<usize>())
+    };
+    copy_to_user(token, argv_base as *mut u8, argv_bytes)
+        .expect("arguments fit on the new user stack");
+    trap_cx.x[2] = user_sp;
+    trap_cx.x[10] = args.len();
+    trap_cx.x[11] = argv_base;
+    Some(args.len())
+}
+
+/// Get the trap context of the current user task
+pub fn current_trap_cx() -> &'static mut TrapContext {
+    with_current_task(|task| task.get_trap_cx())
+}
+
+/// Get the address the trap context of the current user task has in its
+/// address space, which depends on the thread's slot
+pub fn current_trap_cx_user_va() -> usize {
+    with_current_task(|task| trap_cx_position(task.slot))
+}
+
+/// Run `f` with exclusive access to the current task.
+///
+/// `f` must not call back into the task manager, e.g. to yield.
+pub fn with_current_task<T>(f: impl FnOnce(&mut TaskControlBlock) -> T) -> T {
+    TASK_MANAGER.with_current_task(f)
+}
+
+/// Run `f` with exclusive access to the process of the current task.
+///
+/// `f` must not call back into the task manager, e.g. to yield.
+pub fn with_current_process<T>(f: impl FnOnce(&mut ProcessControlBlockInner) -> T) -> T {
+    let process = TASK_MANAGER.current_process();
+    let mut inner = process.inner_exclusive_access();
+    f(&mut inner)
+}
+
+/// Fork the current process, returning the pid of the child
+pub fn fork_current() -> Option<usize> {
+    TASK_MANAGER.fork_current()
+}
+
+/// Spawn a child of the current process running `elf_data`, returning its pid
+pub fn spawn_current(elf_data: &[u8]) -> usize {
+    TASK_MANAGER.spawn_current(elf_data)
+}
+
+/// Start a thread of the current process at `entry` with `arg` in a0,
+/// returning its tid; fails for kernel tasks and once the process has
+/// `MAX_THREADS` threads
+pub fn create_thread_current(entry: usize, arg: usize) -> Option<usize> {
+    TASK_MANAGER.create_thread_current(entry, arg)
+}
+
+/// Reap a zombie child of the current process, see [`TaskManager::reap_zombie_child`]
+pub fn reap_zombie_child(pid: isize) -> Result<(usize, i32), isize> {
+    TASK_MANAGER.reap_zombie_child(pid)
+}
+
+/// Make `signal` pending on the user process `pid`, see [`TaskManager::send_signal`]
+pub fn send_signal(pid: usize, signal: SignalFlags) -> bool {
+    TASK_MANAGER.send_signal(pid, signal)
+}
+
+/// Act on the pending signals of the current user task before it returns
+/// to user mode. Does not return if a signal terminates the task, and only
+/// returns from a stop once the task is continued.
+pub fn handle_signals() {
+    loop {
+        match with_current_process(|process| process.next_signal()) {
+            SignalDisposition::Continue => return,
+            SignalDisposition::Terminate(signum) => {
+                println!("[KERNEL] Task killed by signal {}", signum);
+                exit_process_and_run_next(-(signum as i32));
+            }
+            SignalDisposition::Stopped => suspend_current_and_run_next(),
+            SignalDisposition::Catch(signum, action) => {
+                if !enter_signal_handler(signum, action) {
+                    error!("Failed to push the frame for signal {}", signum);
+                    exit_process_and_run_next(-(SIGSEGV as i32));
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Push a [`SignalFrame`] onto the user stack and redirect the current task
+/// to the handler in `action`, which receives `signum` in a0. Fails if the
+/// frame does not fit on the user stack.
+fn enter_signal_handler(signum: usize, action: SignalAction) -> bool {
+    let trap_cx = current_trap_cx();
+    let (token, mask) =
+        with_current_process(|process| (process.get_user_token(), process.signal_mask));
+    let frame = SignalFrame::new(trap_cx, mask);
+    let frame_size = size_of::<SignalFrame>();
+    // Keep the stack pointer 16-byte aligned as the calling convention requires
+    let frame_ptr = trap_cx.x[2].wrapping_sub(frame_size) & !0xf;
+    let frame_bytes =
+        unsafe { core::slice::from_raw_parts(&frame as *const _ as *const u8, frame_size) };
+    // Translating may resolve lazy stack pages, so the task manager must not be borrowed
+    if copy_to_user(token, frame_ptr as *mut u8, frame_bytes).is_err() {
+        return false;
+    }
+    trap_cx.x[2] = frame_ptr;
+    trap_cx.x[10] = signum;
+    trap_cx.sepc = action.handler;
+    with_current_process(|process| {
+        process.signal_mask |= action.mask | SignalFlags::from_signum(signum).unwrap();
+        process.signal_frames.push(frame_ptr);
+    });
+    true
+}
+
+/// Resume the code the innermost running signal handler interrupted, from
+/// the [`SignalFrame`] pushed by [`enter_signal_handler`], and return its a0.
+///
+/// Fails if no handler is running or its frame is no longer readable.
+pub fn leave_signal_handler() -> Option<usize> {
+    let (token, frame_ptr) = with_current_process(|process| {
+        Some((process.get_user_token(), process.signal_frames.pop()?))
+    })?;
+    let frame_size = size_of::<SignalFrame>();
+    let frame_bytes = copy_from_user(token, frame_ptr as *const u8, frame_size).ok()?;
+    // Every bit pattern is a valid frame, so it can be read back byte-wise
+    let frame: SignalFrame =
+        unsafe { core::ptr::read_unaligned(frame_bytes.as_ptr() as *const SignalFrame) };
+    // The frame sits in user memory, so only take back what user code may
+    // change anyway; sstatus stays as it is
+    let trap_cx = current_trap_cx();
+    trap_cx.x = frame.x;
+    trap_cx.sepc = frame.sepc;
+    with_current_process(|process| {
+        process.signal_mask = SignalFlags::from_bits_truncate(frame.mask.bits())
+            - (SignalFlags::SIGKILL | SignalFlags::SIGSTOP);
+    });
+    Some(trap_cx.x[10])
+}
+
+/// Return the tid of the current task if `addr` lies in the guard page below
+/// its user stack, i.e. if a fault at `addr` is a stack overflow
+pub fn current_stack_overflow(addr: usize) -> Option<usize> {
+    with_current_task(|task| task.is_stack_guard(addr).then_some(task.tid.0))
+}
+
+/// Resolve a store fault at `va` on a copy-on-write page of the current task
+pub fn handle_current_cow_fault(va: usize) -> Result<(), FaultError> {
+    with_current_process(|process| match process.memory_set.as_mut() {
+        Some(memory_set) => memory_set.handle_cow_fault(va.into()),
+        None => Err(FaultError::Genuine),
+    })
+}
+
+/// Resolve a page fault at `va` on a lazily mapped page of the current task,
+/// caused by a store if `write`
+pub fn handle_current_lazy_fault(va: usize, write: bool) -> Result<(), FaultError> {
+    with_current_process(|process| match process.memory_set.as_mut() {
+        Some(memory_set) => memory_set.handle_lazy_fault(va.into(), write),
+        None => Err(FaultError::Genuine),
+    })
+}
+
+/// Start running the first task
+pub fn run_first_task() -> ! {
+    TASK_MANAGER.run_first_task()
+}
+
+/// Suspend the current task and switch to the next one
+pub fn suspend_current_and_run_next() {
+    TASK_MANAGER.mark_current_suspended();
+    TASK_MANAGER.run_next_task();
+}
+
+/// Block the current task and switch to the next one; it runs again once
+/// woken up with [`wakeup_task`]
+pub fn block_current_and_run_next() {
+    TASK_MANAGER.mark_current_blocked();
+    TASK_MANAGER.run_next_task();
+}
+
+/// Count the tasks in each state
+pub fn task_counts() -> TaskCounts {
+    TASK_MANAGER.task_counts()
+}
+
+/// Make the blocked task `tid` ready to run again
+pub fn wakeup_task(tid: usize) {
+    TASK_MANAGER.wakeup_task(tid);
+}
+
+/// One wait of a thread, to be ended by [`wakeup_waiter`]. A wakeup that
+/// comes after the thread stopped waiting, e.g. from a wait queue it was
+/// left on when it exited, matches no wait and is ignored.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Waiter {
+    tid: usize,
+    token: usize,
+}
+
+/// Token of the next wait; tokens are never reused
+static NEXT_WAIT_TOKEN: AtomicUsize = AtomicUsize::new(1);
+
+/// Begin a new wait of the current thread, which then blocks with
+/// [`block_current_and_run_next`] until the returned waiter is woken up
+pub fn prepare_wait() -> Waiter {
+    let token = NEXT_WAIT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    with_current_task(|task| {
+        task.wait_token = token;
+        Waiter {
+            tid: task.tid.0,
+            token,
+        }
+    })
+}
+
+/// Make the thread of `waiter` ready to run again, unless it has stopped
+/// waiting in the meantime
+pub fn wakeup_waiter(waiter: Waiter) {
+    TASK_MANAGER.wakeup_waiter(waiter);
+}
+
+/// Clear the `u32` registered by `sys_set_tid_address` for the current
+/// thread, if any, and wake a thread waiting on it, e.g. in `pthread_join`.
+/// A misaligned address or one the kernel may not write is ignored.
+fn clear_child_tid() {
+    let tidptr = with_current_task(|task| task.clear_child_tid);
+    if tidptr == 0 {
+        return;
+    }
+    if tidptr % size_of::<u32>() != 0 {
+        return;
+    }
+    // Translating may resolve lazy pages, so the task manager must not be borrowed
+    let token = current_user_token();
+    if let Ok(word) = try_translated_refmut(token, tidptr as *mut u32) {
+        *word = 0;
+        // The page is writable, so the key is that of the word just cleared
+        if let Ok((key, _)) = futex_key(token, tidptr) {
+            futex_wake(key, 1);
+        }
+    }
+}
+
+/// Stop the exiting thread `tid` from waiting on a timer or futex, as a wait
+/// left behind would wake whichever thread reuses the tid
+fn cancel_waits(tid: usize) {
+    remove_timer(tid);
+    futex_cancel_all(tid);
+}
+
+/// Exit the current task with `exit_code` and switch to the next one
+pub fn exit_current_and_run_next(exit_code: i32) -> ! {
+    clear_child_tid();
+    TASK_MANAGER.mark_current_exited(exit_code, false);
+    TASK_MANAGER.run_next_task();
+    panic!("unreachable in exit_current_and_run_next!");
+}
+
+/// End the whole process of the current task with `exit_code`, even if the
+/// task is not its main thread, e.g. because of a fatal signal
+pub fn exit_process_and_run_next(exit_code: i32) -> ! {
+    clear_child_tid();
+    TASK_MANAGER.mark_current_exited(exit_code, true);
+    TASK_MANAGER.run_next_task();
+    panic!("unreachable in exit_process_and_run_next!");
+}
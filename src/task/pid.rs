@@ -0,0 +1,135 @@
+//! Process ID allocation
+
+use crate::config::{kernel_stack_position, KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE};
+use crate::mm::{MapPermission, VirtAddr, KERNEL_SPACE};
+use crate::sync::UPSafeCell;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// Hands out increasing pids and recycles freed ones first
+pub struct PidAllocator {
+    current: usize,
+    recycled: Vec<usize>,
+}
+
+impl PidAllocator {
+    /// Pid 0 is reserved for the idle task and never handed out
+    pub fn new() -> Self {
+        PidAllocator {
+            current: 1,
+            recycled: Vec::new(),
+        }
+    }
+
+    pub fn alloc(&mut self) -> PidHandle {
+        if let Some(pid) = self.recycled.pop() {
+            PidHandle(pid)
+        } else {
+            self.current += 1;
+            PidHandle(self.current - 1)
+        }
+    }
+
+    pub fn dealloc(&mut self, pid: usize) {
+        assert!(pid < self.current);
+        assert!(
+            !self.recycled.iter().any(|ppid| *ppid == pid),
+            "pid {} has been deallocated!",
+            pid
+        );
+        self.recycled.push(pid);
+    }
+}
+
+lazy_static! {
+    static ref PID_ALLOCATOR: UPSafeCell<PidAllocator> =
+        unsafe { UPSafeCell::new(PidAllocator::new()) };
+}
+
+/// RAII handle of an allocated pid, returned to the allocator on drop
+pub struct PidHandle(pub usize);
+
+impl Drop for PidHandle {
+    fn drop(&mut self) {
+        PID_ALLOCATOR.exclusive_access().dealloc(self.0);
+    }
+}
+
+/// Allocate a pid from the global allocator
+pub fn pid_alloc() -> PidHandle {
+    PID_ALLOCATOR.exclusive_access().alloc()
+}
+
+/// Kernel stack of a task, mapped into the kernel address space at a
+/// position derived from its pid and unmapped again on drop
+pub struct KernelStack {
+    pid: usize,
+}
+
+impl KernelStack {
+    pub fn new(pid_handle: &PidHandle) -> Self {
+        let pid = pid_handle.0;
+        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(pid);
+        KERNEL_SPACE.exclusive_access().insert_framed_area(
+            kernel_stack_bottom.into(),
+            kernel_stack_top.into(),
+            MapPermission::R | MapPermission::W,
+        );
+        KernelStack { pid }
+    }
+
+    /// Write `value` at the top of the stack and return a pointer to it
+    #[allow(unused)]
+    pub fn push_on_top<T>(&self, value: T) -> *mut T
+    where
+        T: Sized,
+    {
+        let kernel_stack_top = self.get_top();
+        let ptr_mut = (kernel_stack_top - core::mem::size_of::<T>()) as *mut T;
+        unsafe {
+            *ptr_mut = value;
+        }
+        ptr_mut
+    }
+
+    pub fn get_top(&self) -> usize {
+        let (_, kernel_stack_top) = kernel_stack_position(self.pid);
+        kernel_stack_top
+    }
+}
+
+/// Return the pid whose kernel stack `addr` overflowed into, i.e. the pid
+/// whose stack sits right above the guard page `addr` lies in
+pub fn kernel_stack_overflow(addr: usize) -> Option<usize> {
+    let slot_size = KERNEL_STACK_SIZE + PAGE_SIZE;
+    let pid = TRAMPOLINE.checked_sub(addr)?.checked_sub(1)? / slot_size;
+    let (kernel_stack_bottom, _) = kernel_stack_position(pid);
+    // Pid 0 is never handed out, so nothing runs on its stack
+    if pid > 0 && addr < kernel_stack_bottom && addr >= kernel_stack_bottom - PAGE_SIZE {
+        Some(pid)
+    } else {
+        None
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let (kernel_stack_bottom, _) = kernel_stack_position(self.pid);
+        let kernel_stack_bottom_va: VirtAddr = kernel_stack_bottom.into();
+        KERNEL_SPACE
+            .exclusive_access()
+            .remove_area_with_start_vpn(kernel_stack_bottom_va.into());
+    }
+}
+
+/// Classify addresses around the kernel stack of pid 1
+pub fn kernel_stack_overflow_test() {
+    let (bottom, top) = kernel_stack_position(1);
+    assert_eq!(kernel_stack_overflow(bottom - 1), Some(1));
+    assert_eq!(kernel_stack_overflow(bottom - PAGE_SIZE), Some(1));
+    assert_eq!(kernel_stack_overflow(bottom), None);
+    assert_eq!(kernel_stack_overflow(top - 1), None);
+    // The guard page of pid 1 ends where the stack of pid 2 begins
+    assert_eq!(kernel_stack_overflow(kernel_stack_position(2).1 - 1), None);
+    println!("kernel_stack_overflow_test passed!");
+}
@@ -0,0 +1,249 @@
+//! Process control block
+//!
+//! A process owns the resources its threads share: the address space, open
+//! files, the heap, signal state and its place in the process tree. Each
+//! thread has a [`TaskControlBlock`](super::TaskControlBlock) of its own.
+
+use super::signal::{
+    SignalAction, SignalActions, SignalDisposition, SignalFlags, MAX_SIG, SIGCONT, SIGKILL,
+    SIG_DFL, SIG_IGN,
+};
+use crate::config::{MAX_FD, MAX_THREADS, USER_HEAP_SIZE};
+use crate::fs::{FileDescriptor, Stdin, Stdout};
+use crate::mm::{MemorySet, VirtAddr, KERNEL_SPACE};
+use crate::sync::{Condvar, DeadlockDetector, Mutex, Semaphore, UPSafeCell};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefMut;
+
+/// Process control block
+pub struct ProcessControlBlock {
+    /// Equal to the tid of the main thread
+    pub pid: usize,
+    inner: UPSafeCell<ProcessControlBlockInner>,
+}
+
+pub struct ProcessControlBlockInner {
+    /// User address space, `None` for kernel tasks
+    pub memory_set: Option<MemorySet>,
+    /// Open files indexed by descriptor; `None` marks a free slot
+    pub fd_table: Vec<Option<FileDescriptor>>,
+    /// Start of the user heap
+    pub heap_bottom: usize,
+    /// Current program break, i.e. the end of the user heap
+    pub program_brk: usize,
+    /// Exit code reported to the parent once the process is a zombie
+    pub exit_code: i32,
+    /// `time` the threads spent in user mode
+    pub utime: usize,
+    /// `time` the threads spent in the kernel
+    pub stime: usize,
+    /// `utime` of the reaped children, including their own `cutime`
+    pub cutime: usize,
+    /// `stime` of the reaped children, including their own `cstime`
+    pub cstime: usize,
+    /// Absolute path of the current working directory
+    pub cwd: String,
+    /// Pid of the process that forked this one
+    pub parent: Option<usize>,
+    /// Pids of the forked children that have not been reaped yet
+    pub children: Vec<usize>,
+    /// Signals sent to the process but not handled yet
+    pub signals: SignalFlags,
+    /// Signals whose delivery is currently blocked
+    pub signal_mask: SignalFlags,
+    /// Registered action of each signal
+    pub signal_actions: SignalActions,
+    /// Stopped by SIGSTOP and waiting for SIGCONT
+    pub frozen: bool,
+    /// User addresses of the frames of the running signal handlers,
+    /// innermost last
+    pub signal_frames: Vec<usize>,
+    /// Tid of the thread in each slot, which fixes where the thread's trap
+    /// context and user stack live; `None` marks a free slot
+    pub thread_slots: Vec<Option<usize>>,
+    /// Mutexes indexed by id; `None` marks a free slot
+    pub mutex_list: Vec<Option<Arc<dyn Mutex>>>,
+    /// Semaphores indexed by id; `None` marks a free slot
+    pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
+    /// Condition variables indexed by id; `None` marks a free slot
+    pub condvar_list: Vec<Option<Arc<Condvar>>>,
+    /// Bookkeeping of who holds and waits for which mutex and semaphore
+    pub deadlock_detector: DeadlockDetector,
+}
+
+impl ProcessControlBlock {
+    /// Create a process `pid` without user memory whose only thread sits in
+    /// `slot`. It has standard I/O and default signal handling.
+    pub fn new_bare(pid: usize, slot: usize) -> Self {
+        let mut thread_slots = vec![None; MAX_THREADS];
+        thread_slots[slot] = Some(pid);
+        Self {
+            pid,
+            inner: unsafe {
+                UPSafeCell::new(ProcessControlBlockInner {
+                    memory_set: None,
+                    fd_table: vec![
+                        // 0 -> stdin
+                        Some(FileDescriptor::new(Arc::new(Stdin), false)),
+                        // 1 -> stdout
+                        Some(FileDescriptor::new(Arc::new(Stdout), false)),
+                        // 2 -> stderr
+                        Some(FileDescriptor::new(Arc::new(Stdout), false)),
+                    ],
+                    heap_bottom: 0,
+                    program_brk: 0,
+                    exit_code: 0,
+                    utime: 0,
+                    stime: 0,
+                    cutime: 0,
+                    cstime: 0,
+                    cwd: String::from("/"),
+                    parent: None,
+                    children: Vec::new(),
+                    signals: SignalFlags::empty(),
+                    signal_mask: SignalFlags::empty(),
+                    signal_actions: [SignalAction::default(); MAX_SIG + 1],
+                    frozen: false,
+                    signal_frames: Vec::new(),
+                    thread_slots,
+                    mutex_list: Vec::new(),
+                    semaphore_list: Vec::new(),
+                    condvar_list: Vec::new(),
+                    deadlock_detector: DeadlockDetector::default(),
+                })
+            },
+        }
+    }
+
+    /// Get exclusive access to the mutable state of the process
+    pub fn inner_exclusive_access(&self) -> RefMut<'_, ProcessControlBlockInner> {
+        self.inner.exclusive_access()
+    }
+}
+
+impl ProcessControlBlockInner {
+    /// Allocate the lowest free file descriptor, or `None` if all `MAX_FD`
+    /// are taken
+    pub fn alloc_fd(&mut self) -> Option<usize> {
+        self.alloc_fd_from(0)
+    }
+
+    /// Allocate the lowest free file descriptor not below `min_fd`, or
+    /// `None` if there is none below `MAX_FD`
+    pub fn alloc_fd_from(&mut self, min_fd: usize) -> Option<usize> {
+        if let Some(fd) = (min_fd..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
+            Some(fd)
+        } else {
+            let fd = self.fd_table.len().max(min_fd);
+            if fd >= MAX_FD {
+                return None;
+            }
+            self.fd_table.resize(fd + 1, None);
+            Some(fd)
+        }
+    }
+
+    /// Take the lowest free thread slot for thread `tid`
+    pub fn alloc_thread_slot(&mut self, tid: usize) -> Option<usize> {
+        let slot = self.thread_slots.iter().position(|slot| slot.is_none())?;
+        self.thread_slots[slot] = Some(tid);
+        Some(slot)
+    }
+
+    /// Get the satp token of the address space the process runs in
+    pub fn get_user_token(&self) -> usize {
+        match &self.memory_set {
+            Some(memory_set) => memory_set.token(),
+            None => KERNEL_SPACE.exclusive_access().token(),
+        }
+    }
+
+    /// Reset signal handling for a new program image: the old handlers are
+    /// gone along with the old image, ignored signals stay ignored
+    pub fn reset_signal_handlers(&mut self) {
+        self.signal_frames.clear();
+        for action in self.signal_actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SignalAction::default();
+            }
+        }
+    }
+
+    /// Move the program break by `size` bytes, returning the old break.
+    ///
+    /// Fails if the process has no user heap, the new break would fall
+    /// outside `[heap_bottom, heap_bottom + USER_HEAP_SIZE]`, or the pages
+    /// the heap would grow into or shrink out of are taken by a shared
+    /// segment or, when growing, by another mapping.
+    pub fn change_program_brk(&mut self, size: isize) -> Option<usize> {
+        let memory_set = self.memory_set.as_mut()?;
+        let old_break = self.program_brk;
+        let new_brk = self.program_brk as isize + size;
+        if new_brk < self.heap_bottom as isize
+            || new_brk as usize > self.heap_bottom + USER_HEAP_SIZE
+        {
+            return None;
+        }
+        // The pages the heap grows into may already be taken, e.g. by mmap
+        let grown_from: VirtAddr = VirtAddr(old_break).ceil().into();
+        if size > 0 && memory_set.overlaps(grown_from, VirtAddr(new_brk as usize)) {
+            return None;
+        }
+        if size < 0 {
+            if !memory_set.shrink_to(VirtAddr(old_break), VirtAddr(new_brk as usize)) {
+                return None;
+            }
+        } else {
+            memory_set.append_to(
+                VirtAddr(self.heap_bottom),
+                VirtAddr(old_break),
+                VirtAddr(new_brk as usize),
+            );
+        }
+        self.program_brk = new_brk as usize;
+        Some(old_break)
+    }
+
+    /// Take the next pending signal that needs acting on and decide what to do.
+    ///
+    /// SIGKILL always wins; SIGSTOP and SIGCONT only toggle `frozen`. Other
+    /// signals wait while blocked by `signal_mask`.
+    pub fn next_signal(&mut self) -> SignalDisposition {
+        if self.signals.contains(SignalFlags::SIGKILL) {
+            return SignalDisposition::Terminate(SIGKILL);
+        }
+        if self.signals.contains(SignalFlags::SIGCONT) {
+            self.signals.remove(SignalFlags::SIGCONT);
+            self.frozen = false;
+        }
+        if self.signals.contains(SignalFlags::SIGSTOP) {
+            self.signals.remove(SignalFlags::SIGSTOP);
+            self.frozen = true;
+        }
+        if self.frozen {
+            return SignalDisposition::Stopped;
+        }
+        for signum in 1..=MAX_SIG {
+            let signal = match SignalFlags::from_signum(signum) {
+                Some(signal) if self.signals.contains(signal) => signal,
+                _ => continue,
+            };
+            if self.signal_mask.contains(signal) {
+                continue;
+            }
+            self.signals.remove(signal);
+            let action = self.signal_actions[signum];
+            match action.handler {
+                // Continuing is the default action of SIGCONT, done above
+                SIG_DFL if signum == SIGCONT => {}
+                SIG_DFL => return SignalDisposition::Terminate(signum),
+                SIG_IGN => {}
+                _ => return SignalDisposition::Catch(signum, action),
+            }
+        }
+        SignalDisposition::Continue
+    }
+}
@@ -0,0 +1,111 @@
+//! POSIX signals
+//!
+//! A signal sent with `sys_kill` stays pending on the target until it next
+//! returns to user mode. SIGKILL and SIGSTOP/SIGCONT are acted on by the
+//! kernel; other signals run the handler registered with `sys_sigaction`
+//! on a [`SignalFrame`] pushed onto the user stack.
+
+use crate::trap::TrapContext;
+use bitflags::bitflags;
+
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+/// Only used as exit code, of tasks whose signal frame could not be written
+pub const SIGSEGV: usize = 11;
+/// Sent to tasks writing to a pipe without readers
+pub const SIGPIPE: usize = 13;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const MAX_SIG: usize = 31;
+
+/// Handler value selecting the default action
+pub const SIG_DFL: usize = 0;
+/// Handler value ignoring the signal
+pub const SIG_IGN: usize = 1;
+
+bitflags! {
+    /// A set of signals, with bit `n` standing for signal number `n`
+    #[derive(Copy, Clone, PartialEq, Debug, Default)]
+    pub struct SignalFlags: u32 {
+        const SIGKILL = 1 << SIGKILL;
+        const SIGUSR1 = 1 << SIGUSR1;
+        const SIGPIPE = 1 << SIGPIPE;
+        const SIGCONT = 1 << SIGCONT;
+        const SIGSTOP = 1 << SIGSTOP;
+    }
+}
+
+impl SignalFlags {
+    /// The set holding only `signum`, if the kernel supports that signal
+    pub fn from_signum(signum: usize) -> Option<Self> {
+        if signum > MAX_SIG {
+            return None;
+        }
+        Self::from_bits(1 << signum).filter(|flags| !flags.is_empty())
+    }
+}
+
+/// What to do when a signal arrives, as passed to `sys_sigaction`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SignalAction {
+    /// User address of the handler, or `SIG_DFL`/`SIG_IGN`
+    pub handler: usize,
+    /// Signals blocked while the handler runs, besides the signal itself
+    pub mask: SignalFlags,
+}
+
+impl Default for SignalAction {
+    fn default() -> Self {
+        Self {
+            handler: SIG_DFL,
+            mask: SignalFlags::empty(),
+        }
+    }
+}
+
+/// Registered action of each signal, indexed by signal number
+pub type SignalActions = [SignalAction; MAX_SIG + 1];
+
+/// Saved on the user stack while a handler runs, so that the interrupted
+/// code can be resumed afterwards
+#[repr(C)]
+pub struct SignalFrame {
+    /// General registers x0-x31 of the interrupted code
+    pub x: [usize; 32],
+    /// Where the interrupted code resumes
+    pub sepc: usize,
+    /// sstatus of the interrupted code, for the handler to inspect only
+    pub sstatus: usize,
+    /// Blocked signals before the handler was entered
+    pub mask: SignalFlags,
+    /// Makes the trailing padding explicit, so that the frame copied out to
+    /// user space holds no uninitialized bytes
+    _pad: u32,
+}
+
+impl SignalFrame {
+    /// Save the user state in `trap_cx`, leaving out its kernel fields,
+    /// which user code must not see
+    pub fn new(trap_cx: &TrapContext, mask: SignalFlags) -> Self {
+        Self {
+            x: trap_cx.x,
+            sepc: trap_cx.sepc,
+            sstatus: trap_cx.sstatus_bits(),
+            mask,
+            _pad: 0,
+        }
+    }
+}
+
+/// What the task returning to user mode has to do about its pending signals
+pub enum SignalDisposition {
+    /// Nothing left to handle, return to user mode
+    Continue,
+    /// Terminated by the signal with this number
+    Terminate(usize),
+    /// Stopped until SIGCONT arrives
+    Stopped,
+    /// Run the handler of the signal with this number
+    Catch(usize, SignalAction),
+}
@@ -0,0 +1,62 @@
+//! Wrapper around the `__switch` assembly routine
+
+use super::TaskContext;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+core::arch::global_asm!(include_str!("switch.S"));
+
+extern "C" {
+    /// Save callee-saved registers into `current_task_cx_ptr` and
+    /// resume execution from `next_task_cx_ptr`
+    fn __switch(current_task_cx_ptr: *mut TaskContext, next_task_cx_ptr: *const TaskContext);
+}
+
+/// Save the running kernel context into `current_task_cx_ptr` and resume `next_task_cx_ptr`.
+///
+/// Returns once some other context switches back into `current_task_cx_ptr`.
+/// Both pointers must stay valid until then; callers must release any
+/// `UPSafeCell` borrow before switching, since the next task may need it.
+pub fn switch(current_task_cx_ptr: *mut TaskContext, next_task_cx_ptr: *const TaskContext) {
+    unsafe {
+        __switch(current_task_cx_ptr, next_task_cx_ptr);
+    }
+}
+
+const SWITCH_TEST_STACK_SIZE: usize = 4096;
+
+#[repr(align(16))]
+struct SwitchTestStack([u8; SWITCH_TEST_STACK_SIZE]);
+
+static mut SWITCH_TEST_STACK: SwitchTestStack = SwitchTestStack([0; SWITCH_TEST_STACK_SIZE]);
+static mut SWITCH_TEST_MAIN_CX: TaskContext = TaskContext::zero_init();
+static mut SWITCH_TEST_PING_CX: TaskContext = TaskContext::zero_init();
+static SWITCH_TEST_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs on its own stack, bumping the counter once per switch into it
+fn switch_test_ping() -> ! {
+    loop {
+        SWITCH_TEST_COUNTER.fetch_add(1, Ordering::SeqCst);
+        unsafe {
+            switch(
+                core::ptr::addr_of_mut!(SWITCH_TEST_PING_CX),
+                core::ptr::addr_of!(SWITCH_TEST_MAIN_CX),
+            );
+        }
+    }
+}
+
+/// Switch back and forth between the current stack and a second kernel stack
+pub fn switch_test() {
+    unsafe {
+        let stack_top = core::ptr::addr_of!(SWITCH_TEST_STACK) as usize + SWITCH_TEST_STACK_SIZE;
+        SWITCH_TEST_PING_CX = TaskContext::goto_entry(switch_test_ping as usize, stack_top);
+        for round in 1..=2 {
+            switch(
+                core::ptr::addr_of_mut!(SWITCH_TEST_MAIN_CX),
+                core::ptr::addr_of!(SWITCH_TEST_PING_CX),
+            );
+            assert_eq!(SWITCH_TEST_COUNTER.load(Ordering::SeqCst), round);
+        }
+    }
+    println!("switch_test passed!");
+}
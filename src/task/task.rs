@@ -0,0 +1,401 @@
+//! Task control block
+//!
+//! A task is a thread of a [`ProcessControlBlock`]; kernel tasks are the only
+//! thread of a process without user memory.
+
+use super::pid::{pid_alloc, KernelStack, PidHandle};
+use super::process::ProcessControlBlock;
+use super::TaskContext;
+use crate::config::{
+    thread_stack_position, trap_cx_position, BIG_STRIDE, DEFAULT_PRIORITY, PAGE_SIZE,
+    USER_STACK_SIZE,
+};
+use crate::mm::{MapPermission, MemorySet, VirtAddr, KERNEL_SPACE};
+use crate::sync::DeadlockDetector;
+use crate::timer::get_time;
+use crate::trap::{trap_handler, TrapContext};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+/// Task control block
+pub struct TaskControlBlock {
+    /// Thread id, drawn from the same allocator as pids; the main thread's
+    /// tid is the pid of its process
+    pub tid: PidHandle,
+    /// The process the thread belongs to
+    pub process: Arc<ProcessControlBlock>,
+    /// Index of the thread's slot in the process, see
+    /// [`trap_cx_position`] and [`thread_stack_position`]
+    pub slot: usize,
+    /// Only changed through [`TaskControlBlock::set_status`]
+    task_status: TaskStatus,
+    pub task_cx: TaskContext,
+    /// Scheduling priority, at least 2; the CPU share grows with it
+    pub priority: usize,
+    /// How far `pass` advances each time the task is scheduled,
+    /// `BIG_STRIDE / priority`
+    pub stride: usize,
+    /// The stride scheduler runs the ready task with the smallest pass
+    pub pass: usize,
+    /// The task's kernel stack, which `trap_handler` runs on
+    pub kernel_stack: KernelStack,
+    /// Top of the task's user stack, 0 for kernel tasks. The page below
+    /// the stack is left unmapped to catch overflows.
+    pub user_stack_top: usize,
+    /// Number of times the task was scheduled
+    pub run_count: usize,
+    /// Time the task spent running before it was last scheduled, in ms
+    pub run_time: usize,
+    /// When the task was first scheduled, in ms since boot
+    pub first_run_time: Option<usize>,
+    /// When the task was last scheduled, in ms since boot
+    pub last_run_time: usize,
+    /// Number of invocations of each syscall below `MAX_SYSCALL_NUM`, for
+    /// the syscalls the task made at all
+    pub syscall_times: BTreeMap<usize, u32>,
+    /// `time` when the task last entered or left the kernel or was scheduled
+    pub mode_time: usize,
+    /// User address of a `u32` to clear and wake futex waiters on when the
+    /// thread exits, 0 for none; set by `sys_set_tid_address`
+    pub clear_child_tid: usize,
+    /// Token of the wait begun by the last [`prepare_wait`], 0 once the
+    /// thread has been woken up
+    ///
+    /// [`prepare_wait`]: super::prepare_wait
+    pub wait_token: usize,
+}
+
+impl TaskControlBlock {
+    /// Create the main thread of a new process without user memory, with a
+    /// fresh pid and kernel stack. It resumes from the context `make_task_cx`
+    /// builds for the top of that stack and has default scheduling.
+    fn new_bare(make_task_cx: impl FnOnce(usize) -> TaskContext) -> Self {
+        let tid = pid_alloc();
+        let process = Arc::new(ProcessControlBlock::new_bare(tid.0, 0));
+        Self::new_thread(tid, process, 0, make_task_cx)
+    }
+
+    /// Create the thread `tid` of `process` in `slot`, with a fresh kernel stack
+    fn new_thread(
+        tid: PidHandle,
+        process: Arc<ProcessControlBlock>,
+        slot: usize,
+        make_task_cx: impl FnOnce(usize) -> TaskContext,
+    ) -> Self {
+        let kernel_stack = KernelStack::new(&tid);
+        let kernel_stack_top = kernel_stack.get_top();
+        Self {
+            tid,
+            process,
+            slot,
+            task_status: TaskStatus::Ready,
+            task_cx: make_task_cx(kernel_stack_top),
+            priority: DEFAULT_PRIORITY,
+            stride: BIG_STRIDE / DEFAULT_PRIORITY,
+            pass: 0,
+            kernel_stack,
+            user_stack_top: 0,
+            run_count: 0,
+            run_time: 0,
+            first_run_time: None,
+            last_run_time: 0,
+            syscall_times: BTreeMap::new(),
+            mode_time: 0,
+            clear_child_tid: 0,
+            wait_token: 0,
+        }
+    }
+
+    /// Create a kernel task that starts executing at `entry`
+    pub fn new_kernel(entry: usize) -> Self {
+        Self::new_bare(|kernel_stack_top| TaskContext::goto_entry(entry, kernel_stack_top))
+    }
+
+    /// Create the main thread of a new process running the program in
+    /// `elf_data` from its entry point, with standard I/O and no parent
+    pub fn new_user(elf_data: &[u8]) -> Self {
+        let mut task = Self::new_bare(TaskContext::goto_trap_return);
+        task.exec(elf_data);
+        task
+    }
+
+    /// Whether this is the main thread, whose exit ends the whole process
+    pub fn is_main_thread(&self) -> bool {
+        self.tid.0 == self.process.pid
+    }
+
+    /// Get the scheduling state of the task
+    pub fn status(&self) -> TaskStatus {
+        self.task_status
+    }
+
+    /// Move the task into the scheduling state `next`. Debug builds check
+    /// that the transition is legal, e.g. that zombies never run again.
+    pub fn set_status(&mut self, next: TaskStatus) {
+        debug_assert!(
+            self.task_status.can_become(next),
+            "illegal transition of task {} from {:?} to {:?}",
+            self.tid.0,
+            self.task_status,
+            next
+        );
+        self.task_status = next;
+    }
+
+    /// Make the task `Running` at `now` ms and advance its pass
+    pub fn start_running(&mut self, now: usize) {
+        self.set_status(TaskStatus::Running);
+        self.pass = self.pass.wrapping_add(self.stride);
+        self.run_count += 1;
+        self.first_run_time.get_or_insert(now);
+        self.last_run_time = now;
+        self.mode_time = get_time();
+    }
+
+    /// Account for the task being switched away from at `now` ms
+    pub fn stop_running(&mut self, now: usize) {
+        self.run_time += now - self.last_run_time;
+        self.charge_time(false);
+    }
+
+    /// Charge the `time` since `mode_time` to the process, as user time if
+    /// `user` and as kernel time otherwise, and restart the count
+    pub fn charge_time(&mut self, user: bool) {
+        let now = get_time();
+        let mut process = self.process.inner_exclusive_access();
+        if user {
+            process.utime += now - self.mode_time;
+        } else {
+            process.stime += now - self.mode_time;
+        }
+        self.mode_time = now;
+    }
+
+    /// Set the scheduling priority, which must be at least 2 so that strides
+    /// stay below `BIG_STRIDE / 2` and passes compare correctly on overflow
+    pub fn set_priority(&mut self, priority: usize) {
+        assert!(priority >= 2, "priority {} is below 2", priority);
+        self.priority = priority;
+        self.stride = BIG_STRIDE / priority;
+    }
+
+    /// Whether `addr` lies in the guard page below the task's user stack
+    pub fn is_stack_guard(&self, addr: usize) -> bool {
+        let stack_bottom = self.user_stack_top.wrapping_sub(USER_STACK_SIZE);
+        self.user_stack_top != 0 && addr < stack_bottom && addr >= stack_bottom - PAGE_SIZE
+    }
+
+    /// Get the satp token of the address space the task runs in
+    pub fn get_user_token(&self) -> usize {
+        self.process.inner_exclusive_access().get_user_token()
+    }
+
+    /// Get the trap context in the task's trap context page, accessed
+    /// through its physical address; kernel tasks have none
+    pub fn get_trap_cx(&self) -> &'static mut TrapContext {
+        let process = self.process.inner_exclusive_access();
+        let memory_set = process
+            .memory_set
+            .as_ref()
+            .expect("kernel tasks have no trap context");
+        let trap_cx_va: VirtAddr = trap_cx_position(self.slot).into();
+        let trap_cx_ppn = memory_set.translate(trap_cx_va.into()).unwrap().ppn();
+        trap_cx_ppn.get_mut()
+    }
+
+    /// Create a thread of this task's process that starts at `entry` with
+    /// `arg` in a0, on a user stack and trap context page of its own.
+    ///
+    /// Returns `None` for kernel tasks and once all thread slots are taken.
+    pub fn create_thread(&self, entry: usize, arg: usize) -> Option<Self> {
+        let tid = pid_alloc();
+        let slot = {
+            let mut process = self.process.inner_exclusive_access();
+            process.memory_set.as_ref()?;
+            let slot = process.alloc_thread_slot(tid.0)?;
+            let memory_set = process.memory_set.as_mut().unwrap();
+            let trap_cx_bottom = trap_cx_position(slot);
+            memory_set.insert_framed_area(
+                trap_cx_bottom.into(),
+                (trap_cx_bottom + PAGE_SIZE).into(),
+                MapPermission::R | MapPermission::W,
+            );
+            let (stack_bottom, stack_top) = thread_stack_position(slot);
+            memory_set.insert_lazy_area(
+                stack_bottom.into(),
+                stack_top.into(),
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            );
+            slot
+        };
+        let mut thread = Self::new_thread(
+            tid,
+            self.process.clone(),
+            slot,
+            TaskContext::goto_trap_return,
+        );
+        thread.set_priority(self.priority);
+        // Start level with the creator instead of hogging the CPU to catch up
+        thread.pass = self.pass;
+        thread.user_stack_top = thread_stack_position(slot).1;
+        let trap_cx = thread.get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry,
+            thread_stack_position(slot).1,
+            KERNEL_SPACE.exclusive_access().token(),
+            thread.kernel_stack.get_top(),
+            trap_handler as usize,
+        );
+        trap_cx.x[10] = arg;
+        Some(thread)
+    }
+
+    /// Free the user stack and trap context page of this thread after it
+    /// exited, so that its slot can be reused; the main thread's user stack
+    /// is part of the program image and stays
+    pub fn release_thread_slot(&self) {
+        let mut process = self.process.inner_exclusive_access();
+        process.thread_slots[self.slot] = None;
+        process.deadlock_detector.remove_thread(self.slot);
+        if let Some(memory_set) = process.memory_set.as_mut() {
+            release_slot_areas(memory_set, self.slot);
+        }
+    }
+
+    /// Create a child process sharing this process's user pages
+    /// copy-on-write, with this thread as its main and only thread.
+    ///
+    /// The child resumes from the same trap context with a return value of 0.
+    /// Returns `None` for kernel tasks, which have no user image to copy.
+    /// The child is linked to this process as its parent.
+    pub fn fork(&self) -> Option<Self> {
+        let mut parent = self.process.inner_exclusive_access();
+        let mut memory_set = MemorySet::from_existed_user_cow(parent.memory_set.as_mut()?);
+        // The other threads do not live on in the child
+        for (slot, tid) in parent.thread_slots.iter().enumerate() {
+            if slot != self.slot && tid.is_some() {
+                release_slot_areas(&mut memory_set, slot);
+            }
+        }
+        let tid = pid_alloc();
+        let process = Arc::new(ProcessControlBlock::new_bare(tid.0, self.slot));
+        {
+            let mut child = process.inner_exclusive_access();
+            child.memory_set = Some(memory_set);
+            // Both processes refer to the same open files, e.g. pipe ends,
+            // which stay open until neither of them has a descriptor left
+            child.fd_table = parent.fd_table.clone();
+            child.heap_bottom = parent.heap_bottom;
+            child.program_brk = parent.program_brk;
+            child.cwd = parent.cwd.clone();
+            child.parent = Some(self.process.pid);
+            // Pending signals are not inherited, registered actions are
+            child.signal_mask = parent.signal_mask;
+            child.signal_actions = parent.signal_actions;
+            // The child's stack holds copies of the frames
+            child.signal_frames = parent.signal_frames.clone();
+        }
+        parent.children.push(tid.0);
+        drop(parent);
+        let mut child = Self::new_thread(tid, process, self.slot, TaskContext::goto_trap_return);
+        child.set_priority(self.priority);
+        // Start level with the parent instead of hogging the CPU to catch up
+        child.pass = self.pass;
+        child.user_stack_top = self.user_stack_top;
+        // The trap context page was copied along with the address space
+        let trap_cx = child.get_trap_cx();
+        trap_cx.kernel_sp = child.kernel_stack.get_top();
+        trap_cx.x[10] = 0;
+        Some(child)
+    }
+
+    /// Create a child process running the program in `elf_data` from its
+    /// entry point. Unlike [`TaskControlBlock::fork`] nothing of this
+    /// process's address space is copied; the child only gets standard I/O
+    /// and the working directory.
+    /// The child is linked to this process as its parent.
+    pub fn spawn(&self, elf_data: &[u8]) -> Self {
+        let mut child = Self::new_user(elf_data);
+        {
+            let mut child_process = child.process.inner_exclusive_access();
+            child_process.parent = Some(self.process.pid);
+            child_process.cwd = self.process.inner_exclusive_access().cwd.clone();
+        }
+        // Start level with the parent instead of hogging the CPU to catch up
+        child.pass = self.pass;
+        self.process
+            .inner_exclusive_access()
+            .children
+            .push(child.process.pid);
+        child
+    }
+
+    /// Replace the process image with the program in `elf_data`, making this
+    /// thread the only one, in slot 0; it resumes at the new entry point when
+    /// it returns to user mode. The other threads must have been stopped.
+    pub fn exec(&mut self, elf_data: &[u8]) {
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        {
+            let mut process = self.process.inner_exclusive_access();
+            process.memory_set = Some(memory_set);
+            // from_elf places the empty heap right above the user stack
+            process.heap_bottom = user_sp;
+            process.program_brk = user_sp;
+            process.reset_signal_handlers();
+            process.thread_slots.fill(None);
+            process.thread_slots[0] = Some(self.tid.0);
+            // Ids of the old image's locks mean nothing to the new one
+            process.mutex_list.clear();
+            process.semaphore_list.clear();
+            process.condvar_list.clear();
+            process.deadlock_detector = DeadlockDetector::default();
+        }
+        self.slot = 0;
+        self.user_stack_top = user_sp;
+        *self.get_trap_cx() = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            self.kernel_stack.get_top(),
+            trap_handler as usize,
+        );
+    }
+}
+
+/// Unmap the trap context page and thread stack of `slot`, as far as they
+/// are mapped in `memory_set`
+fn release_slot_areas(memory_set: &mut MemorySet, slot: usize) {
+    let trap_cx_bottom = trap_cx_position(slot);
+    memory_set.remove_area(trap_cx_bottom.into(), (trap_cx_bottom + PAGE_SIZE).into());
+    let (stack_bottom, stack_top) = thread_stack_position(slot);
+    memory_set.remove_area(stack_bottom.into(), stack_top.into());
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// Scheduling state of a task
+pub enum TaskStatus {
+    Ready,
+    Running,
+    /// Waiting for an event, e.g. a timer, before it may run again
+    Blocked,
+    /// Exited. A main thread stays until the exit code of its process has
+    /// been collected, other threads are dropped by the scheduler.
+    Zombie,
+}
+
+impl TaskStatus {
+    /// Whether a task may go from this state straight to `next`
+    pub fn can_become(self, next: TaskStatus) -> bool {
+        matches!(
+            (self, next),
+            (TaskStatus::Ready, TaskStatus::Running)
+                | (TaskStatus::Running, TaskStatus::Ready)
+                | (TaskStatus::Running, TaskStatus::Blocked)
+                | (TaskStatus::Running, TaskStatus::Zombie)
+                | (TaskStatus::Blocked, TaskStatus::Ready)
+                // The other threads of an exiting process end wherever they are
+                | (TaskStatus::Ready, TaskStatus::Zombie)
+                | (TaskStatus::Blocked, TaskStatus::Zombie)
+        )
+    }
+}
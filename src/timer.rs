@@ -0,0 +1,197 @@
+//! Timer subsystem built on the `time` CSR
+//!
+//! Timer interrupts are armed through the backend selected by
+//! [`TIMER_BACKEND`]: the SBI firmware, or the `stimecmp` CSR of the Sstc
+//! extension directly. Sleeping tasks wait in a queue ordered by wake-up
+//! time, which [`check_timer`] drains on every tick.
+//!
+//! With [`WATCHDOG_TIMEOUT_TICKS`] set, [`check_timer`] also watches the
+//! scheduler: if no task was switched to for that long while no sleeping task
+//! is due to wake up either, the kernel hangs, and the watchdog reboots it.
+
+use crate::config::{CLOCK_FREQ, TICKS_PER_SEC, WATCHDOG_TIMEOUT_TICKS};
+use crate::console::console_flush;
+use crate::sbi::{reboot_warm, set_timer};
+use crate::sync::UPSafeCell;
+use crate::task::wakeup_task;
+use alloc::collections::BinaryHeap;
+use core::arch::asm;
+use core::cmp::Ordering;
+use core::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use lazy_static::*;
+use riscv::register::{sie, time};
+
+const MSEC_PER_SEC: usize = 1000;
+const USEC_PER_SEC: usize = 1_000_000;
+const NSEC_PER_SEC: usize = 1_000_000_000;
+
+/// How timer interrupts are programmed
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum TimerBackend {
+    /// Ask the SBI firmware through `set_timer`
+    Sbi,
+    /// Write the `stimecmp` CSR, which raises the supervisor timer interrupt
+    /// without a call into the firmware. Only works on harts with the Sstc
+    /// extension whose firmware has set `menvcfg.STCE`.
+    Sstc,
+}
+
+/// The SBI firmware, unless the `sstc-timer` feature is enabled
+const TIMER_BACKEND: TimerBackend = if cfg!(feature = "sstc-timer") {
+    TimerBackend::Sstc
+} else {
+    TimerBackend::Sbi
+};
+
+/// Read the current value of the `time` counter
+pub fn get_time() -> usize {
+    time::read()
+}
+
+/// Get the time elapsed since boot in milliseconds
+pub fn get_time_ms() -> usize {
+    time::read() / (CLOCK_FREQ / MSEC_PER_SEC)
+}
+
+/// Get the time elapsed since boot in microseconds
+pub fn get_time_us() -> usize {
+    let ticks = time::read();
+    ticks / CLOCK_FREQ * USEC_PER_SEC + ticks % CLOCK_FREQ * USEC_PER_SEC / CLOCK_FREQ
+}
+
+/// Get the time elapsed since boot in nanoseconds, as precise as the
+/// `time` counter frequency allows
+pub fn get_time_ns() -> usize {
+    let ticks = time::read();
+    ticks / CLOCK_FREQ * NSEC_PER_SEC + ticks % CLOCK_FREQ * NSEC_PER_SEC / CLOCK_FREQ
+}
+
+/// Enable timer interrupts for the selected backend
+pub fn enable_timer_interrupt() {
+    // Both backends deliver a supervisor timer interrupt
+    unsafe {
+        sie::set_stimer();
+    }
+}
+
+/// Raise a timer interrupt once `time` reaches `deadline`
+fn set_deadline(deadline: usize) {
+    match TIMER_BACKEND {
+        TimerBackend::Sbi => set_timer(deadline).expect("failed to arm the timer"),
+        // stimecmp, which the toolchain's assembler does not know by name
+        TimerBackend::Sstc => unsafe { asm!("csrw 0x14d, {}", in(reg) deadline) },
+    }
+}
+
+/// Program the next timer interrupt one tick from now
+pub fn set_next_trigger() {
+    set_deadline(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
+}
+
+/// A task sleeping until `expire_ms`
+struct TimerCondVar {
+    expire_ms: usize,
+    pid: usize,
+}
+
+impl PartialEq for TimerCondVar {
+    fn eq(&self, other: &Self) -> bool {
+        self.expire_ms == other.expire_ms
+    }
+}
+
+impl Eq for TimerCondVar {}
+
+impl PartialOrd for TimerCondVar {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TimerCondVar {
+    /// Reversed, so that the max-heap yields the earliest deadline first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.expire_ms.cmp(&self.expire_ms)
+    }
+}
+
+lazy_static! {
+    /// Sleeping tasks, earliest deadline on top
+    static ref TIMERS: UPSafeCell<BinaryHeap<TimerCondVar>> =
+        unsafe { UPSafeCell::new(BinaryHeap::new()) };
+}
+
+/// Wake the task `pid` once `get_time_ms()` reaches `expire_ms`
+pub fn add_timer(expire_ms: usize, pid: usize) {
+    TIMERS
+        .exclusive_access()
+        .push(TimerCondVar { expire_ms, pid });
+}
+
+/// Stop waking the task `pid` at its deadline, e.g. because something else
+/// woke it up first
+pub fn remove_timer(pid: usize) {
+    TIMERS.exclusive_access().retain(|timer| timer.pid != pid);
+}
+
+/// Whether some task sleeps until a deadline
+pub fn has_pending_timers() -> bool {
+    !TIMERS.exclusive_access().is_empty()
+}
+
+/// Wake every task whose deadline has passed, and check the watchdog
+pub fn check_timer() {
+    let current_ms = get_time_ms();
+    let mut timers = TIMERS.exclusive_access();
+    while let Some(timer) = timers.peek() {
+        if timer.expire_ms > current_ms {
+            break;
+        }
+        wakeup_task(timer.pid);
+        timers.pop();
+    }
+    // Waiting for a sleeping task to wake up is no hang
+    if !timers.is_empty() {
+        watchdog_pet();
+    }
+    drop(timers);
+    watchdog_check();
+}
+
+/// Number of times the watchdog was petted, only ever increasing
+static WATCHDOG_COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// Value of `WATCHDOG_COUNTER` at the last check
+static WATCHDOG_LAST_COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// Value of `time` when `WATCHDOG_COUNTER` was last seen to change
+static WATCHDOG_LAST_CHANGE: AtomicUsize = AtomicUsize::new(0);
+
+/// Tell the watchdog that the kernel makes progress; the scheduler does so
+/// on every switch
+pub fn watchdog_pet() {
+    WATCHDOG_COUNTER.fetch_add(1, AtomicOrdering::Relaxed);
+}
+
+/// Log a hang and reboot if the watchdog was not petted for
+/// `WATCHDOG_TIMEOUT_TICKS` ticks
+fn watchdog_check() {
+    let timeout_ticks = match WATCHDOG_TIMEOUT_TICKS {
+        Some(timeout_ticks) => timeout_ticks,
+        None => return,
+    };
+    let now = get_time();
+    let counter = WATCHDOG_COUNTER.load(AtomicOrdering::Relaxed);
+    if WATCHDOG_LAST_COUNTER.swap(counter, AtomicOrdering::Relaxed) != counter {
+        WATCHDOG_LAST_CHANGE.store(now, AtomicOrdering::Relaxed);
+        return;
+    }
+    let idle_ticks =
+        (now - WATCHDOG_LAST_CHANGE.load(AtomicOrdering::Relaxed)) / (CLOCK_FREQ / TICKS_PER_SEC);
+    if idle_ticks >= timeout_ticks {
+        error!(
+            "watchdog: kernel hang, nothing scheduled for {} ticks; rebooting",
+            idle_ticks
+        );
+        console_flush();
+        reboot_warm();
+    }
+}
@@ -1,9 +1,17 @@
 //! Trap context for saving registers
 
+use crate::percpu::hart_id;
+use core::mem::transmute;
 use riscv::register::sstatus::{self, Sstatus, SPP};
 
+/// sstatus.SPIE: interrupt enable restored by `sret`
+const SSTATUS_SPIE: usize = 1 << 5;
+/// sstatus.SPP: privilege mode `sret` returns to, clear for user mode
+const SSTATUS_SPP: usize = 1 << 8;
+
 #[repr(C)]
-/// Trap context saved on kernel stack
+#[derive(Clone, Copy)]
+/// Trap context saved in the task's trap context page
 pub struct TrapContext {
     /// General registers x0-x31
     pub x: [usize; 32],
@@ -11,24 +19,62 @@ pub struct TrapContext {
     pub sstatus: Sstatus,
     /// Supervisor exception program counter
     pub sepc: usize,
+    /// satp token of the kernel address space
+    pub kernel_satp: usize,
+    /// Top of the task's kernel stack
+    pub kernel_sp: usize,
+    /// Virtual address of `trap_handler` in the kernel address space
+    pub trap_handler: usize,
+    /// Id of the hart the task runs on, which traps load into tp
+    pub kernel_tp: usize,
 }
 
 impl TrapContext {
-    /// Create an empty trap context
-    pub fn app_init_context(entry: usize, sp: usize) -> Self {
-        let sstatus = sstatus::read();
-        // Note: set_spp is not available in riscv 0.10, we'll manually set bits if needed
+    /// Create the trap context a user task starts from
+    pub fn app_init_context(
+        entry: usize,
+        sp: usize,
+        kernel_satp: usize,
+        kernel_sp: usize,
+        trap_handler: usize,
+    ) -> Self {
+        // riscv 0.10 can only set SPP on the live CSR, so patch the bits of
+        // a copy instead; Sstatus is a plain wrapper around the raw value
+        let mut bits: usize = unsafe { transmute(sstatus::read()) };
+        bits &= !SSTATUS_SPP;
+        bits |= SSTATUS_SPIE;
+        let sstatus: Sstatus = unsafe { transmute(bits) };
         let mut cx = Self {
             x: [0; 32],
             sstatus,
             sepc: entry,
+            kernel_satp,
+            kernel_sp,
+            trap_handler,
+            kernel_tp: hart_id(),
         };
         cx.set_sp(sp);
         cx
     }
 
+    /// Raw value of the saved sstatus, which riscv 0.10 does not expose
+    pub fn sstatus_bits(&self) -> usize {
+        unsafe { transmute(self.sstatus) }
+    }
+
     /// Set stack pointer
     pub fn set_sp(&mut self, sp: usize) {
         self.x[2] = sp;
     }
 }
+
+/// Check that a fresh user context `sret`s into U-mode with interrupts enabled
+pub fn app_init_context_test() {
+    let live_spp = sstatus::read().spp();
+    let cx = TrapContext::app_init_context(0x1000, 0x2000, 0, 0, 0);
+    assert_eq!(cx.sstatus.spp(), SPP::User);
+    assert!(cx.sstatus.spie());
+    // The live sstatus is left alone
+    assert_eq!(sstatus::read().spp(), live_spp);
+    println!("app_init_context_test passed!");
+}
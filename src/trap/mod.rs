@@ -2,60 +2,110 @@
 
 mod context;
 
-pub use context::TrapContext;
+pub use context::{app_init_context_test, TrapContext};
 
+use crate::config::TRAMPOLINE;
+use crate::mm::FaultError;
+use crate::plic::handle_external_interrupt;
 use crate::syscall::syscall;
+use crate::task::{
+    current_stack_overflow, current_trap_cx, current_trap_cx_user_va, current_user_token,
+    exit_process_and_run_next, handle_current_cow_fault, handle_current_lazy_fault, handle_signals,
+    kernel_stack_overflow, suspend_current_and_run_next, with_current_task, SIGKILL,
+};
+use crate::timer::{check_timer, set_next_trigger};
+use core::arch::{asm, global_asm};
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
-    sie, stval, stvec,
+    stval, stvec,
 };
 
-core::arch::global_asm!(include_str!("trap.S"));
+global_asm!(include_str!("trap.S"));
 
 /// Initialize trap handling
 pub fn init() {
+    set_kernel_trap_entry();
+    info!(
+        "Kernel trap vector installed at {:#x}",
+        trap_from_kernel as usize
+    );
+}
+
+/// Traps taken while in the kernel go to [`trap_from_kernel`] through
+/// `__kernel_trap`, which switches to a stack of its own first
+fn set_kernel_trap_entry() {
     extern "C" {
-        fn __alltraps();
+        fn __kernel_trap();
     }
     unsafe {
-        stvec::write(__alltraps as usize, TrapMode::Direct);
+        stvec::write(__kernel_trap as usize, TrapMode::Direct);
     }
 }
 
-/// Enable timer interrupt
-pub fn enable_timer_interrupt() {
+/// Traps taken in user mode enter `__alltraps` through the trampoline
+fn set_user_trap_entry() {
     unsafe {
-        sie::set_stimer();
+        stvec::write(TRAMPOLINE, TrapMode::Direct);
     }
 }
 
 #[no_mangle]
-/// Handle trap from user/kernel
-pub fn trap_handler(cx: &mut TrapContext) -> &mut TrapContext {
+/// Handle a trap from user mode, reached from `__alltraps` on the kernel
+/// page table and the task's kernel stack
+pub fn trap_handler() -> ! {
+    set_kernel_trap_entry();
+    with_current_task(|task| task.charge_time(true));
     let scause = scause::read();
     let stval = stval::read();
     match scause.cause() {
         Trap::Exception(Exception::UserEnvCall) => {
+            let mut cx = current_trap_cx();
             cx.sepc += 4;
-            cx.x[10] = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]) as usize;
+            let result = syscall(
+                cx.x[17],
+                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+            );
+            // exec replaces the trap context page, so look it up again
+            cx = current_trap_cx();
+            cx.x[10] = result as usize;
+        }
+        Trap::Exception(Exception::LoadPageFault) if resolve_page_fault(stval, false) => {
+            // Lazily mapped page populated, retry the load
+        }
+        Trap::Exception(Exception::StorePageFault) if resolve_page_fault(stval, true) => {
+            // Copy-on-write page duplicated or lazy page populated, retry
         }
         Trap::Exception(Exception::StoreFault)
         | Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::LoadFault)
         | Trap::Exception(Exception::LoadPageFault) => {
-            println!(
-                "[KERNEL] Page fault at {:#x}, bad addr = {:#x}",
-                cx.sepc, stval
+            if let Some(tid) = current_stack_overflow(stval) {
+                error!(
+                    "Stack overflow at {:#x}, bad addr = {:#x}",
+                    current_trap_cx().sepc,
+                    stval
+                );
+                panic!("stack overflow in task {}", tid);
+            }
+            error!(
+                "Page fault at {:#x}, bad addr = {:#x}",
+                current_trap_cx().sepc,
+                stval
             );
             panic!("Page fault!");
         }
         Trap::Exception(Exception::IllegalInstruction) => {
-            println!("[KERNEL] Illegal instruction at {:#x}", cx.sepc);
+            error!("Illegal instruction at {:#x}", current_trap_cx().sepc);
             panic!("Illegal instruction!");
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
-            println!("[KERNEL] Timer interrupt");
+            set_next_trigger();
+            check_timer();
+            suspend_current_and_run_next();
+        }
+        Trap::Interrupt(Interrupt::SupervisorExternal) => {
+            handle_external_interrupt();
         }
         _ => {
             panic!(
@@ -65,5 +115,72 @@ pub fn trap_handler(cx: &mut TrapContext) -> &mut TrapContext {
             );
         }
     }
-    cx
+    handle_signals();
+    trap_return()
+}
+
+/// Try to resolve a user page fault at `va` as copy-on-write (stores only)
+/// or demand paging; false if the fault is genuine. A process that faults
+/// when no frame is left is killed, as there is nothing to retry with.
+fn resolve_page_fault(va: usize, write: bool) -> bool {
+    let mut result = Err(FaultError::Genuine);
+    if write {
+        result = handle_current_cow_fault(va);
+    }
+    if result == Err(FaultError::Genuine) {
+        result = handle_current_lazy_fault(va, write);
+    }
+    match result {
+        Ok(()) => true,
+        Err(FaultError::Genuine) => false,
+        Err(FaultError::OutOfMemory) => {
+            error!(
+                "Out of memory on page fault at {:#x}, bad addr = {:#x}, killed",
+                current_trap_cx().sepc,
+                va
+            );
+            exit_process_and_run_next(-(SIGKILL as i32))
+        }
+    }
+}
+
+#[no_mangle]
+/// Return to user mode through `__restore` in the trampoline, switching to
+/// the current task's address space
+pub fn trap_return() -> ! {
+    set_user_trap_entry();
+    with_current_task(|task| task.charge_time(false));
+    let trap_cx_ptr = current_trap_cx_user_va();
+    let user_satp = current_user_token();
+    extern "C" {
+        fn __alltraps();
+        fn __restore();
+    }
+    let restore_va = __restore as usize - __alltraps as usize + TRAMPOLINE;
+    unsafe {
+        asm!(
+            "fence.i",
+            "jr {restore_va}",
+            restore_va = in(reg) restore_va,
+            in("a0") trap_cx_ptr,
+            in("a1") user_satp,
+            options(noreturn)
+        );
+    }
+}
+
+#[no_mangle]
+/// Traps from kernel mode are not expected; page faults in the guard page
+/// below a kernel stack are reported as overflows of that stack
+pub fn trap_from_kernel() -> ! {
+    let cause = scause::read().cause();
+    let stval = stval::read();
+    if let Trap::Exception(Exception::StorePageFault) | Trap::Exception(Exception::LoadPageFault) =
+        cause
+    {
+        if let Some(pid) = kernel_stack_overflow(stval) {
+            panic!("stack overflow in task {}", pid);
+        }
+    }
+    panic!("a trap {:?} from kernel, stval = {:#x}!", cause, stval);
 }